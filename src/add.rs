@@ -0,0 +1,299 @@
+use std::{fs::Metadata, path::Path};
+
+use crate::{
+    error::{Context, Error, Result},
+    hash::Hash,
+    hash_object::hash_object,
+    index::{mode_from_metadata, Index, IndexEntry, StatData},
+    object_store::ObjectStore,
+    repository::Repository,
+};
+
+/// Stages the files `pathspecs` name, or everything under the directories they name, like
+/// `git add`. Tracked files that were deleted are unstaged, and ignored files are skipped
+/// unless they're already tracked or `force` is set.
+pub fn add(repository: &mut Repository, pathspecs: &[impl AsRef<Path>], force: bool) -> Result<()> {
+    let mut index = Index::read(repository.git_directory())?.unwrap_or_default();
+    let index_stat = Index::stat(repository.git_directory())?;
+
+    for pathspec in pathspecs {
+        let path = repository.index_path(pathspec.as_ref())?;
+        let file = repository.work_tree().join(&path);
+
+        // like git, the repository's own files are never content, even with `force`
+        if repository.is_git_path(&file)? {
+            return Err(Error::InvalidPath(file));
+        }
+
+        let tracked = tracked_under(&index, &path);
+
+        match std::fs::symlink_metadata(&file) {
+            Ok(metadata) if metadata.is_dir() => {
+                let ignored = !force && repository.is_ignored(&file)?;
+
+                if ignored && tracked.is_empty() {
+                    return Err(Error::Ignored(path));
+                }
+
+                // an ignored directory only has what's already tracked in it updated, below
+                if !ignored {
+                    for entry in repository.walk(&file) {
+                        let entry = entry?;
+
+                        if entry
+                            .file_type()
+                            .is_some_and(|file_type| file_type.is_dir())
+                        {
+                            continue;
+                        }
+
+                        let path = repository.index_path(entry.path())?;
+
+                        stage(repository, &mut index, index_stat.as_ref(), &path)?;
+                    }
+                }
+            }
+            Ok(_) => {
+                if !force && tracked.is_empty() && repository.is_ignored(&file)? {
+                    return Err(Error::Ignored(path));
+                }
+
+                stage(repository, &mut index, index_stat.as_ref(), &path)?;
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                if tracked.is_empty() {
+                    return Err(Error::PathspecMismatch(path));
+                }
+            }
+            Err(error) => return Err(error).with_context(|| format!("reading {path}")),
+        }
+
+        // tracked files are kept up to date even when they're ignored, and unstaged once
+        // they're deleted
+        for path in tracked {
+            if repository
+                .work_tree()
+                .join(&path)
+                .symlink_metadata()
+                .is_ok()
+            {
+                stage(repository, &mut index, index_stat.as_ref(), &path)?;
+            } else {
+                index.remove(&path);
+            }
+        }
+    }
+
+    index.write(repository.git_directory())
+}
+
+/// Hashes the file at `path` in the work tree into the object store and stages it, unless the
+/// index already has it with the same stat data, and `index_stat`, the index file's own, says
+/// it can be trusted.
+pub(crate) fn stage(
+    repository: &mut Repository,
+    index: &mut Index,
+    index_stat: Option<&StatData>,
+    path: &str,
+) -> Result<()> {
+    let file = repository.work_tree().join(path);
+    let metadata = std::fs::symlink_metadata(&file).with_context(|| format!("reading {path}"))?;
+    let mode = mode_from_metadata(&metadata);
+
+    if index.entry(path).is_some_and(|entry| {
+        entry.mode == mode && entry.stat.matches(&metadata) && !entry.stat.is_racy(index_stat)
+    }) {
+        return Ok(());
+    }
+
+    let hash = hash_file(&file, &metadata, repository.objects_mut())?;
+
+    index.add(IndexEntry::new(
+        path,
+        mode,
+        hash,
+        StatData::from_metadata(&metadata),
+    ));
+
+    Ok(())
+}
+
+/// Writes the file at `path` into `objects` as a blob. Symlinks are stored as the path they
+/// point at, like git does.
+pub(crate) fn hash_file(
+    path: &Path,
+    metadata: &Metadata,
+    objects: &mut impl ObjectStore,
+) -> Result<Hash> {
+    if !metadata.file_type().is_symlink() {
+        return hash_object(true, path, objects);
+    }
+
+    let target = std::fs::read_link(path).with_context(|| format!("reading {}", path.display()))?;
+
+    #[cfg(unix)]
+    let target = {
+        use std::os::unix::ffi::OsStrExt;
+
+        target.as_os_str().as_bytes().to_vec()
+    };
+
+    #[cfg(not(unix))]
+    let target = target.to_string_lossy().replace('\\', "/").into_bytes();
+
+    objects.write("blob", &target)
+}
+
+/// The staged paths that are `path` or inside it
+pub(crate) fn tracked_under(index: &Index, path: &str) -> Vec<String> {
+    let directory = format!("{path}/");
+
+    index
+        .entries()
+        .iter()
+        .filter(|entry| path.is_empty() || entry.path == path || entry.path.starts_with(&directory))
+        .map(|entry| entry.path.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::init;
+    use crate::test_utils::temp_directory;
+
+    fn create_repository(name: &str) -> Result<Repository> {
+        let directory = temp_directory(&format!("add-{name}"));
+
+        std::fs::create_dir_all(directory.join("src"))?;
+
+        let git_directory = init(directory.clone())?;
+
+        Repository::open(git_directory, directory)
+    }
+
+    fn staged(repository: &Repository) -> Result<Vec<String>> {
+        let index = Index::read(repository.git_directory())?.unwrap_or_default();
+
+        Ok(index
+            .entries()
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect())
+    }
+
+    #[test]
+    fn should_stage_files_and_directories() -> Result<()> {
+        let mut repository = create_repository("stage")?;
+        let work_tree = repository.work_tree().to_path_buf();
+
+        std::fs::write(work_tree.join("README"), "hello\n")?;
+        std::fs::write(work_tree.join("src/main.rs"), "fn main() {}\n")?;
+        std::fs::write(work_tree.join("src/lib.rs"), "")?;
+
+        add(&mut repository, &[work_tree.join("README")], false)?;
+
+        assert_eq!(staged(&repository)?, vec!["README"]);
+
+        add(&mut repository, &[work_tree.join("src")], false)?;
+
+        let index = Index::read(repository.git_directory())?.unwrap_or_default();
+        let entry = index.entry("src/main.rs").context("main.rs is staged")?;
+
+        assert_eq!(
+            staged(&repository)?,
+            vec!["README", "src/lib.rs", "src/main.rs"]
+        );
+        assert_eq!(
+            repository.objects().read_existing(&entry.hash)?,
+            ("blob", b"fn main() {}\n".to_vec())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_unstage_deleted_files() -> Result<()> {
+        let mut repository = create_repository("deleted")?;
+        let work_tree = repository.work_tree().to_path_buf();
+
+        std::fs::write(work_tree.join("src/old.rs"), "")?;
+        add(&mut repository, &[&work_tree], false)?;
+        std::fs::remove_file(work_tree.join("src/old.rs"))?;
+        add(&mut repository, &[work_tree.join("src/old.rs")], false)?;
+
+        assert!(staged(&repository)?.is_empty());
+        assert!(matches!(
+            add(&mut repository, &[work_tree.join("src/old.rs")], false),
+            Err(Error::PathspecMismatch(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn should_respect_ignore_files() -> Result<()> {
+        let mut repository = create_repository("ignored")?;
+        let work_tree = repository.work_tree().to_path_buf();
+
+        std::fs::write(work_tree.join(".gitignore"), "*.log\n")?;
+        std::fs::write(work_tree.join("debug.log"), "")?;
+        add(&mut repository, &[&work_tree], false)?;
+
+        assert_eq!(staged(&repository)?, vec![".gitignore"]);
+        assert!(matches!(
+            add(&mut repository, &[work_tree.join("debug.log")], false),
+            Err(Error::Ignored(_))
+        ));
+
+        add(&mut repository, &[work_tree.join("debug.log")], true)?;
+
+        assert_eq!(staged(&repository)?, vec![".gitignore", "debug.log"]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_ignore_everything_inside_ignored_directories() -> Result<()> {
+        let mut repository = create_repository("ignored-directory")?;
+        let work_tree = repository.work_tree().to_path_buf();
+
+        std::fs::create_dir_all(work_tree.join("build/out"))?;
+        std::fs::write(work_tree.join(".vcignore"), "build/\n")?;
+        std::fs::write(work_tree.join("build/out/a.txt"), "")?;
+
+        for pathspec in [work_tree.join("build"), work_tree.join("build/out/a.txt")] {
+            assert!(matches!(
+                add(&mut repository, &[pathspec], false),
+                Err(Error::Ignored(_))
+            ));
+        }
+
+        assert!(staged(&repository)?.is_empty());
+
+        add(&mut repository, &[work_tree.join("build")], true)?;
+
+        assert_eq!(staged(&repository)?, vec!["build/out/a.txt"]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_refuse_paths_inside_repository_directories() -> Result<()> {
+        let mut repository = create_repository("git-directory")?;
+        let work_tree = repository.work_tree().to_path_buf();
+
+        std::fs::create_dir_all(work_tree.join("vendor/.git"))?;
+        std::fs::write(work_tree.join("vendor/.git/config"), "")?;
+
+        for pathspec in [
+            work_tree.join(".vc"),
+            work_tree.join(".vc/HEAD"),
+            work_tree.join("vendor/.git/config"),
+        ] {
+            assert!(matches!(
+                add(&mut repository, &[pathspec], true),
+                Err(Error::InvalidPath(_))
+            ));
+        }
+
+        assert!(staged(&repository)?.is_empty());
+        Ok(())
+    }
+}
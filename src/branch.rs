@@ -0,0 +1,663 @@
+use std::io::Write;
+
+use crate::{
+    config::ConfigFile,
+    error::{Error, Result},
+    hash::Hash,
+    object_store::ObjectStore,
+    ref_transaction::Expected,
+    reflog,
+    refs::{check_ref_name, RefTarget, HEAD},
+    repository::Repository,
+    rev_walk::{RevWalk, RevWalkOptions},
+    revision::{
+        abbreviate, configured_upstream, peel, resolve_ref_name, resolve_revision, shorten_ref, Tip,
+    },
+};
+
+pub(crate) const BRANCH_PREFIX: &str = "refs/heads/";
+const REMOTE_PREFIX: &str = "refs/remotes/";
+
+/// Which branches to list
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Branches {
+    #[default]
+    Local,
+    /// Remote-tracking branches, under `refs/remotes`
+    Remote,
+    All,
+}
+
+/// Whether a new branch follows the branch it starts from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Track {
+    /// Only when starting from a remote-tracking branch, like git's `branch.autoSetupMerge`
+    #[default]
+    Remote,
+    Always,
+    Never,
+}
+
+/// A line of `list`
+struct Listed {
+    /// Whether HEAD is on it
+    current: bool,
+    name: String,
+    /// The name of a local branch, to show how far it is from its upstream
+    branch: Option<String>,
+    hash: Hash,
+    /// What a symbolic ref like `origin/HEAD` points at
+    target: Option<String>,
+}
+
+/// Lists branches sorted by name, marking the one HEAD is on with `*`, like `git branch`. Once
+/// verbose adds the hash and subject of each branch, and how far it is from its upstream;
+/// twice names the upstream too.
+pub fn list(
+    repository: &Repository,
+    branches: Branches,
+    verbose: u8,
+    output: &mut impl Write,
+) -> Result<()> {
+    let refs = repository.refs();
+    let current = current_branch(repository)?;
+    let mut lines = vec![];
+
+    if branches != Branches::Remote && current.is_none() {
+        if let Some(hash) = refs.resolve(HEAD)? {
+            let name = format!(
+                "(HEAD detached at {})",
+                abbreviate(repository.objects(), &hash, 7)?
+            );
+
+            lines.push(Listed {
+                current: true,
+                name,
+                branch: None,
+                hash,
+                target: None,
+            });
+        }
+    }
+
+    if branches != Branches::Remote {
+        for (name, hash) in refs.list(BRANCH_PREFIX)? {
+            let short = name[BRANCH_PREFIX.len()..].to_owned();
+
+            lines.push(Listed {
+                current: current.as_deref() == Some(name.as_str()),
+                name: short.clone(),
+                branch: Some(short),
+                hash,
+                target: None,
+            });
+        }
+    }
+
+    if branches != Branches::Local {
+        for (name, hash) in refs.list(REMOTE_PREFIX)? {
+            let short = match branches {
+                Branches::All => name["refs/".len()..].to_owned(),
+                _ => name[REMOTE_PREFIX.len()..].to_owned(),
+            };
+            let target = match refs.read(&name)? {
+                Some(RefTarget::Symbolic(target)) => Some(shorten_ref(refs, &target)?),
+                _ => None,
+            };
+
+            lines.push(Listed {
+                current: false,
+                name: short,
+                branch: None,
+                hash,
+                target,
+            });
+        }
+    }
+
+    let width = lines
+        .iter()
+        .map(|line| line.name.len())
+        .max()
+        .unwrap_or_default();
+
+    for Listed {
+        current,
+        name,
+        branch,
+        hash,
+        target,
+    } in lines
+    {
+        let marker = if current { '*' } else { ' ' };
+
+        if let Some(target) = target {
+            writeln!(output, "{marker} {name} -> {target}")?;
+            continue;
+        }
+
+        if verbose == 0 {
+            writeln!(output, "{marker} {name}")?;
+            continue;
+        }
+
+        let commit = repository.objects().read_commit(&hash)?;
+        let message = String::from_utf8_lossy(&commit.message);
+        let subject = message.lines().next().unwrap_or_default();
+        let tracking = match &branch {
+            Some(branch) => tracking(repository, branch, &hash, verbose > 1)?,
+            None => String::new(),
+        };
+
+        writeln!(
+            output,
+            "{marker} {name:<width$} {} {tracking}{subject}",
+            abbreviate(repository.objects(), &hash, 7)?
+        )?;
+    }
+
+    Ok(())
+}
+
+/// A branch `prepare` checked can be created, not written yet
+pub(crate) struct NewBranch {
+    name: String,
+    pub(crate) full_name: String,
+    pub(crate) hash: Hash,
+    /// Whether it replaces a branch that already exists
+    pub(crate) exists: bool,
+    /// The `remote` and `merge` config to follow, and the ref they name
+    upstream: Option<(String, String, String)>,
+    message: String,
+}
+
+impl NewBranch {
+    /// Writes the branch and its upstream config, returning the upstream's short name.
+    pub(crate) fn write(self, repository: &Repository) -> Result<Option<String>> {
+        let refs = repository.refs();
+        let mut transaction = refs.transaction();
+
+        transaction
+            .update(&self.full_name, self.hash, Expected::Anything)
+            .message(&self.message);
+        transaction.commit()?;
+
+        match self.upstream {
+            Some((remote, merge, start_ref)) => {
+                write_upstream(repository, &self.name, &remote, &merge)?;
+
+                shorten_ref(refs, &start_ref).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Creates the branch `name` at the commit `start` names, returning the upstream it was set up
+/// to follow, if any. Only `force` resets an existing branch, and never the one HEAD is on.
+pub fn create(
+    repository: &Repository,
+    name: &str,
+    start: &str,
+    force: bool,
+    track: Track,
+) -> Result<Option<String>> {
+    let branch = prepare(repository, name, start, force, track)?;
+
+    if branch.exists && current_branch(repository)?.as_deref() == Some(branch.full_name.as_str()) {
+        return Err(Error::BadBranch(format!(
+            "cannot force update the branch '{name}' HEAD is on"
+        )));
+    }
+
+    branch.write(repository)
+}
+
+/// Checks everything that `create` could fail on, without changing anything, so the caller can
+/// do the same before it writes the branch.
+pub(crate) fn prepare(
+    repository: &Repository,
+    name: &str,
+    start: &str,
+    force: bool,
+    track: Track,
+) -> Result<NewBranch> {
+    let full_name = branch_name(name)?;
+    let exists = repository.refs().read(&full_name)?.is_some();
+
+    if exists && !force {
+        return Err(Error::BranchExists(name.to_owned()));
+    }
+
+    let hash = peel(
+        repository.objects(),
+        resolve_revision(repository, start)?,
+        "commit",
+    )?;
+    let start_ref = resolve_ref_name(repository, start)?;
+    let upstream = match (track, &start_ref) {
+        (Track::Never, _) => None,
+        (Track::Remote, Some(start_ref)) if start_ref.starts_with(REMOTE_PREFIX) => {
+            merge_config(repository, start_ref)
+        }
+        (Track::Remote, _) => None,
+        (Track::Always, start_ref) => Some(
+            start_ref
+                .as_deref()
+                .and_then(|start_ref| merge_config(repository, start_ref))
+                .ok_or_else(|| not_trackable(start))?,
+        ),
+    };
+    let message = if exists {
+        format!("branch: Reset to {start}")
+    } else {
+        format!("branch: Created from {start}")
+    };
+
+    Ok(NewBranch {
+        name: name.to_owned(),
+        full_name,
+        hash,
+        exists,
+        upstream: upstream
+            .zip(start_ref)
+            .map(|((remote, merge), start_ref)| (remote, merge, start_ref)),
+        message,
+    })
+}
+
+/// Renames the branch `old`, or the current one, to `new`, moving its reflog and config along
+/// and HEAD too if it's on it. Only `force` replaces an existing branch.
+pub fn rename(repository: &Repository, old: Option<&str>, new: &str, force: bool) -> Result<()> {
+    let refs = repository.refs();
+    let current = current_branch(repository)?;
+    let old_name = match old {
+        Some(old) => branch_name(old)?,
+        None => current
+            .clone()
+            .ok_or_else(|| Error::BadBranch("HEAD is not on a branch".to_owned()))?,
+    };
+    let old = &old_name[BRANCH_PREFIX.len()..];
+    let new_name = branch_name(new)?;
+    let hash = match refs.read(&old_name)? {
+        Some(RefTarget::Hash(hash)) => hash,
+        _ => return Err(Error::BranchNotFound(old.to_owned())),
+    };
+
+    if old_name == new_name {
+        return Ok(());
+    }
+
+    if refs.read(&new_name)?.is_some() && !force {
+        return Err(Error::BranchExists(new.to_owned()));
+    }
+
+    let git_directory = refs.git_directory();
+    let mut transaction = refs.transaction();
+
+    transaction
+        .update(&new_name, hash.clone(), Expected::Anything)
+        .delete(&old_name, Expected::Hash(hash))
+        .message(&format!("Branch: renamed {old_name} to {new_name}"));
+
+    // the history goes first, so the rename is its newest entry
+    reflog::rename(git_directory, &old_name, &new_name)?;
+
+    if let Err(error) = transaction.commit() {
+        reflog::rename(git_directory, &new_name, &old_name)?;
+
+        return Err(error);
+    }
+
+    if current.as_deref() == Some(old_name.as_str()) {
+        refs.write(HEAD, &RefTarget::Symbolic(new_name))?;
+    }
+
+    let config = local_config(repository);
+
+    config.remove_section(&format!("branch.{new}"))?;
+    config.rename_section(&format!("branch.{old}"), &format!("branch.{new}"))?;
+
+    Ok(())
+}
+
+/// Deletes branches, or remote-tracking branches with `remote`, returning each with the hash
+/// it was at. Unless `force` is set, a branch has to be merged into its upstream, or into HEAD
+/// when it has none.
+pub fn delete(
+    repository: &Repository,
+    names: &[impl AsRef<str>],
+    force: bool,
+    remote: bool,
+) -> Result<Vec<(String, Hash)>> {
+    let refs = repository.refs();
+    let current = current_branch(repository)?;
+    let mut deleted = vec![];
+
+    for name in names {
+        let name = name.as_ref();
+        let full_name = if remote {
+            format!("{REMOTE_PREFIX}{name}")
+        } else {
+            branch_name(name)?
+        };
+        let Some(RefTarget::Hash(hash)) = refs.read(&full_name)? else {
+            return Err(Error::BranchNotFound(name.to_owned()));
+        };
+
+        if current.as_deref() == Some(full_name.as_str()) {
+            return Err(Error::BadBranch(format!(
+                "cannot delete the branch '{name}' HEAD is on"
+            )));
+        }
+
+        if !force && !remote && !is_merged(repository, name, &hash)? {
+            return Err(Error::NotMerged(name.to_owned()));
+        }
+
+        let mut transaction = refs.transaction();
+
+        transaction.delete(&full_name, Expected::Hash(hash.clone()));
+        transaction.commit()?;
+
+        if !remote {
+            local_config(repository).remove_section(&format!("branch.{name}"))?;
+        }
+
+        deleted.push((name.to_owned(), hash));
+    }
+
+    Ok(deleted)
+}
+
+/// Makes `branch`, or the current one, follow the branch `upstream` names, returning the
+/// branch and the short name of its upstream.
+pub fn set_upstream(
+    repository: &Repository,
+    branch: Option<&str>,
+    upstream: &str,
+) -> Result<(String, String)> {
+    let branch = existing_branch(repository, branch)?;
+    let upstream_ref = resolve_ref_name(repository, upstream)?;
+    let Some((remote, merge)) = upstream_ref
+        .as_deref()
+        .and_then(|upstream_ref| merge_config(repository, upstream_ref))
+    else {
+        return Err(not_trackable(upstream));
+    };
+
+    write_upstream(repository, &branch, &remote, &merge)?;
+
+    let upstream = shorten_ref(
+        repository.refs(),
+        upstream_ref.as_deref().unwrap_or(upstream),
+    )?;
+
+    Ok((branch, upstream))
+}
+
+/// Stops `branch`, or the current one, from following anything.
+pub fn unset_upstream(repository: &Repository, branch: Option<&str>) -> Result<()> {
+    let branch = existing_branch(repository, branch)?;
+    let config = local_config(repository);
+    let remote = config.unset(&format!("branch.{branch}.remote"), true)?;
+    let merge = config.unset(&format!("branch.{branch}.merge"), true)?;
+
+    if !remote && !merge {
+        return Err(Error::BadBranch(format!(
+            "branch '{branch}' has no upstream information"
+        )));
+    }
+
+    Ok(())
+}
+
+/// The full name of the branch HEAD is on, or `None` when it's detached.
+fn current_branch(repository: &Repository) -> Result<Option<String>> {
+    Ok(match repository.refs().read(HEAD)? {
+        Some(RefTarget::Symbolic(target)) => Some(target),
+        _ => None,
+    })
+}
+
+pub(crate) fn branch_name(name: &str) -> Result<String> {
+    let full_name = format!("{BRANCH_PREFIX}{name}");
+
+    // like git, `-d` and friends can't be branch names, and neither can HEAD
+    if name.starts_with('-') || name == HEAD {
+        return Err(Error::InvalidRef(full_name));
+    }
+
+    check_ref_name(&full_name)?;
+
+    Ok(full_name)
+}
+
+/// The short name of `branch`, or of the current branch, checking that it exists.
+fn existing_branch(repository: &Repository, branch: Option<&str>) -> Result<String> {
+    let full_name = match branch {
+        Some(branch) => branch_name(branch)?,
+        None => current_branch(repository)?
+            .ok_or_else(|| Error::BadBranch("HEAD is not on a branch".to_owned()))?,
+    };
+    let branch = full_name[BRANCH_PREFIX.len()..].to_owned();
+
+    match repository.refs().read(&full_name)? {
+        Some(_) => Ok(branch),
+        None => Err(Error::BranchNotFound(branch)),
+    }
+}
+
+/// The `branch.<name>.remote` and `branch.<name>.merge` values that follow `upstream`, a local
+/// branch or a remote-tracking branch some remote's fetch refspec maps to.
+fn merge_config(repository: &Repository, upstream: &str) -> Option<(String, String)> {
+    if upstream.starts_with(BRANCH_PREFIX) {
+        return Some((".".to_owned(), upstream.to_owned()));
+    }
+
+    repository.config().entries().iter().find_map(|entry| {
+        let remote = entry.key.strip_prefix("remote.")?.strip_suffix(".fetch")?;
+        let refspec = entry.value.as_deref()?.trim_start_matches('+');
+        let (source, destination) = refspec.split_once(':')?;
+        let branch = upstream.strip_prefix(destination.strip_suffix('*')?)?;
+
+        Some((
+            remote.to_owned(),
+            format!("{}{branch}", source.strip_suffix('*')?),
+        ))
+    })
+}
+
+fn write_upstream(repository: &Repository, branch: &str, remote: &str, merge: &str) -> Result<()> {
+    let config = local_config(repository);
+
+    config.set(&format!("branch.{branch}.remote"), remote)?;
+    config.set(&format!("branch.{branch}.merge"), merge)
+}
+
+fn local_config(repository: &Repository) -> ConfigFile {
+    ConfigFile::new(repository.git_directory().join("config"))
+}
+
+fn not_trackable(start: &str) -> Error {
+    Error::BadBranch(format!(
+        "cannot set up tracking information, starting point '{start}' is not a branch"
+    ))
+}
+
+/// Whether its upstream, or HEAD when it has none, has every commit `branch` has.
+fn is_merged(repository: &Repository, branch: &str, hash: &Hash) -> Result<bool> {
+    let refs = repository.refs();
+    let upstream = match configured_upstream(repository.config(), branch) {
+        Some(upstream) => refs.resolve(&upstream)?,
+        None => None,
+    };
+    let target = match upstream {
+        Some(upstream) => Some(upstream),
+        None => refs.resolve(HEAD)?,
+    };
+    let Some(target) = target else {
+        return Ok(false);
+    };
+
+    Ok(count_missing(repository.objects(), hash, &target)? == 0)
+}
+
+/// How many commits `from` has that `to` doesn't
+fn count_missing(objects: &impl ObjectStore, from: &Hash, to: &Hash) -> Result<usize> {
+    let tips = [
+        Tip {
+            hash: from.clone(),
+            excluded: false,
+        },
+        Tip {
+            hash: to.clone(),
+            excluded: true,
+        },
+    ];
+    let mut count = 0;
+
+    for commit in RevWalk::new(objects, &tips, RevWalkOptions::default())? {
+        commit?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// `[origin/master: ahead 1, behind 2] ` for a branch that follows something, naming it only
+/// when asked to. An upstream that doesn't exist anymore is gone.
+fn tracking(repository: &Repository, branch: &str, hash: &Hash, named: bool) -> Result<String> {
+    let refs = repository.refs();
+    let Some(upstream) = configured_upstream(repository.config(), branch) else {
+        return Ok(String::new());
+    };
+    let name = shorten_ref(refs, &upstream)?;
+    let Some(upstream) = refs.resolve(&upstream)? else {
+        return Ok(if named {
+            format!("[{name}: gone] ")
+        } else {
+            String::new()
+        });
+    };
+    let ahead = count_missing(repository.objects(), hash, &upstream)?;
+    let behind = count_missing(repository.objects(), &upstream, hash)?;
+    let distance = match (ahead, behind) {
+        (0, 0) => String::new(),
+        (ahead, 0) => format!("ahead {ahead}"),
+        (0, behind) => format!("behind {behind}"),
+        (ahead, behind) => format!("ahead {ahead}, behind {behind}"),
+    };
+
+    Ok(match (named, distance.is_empty()) {
+        (true, true) => format!("[{name}] "),
+        (true, false) => format!("[{name}: {distance}] "),
+        (false, true) => String::new(),
+        (false, false) => format!("[{distance}] "),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commit_tree::commit_tree, init::init, object::Signature, test_utils::temp_directory,
+        tree::Tree,
+    };
+
+    fn create_repository(name: &str) -> Result<(Repository, Vec<Hash>)> {
+        let directory = temp_directory(&format!("branch-{name}"));
+
+        std::fs::create_dir_all(&directory)?;
+
+        let git_directory = init(directory.clone())?;
+        let mut repository = Repository::open(git_directory, directory)?;
+        let objects = repository.objects_mut();
+        let tree = objects.write("tree", &Tree::new(vec![]).serialize())?;
+        let signature = Signature::new("A U Thor", "author@example.com", 1_700_000_000, 0);
+        let first = commit_tree(&tree, &[], "first", &signature, &signature, objects)?;
+        let second = commit_tree(
+            &tree,
+            std::slice::from_ref(&first),
+            "second",
+            &signature,
+            &signature,
+            objects,
+        )?;
+
+        repository
+            .refs()
+            .update(HEAD, &first, "commit (initial): first")?;
+
+        Ok((repository, vec![first, second]))
+    }
+
+    #[test]
+    fn should_create_list_and_delete_branches() -> Result<()> {
+        let (repository, hashes) = create_repository("create")?;
+        let second = hashes[1].to_string();
+
+        create(&repository, "topic", &second, false, Track::Remote)?;
+
+        assert!(matches!(
+            create(&repository, "topic", "HEAD", false, Track::Remote),
+            Err(Error::BranchExists(_))
+        ));
+
+        let mut output = vec![];
+
+        list(&repository, Branches::Local, 1, &mut output)?;
+
+        assert_eq!(
+            String::from_utf8(output)?,
+            format!(
+                "* master {} first\n  topic  {} second\n",
+                &hashes[0].to_string()[..7],
+                &second[..7]
+            )
+        );
+        assert!(matches!(
+            delete(&repository, &["topic"], false, false),
+            Err(Error::NotMerged(_))
+        ));
+        assert!(matches!(
+            delete(&repository, &["master"], true, false),
+            Err(Error::BadBranch(_))
+        ));
+        assert_eq!(
+            delete(&repository, &["topic"], true, false)?,
+            vec![("topic".to_owned(), hashes[1].clone())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_rename_branches_with_their_reflog_and_upstream() -> Result<()> {
+        let (repository, _) = create_repository("rename")?;
+
+        assert_eq!(
+            create(&repository, "topic", "master", false, Track::Always)?,
+            Some("master".to_owned())
+        );
+
+        rename(&repository, None, "main", false)?;
+        rename(&repository, Some("topic"), "feature", false)?;
+
+        let refs = repository.refs();
+        let reflog = refs.reflog("refs/heads/main")?;
+        let config = std::fs::read_to_string(repository.git_directory().join("config"))?;
+
+        assert_eq!(refs.resolve_name(HEAD)?, "refs/heads/main");
+        assert_eq!(reflog.len(), 2);
+        assert_eq!(
+            reflog[1].message,
+            "Branch: renamed refs/heads/master to refs/heads/main"
+        );
+        assert!(refs.reflog("refs/heads/master")?.is_empty());
+        assert!(
+            config.contains("[branch \"feature\"]\n\tremote = .\n\tmerge = refs/heads/master\n")
+        );
+        assert!(!config.contains("topic"));
+        Ok(())
+    }
+}
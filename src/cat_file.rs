@@ -1,37 +1,46 @@
-use std::{
-    fs,
-    io::{self, Read, Write},
-};
+use crate::error::{Context, Result};
+use std::io::Write;
 
-use flate2::read::ZlibDecoder;
+use crate::{hash::Hash, object_store::ObjectStore, repository::Repository, tree::Tree};
 
-pub fn cat_file(args: &[String]) {
-    if args[0].as_str() == "-p" {
-        pretty_print(args[1].as_str());
+/// What `cat-file` shows about an object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatFileMode {
+    /// The content, with trees listed one entry per line
+    Pretty,
+    Type,
+    Size,
+}
+
+pub fn cat_file(
+    repository: &Repository,
+    mode: CatFileMode,
+    hash: &Hash,
+    output: &mut impl Write,
+) -> Result<()> {
+    let (object_type, content) = repository.objects().read_existing(hash)?;
+
+    match mode {
+        CatFileMode::Pretty => pretty_print(object_type, &content, output)?,
+        CatFileMode::Type => writeln!(output, "{object_type}")?,
+        CatFileMode::Size => writeln!(output, "{}", content.len())?,
     }
+
+    output.flush().context("flushing cat-file output")?;
+
+    Ok(())
 }
 
-pub fn pretty_print(hash: &str) {
-    // the first two characters of the hash are the folder name, and the rest are the file name
-    let folder_name = &hash[0..2];
-    let file_name = &hash[2..];
-    let path = format!(".vc/objects/{folder_name}/{file_name}");
-    let mut object = fs::File::open(&path).expect("error opening file for pretty print");
-    let mut content: Vec<u8> = vec![];
-    let mut extracted_content = String::new();
-
-    object
-        .read_to_end(&mut content)
-        .expect("error reading pretty print object to end");
-
-    let mut decoder = ZlibDecoder::new(content.as_slice());
-
-    decoder
-        .read_to_string(&mut extracted_content)
-        .expect("error reading pretty print to string");
-    let split = extracted_content.split("\x00");
-    let extracted_content = split.last().expect("error extracting pretty print");
-
-    print!("{extracted_content}");
-    io::stdout().flush().expect("error flushing pretty print");
+pub fn pretty_print(object_type: &str, content: &[u8], output: &mut impl Write) -> Result<()> {
+    if object_type == "tree" {
+        let tree = Tree::parse(content).context("parsing tree for pretty print")?;
+
+        for tree_object in tree.tree_objects {
+            writeln!(output, "{tree_object}").context("writing pretty print")?;
+        }
+    } else {
+        output.write_all(content).context("writing pretty print")?;
+    }
+
+    Ok(())
 }
@@ -1,57 +1,561 @@
-use anyhow::Context;
-use anyhow::Result;
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
 
-use crate::tree::Tree;
-use crate::{clone::GitObjects, hash::Hash};
+use crate::{
+    branch::{self, branch_name, Track, BRANCH_PREFIX},
+    error::{Context, Error, Result},
+    hash::Hash,
+    identity,
+    index::{Index, IndexEntry, StatData, EXECUTABLE_FILE_MODE, GITLINK_MODE, SYMLINK_MODE},
+    object_store::ObjectStore,
+    reflog::{self, ReflogEntry},
+    refs::{RefTarget, HEAD},
+    repository::Repository,
+    revision::{peel, resolve_revision},
+    rm::is_modified,
+    utils::remove_empty_directories,
+};
 
-pub fn checkout(path: PathBuf, git_objects: &GitObjects, commit_hash: Hash) -> Result<()> {
-    let commit = git_objects
-        .get(&commit_hash)
-        .expect("cannot find commit in git objects");
-    let tree_hash: Hash = commit[5..45]
-        .to_vec()
-        .try_into()
-        .context("converting commit bytes to hash")?;
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SwitchOptions<'a> {
+    /// Create this branch at the target and switch to it
+    pub create: Option<&'a str>,
+    /// Reset the branch being created if it already exists
+    pub force_create: bool,
+    /// Leave HEAD on the target commit rather than on a branch
+    pub detach: bool,
+    /// Throw away local changes rather than refusing to switch
+    pub force: bool,
+}
+
+/// Where HEAD ended up after `switch`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Switched {
+    /// HEAD was on the branch already
+    AlreadyOn(String),
+    Branch(String),
+    /// A branch that was created, or reset, along with the upstream it was set up to follow
+    NewBranch {
+        name: String,
+        reset: bool,
+        upstream: Option<String>,
+    },
+    Detached {
+        hash: Hash,
+        subject: String,
+    },
+}
 
-    process_tree(path, git_objects, tree_hash).context("processing tree")?;
+/// Writes the tree of `commit_hash` into the work tree, which is expected to be empty, and
+/// stages it in the index.
+pub fn checkout(repository: &Repository, commit_hash: Hash) -> Result<()> {
+    let commit = repository
+        .objects()
+        .read_commit(&commit_hash)
+        .context("reading commit")?;
 
-    Ok(())
+    update_work_tree(repository, None, &commit.tree, false).context("processing tree")
 }
 
-fn process_tree(path: PathBuf, git_objects: &GitObjects, tree_hash: Hash) -> Result<()> {
-    let tree_bytes = git_objects
-        .get(&tree_hash)
-        .expect("missing tree referenced in commit");
-    let tree = Tree::from(tree_bytes.as_slice());
+/// Moves HEAD to the branch `target`, or with `detach` to the commit it names, like
+/// `git switch`. Only the paths that differ between the two commits are written or removed,
+/// and other local changes are carried over. Unless `force` is set, nothing is touched if a
+/// change that was never committed would be lost, or an untracked file overwritten.
+pub fn switch(repository: &Repository, target: &str, options: SwitchOptions) -> Result<Switched> {
+    let refs = repository.refs();
+    let objects = repository.objects();
+    // everything that can fail is checked before the work tree is touched
+    let created = match options.create {
+        Some(name) => Some(branch::prepare(
+            repository,
+            name,
+            target,
+            options.force_create,
+            Track::Remote,
+        )?),
+        None => None,
+    };
+    let (branch, hash) = match &created {
+        Some(created) => (Some(created.full_name.clone()), created.hash.clone()),
+        None if options.detach => {
+            let hash = peel(objects, resolve_revision(repository, target)?, "commit")?;
 
-    for tree_object in &tree.tree_objects {
-        match tree_object.object_type {
-            crate::tree::TreeObjectType::Blob => {
-                let mut path = path.clone();
-                let git_object = git_objects
-                    .get(&tree_object.checksum)
-                    .context("getting git object to write to disk")?;
+            (None, hash)
+        }
+        None => {
+            let full_name = branch_name(target)?;
+            let hash = refs
+                .resolve(&full_name)?
+                .ok_or_else(|| Error::BranchNotFound(target.to_owned()))?;
+
+            (Some(full_name), hash)
+        }
+    };
+    let old = refs.resolve(HEAD)?;
+    let old_tree = match &old {
+        Some(old) => Some(objects.read_commit(old)?.tree),
+        None => None,
+    };
+    let commit = objects.read_commit(&hash)?;
+    let committer = identity::reflog_committer(repository.config())?;
+
+    update_work_tree(repository, old_tree.as_ref(), &commit.tree, options.force)?;
+
+    // like `git checkout -B`, this resets the branch HEAD is on too
+    let reset = created.as_ref().is_some_and(|created| created.exists);
+    let upstream = match created {
+        Some(created) => created.write(repository)?,
+        None => None,
+    };
+    let from = match refs.read(HEAD)? {
+        Some(RefTarget::Symbolic(name)) => Some(name),
+        _ => None,
+    };
+    let short_name = |name: &str| name.strip_prefix(BRANCH_PREFIX).unwrap_or(name).to_owned();
+    let message = format!(
+        "checkout: moving from {} to {}",
+        from.as_deref().map_or_else(
+            || old.as_ref().map(Hash::to_string).unwrap_or_default(),
+            short_name
+        ),
+        branch
+            .as_deref()
+            .map_or_else(|| hash.to_string(), short_name)
+    );
+    let head = match &branch {
+        Some(branch) => RefTarget::Symbolic(branch.clone()),
+        None => RefTarget::Hash(hash.clone()),
+    };
+
+    refs.write(HEAD, &head)?;
+    reflog::append(
+        repository.git_directory(),
+        HEAD,
+        &ReflogEntry::new(old, hash.clone(), committer, &message),
+    )?;
+
+    Ok(match (branch, options.create) {
+        (Some(_), Some(name)) => Switched::NewBranch {
+            name: name.to_owned(),
+            reset,
+            upstream,
+        },
+        (Some(branch), None) if from.as_deref() == Some(branch.as_str()) => {
+            Switched::AlreadyOn(short_name(&branch))
+        }
+        (Some(branch), None) => Switched::Branch(short_name(&branch)),
+        (None, _) => Switched::Detached {
+            hash,
+            subject: String::from_utf8_lossy(&commit.message)
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_owned(),
+        },
+    })
+}
 
-                path.push(&tree_object.filename);
-                write_blob_to_file(path, git_object).context("writing blob to file")?;
+/// Moves the work tree and index from `old_tree`, what HEAD has, to `new_tree`. Paths that are
+/// the same in both keep whatever is in the index and work tree. The others have to be clean
+/// unless `force` is set, which makes the index exactly `new_tree`.
+fn update_work_tree(
+    repository: &Repository,
+    old_tree: Option<&Hash>,
+    new_tree: &Hash,
+    force: bool,
+) -> Result<()> {
+    let objects = repository.objects();
+    let git_directory = repository.git_directory();
+    let old = match old_tree {
+        Some(tree) => Index::from_tree(objects, tree)?,
+        None => Index::default(),
+    };
+    let new = Index::from_tree(objects, new_tree)?;
+    let (mut index, index_stat) = match Index::read(git_directory)? {
+        Some(index) => (index, Index::stat(git_directory)?),
+        None => (old.clone(), None),
+    };
+    let paths = old
+        .entries()
+        .iter()
+        .chain(index.entries())
+        .chain(new.entries())
+        .map(|entry| entry.path.clone())
+        .collect::<BTreeSet<_>>();
+    let staged = index
+        .entries()
+        .iter()
+        .filter(|entry| entry.stage == 0)
+        .map(|entry| (entry.path.as_str(), (entry.mode, &entry.hash)))
+        .collect::<BTreeMap<_, _>>();
+    let version = |index: &Index, path: &str| {
+        index
+            .entry(path)
+            .map(|entry| (entry.mode, entry.hash.clone()))
+    };
+    let mut updates = vec![];
+    let mut modified = vec![];
+    let mut untracked = vec![];
+
+    for path in &paths {
+        let old_version = version(&old, path);
+        let new_version = version(&new, path);
+        let staged_version = staged
+            .get(path.as_str())
+            .map(|(mode, hash)| (*mode, (*hash).clone()));
+        let unmerged = index
+            .entries()
+            .iter()
+            .any(|entry| entry.path == *path && entry.stage != 0);
+
+        if force {
+            if unmerged
+                || staged_version != new_version
+                || is_modified(repository, &index, index_stat.as_ref(), path)?
+            {
+                updates.push((path.as_str(), new.entry(path)));
             }
-            crate::tree::TreeObjectType::Tree => {
-                let mut path = path.clone();
 
-                path.push(&tree_object.filename);
-                std::fs::create_dir(&path).context("creating directory")?;
-                process_tree(path, git_objects, tree_object.checksum.clone())
-                    .context("recursively processing tree")?;
+            continue;
+        }
+
+        if unmerged {
+            return Err(Error::Unmerged(path.clone()));
+        }
+
+        if old_version == new_version || staged_version == new_version {
+            continue;
+        }
+
+        match (&old_version, &staged_version) {
+            (None, None) if is_untracked(repository, &staged, path)? => {
+                untracked.push(path.clone());
+            }
+            (None, None) => {}
+            _ if staged_version != old_version => modified.push(path.clone()),
+            _ if is_modified(repository, &index, index_stat.as_ref(), path)? => {
+                modified.push(path.clone())
             }
+            _ => {}
         }
+
+        updates.push((path.as_str(), new.entry(path)));
     }
 
-    Ok(())
+    if !modified.is_empty() {
+        return Err(Error::WouldOverwrite(modified));
+    }
+
+    if !untracked.is_empty() {
+        return Err(Error::WouldOverwriteUntracked(untracked));
+    }
+
+    // removing first makes room for files that replace a directory, or the other way around
+    for (path, _) in updates.iter().filter(|(_, entry)| entry.is_none()) {
+        let file = repository.work_tree().join(path);
+
+        remove(&file).with_context(|| format!("removing {path}"))?;
+        remove_empty_directories(&file, repository.work_tree());
+        index.remove(path);
+    }
+
+    for (path, entry) in updates {
+        let Some(entry) = entry else {
+            continue;
+        };
+        let file = repository.work_tree().join(path);
+
+        write_entry(objects, &file, entry).with_context(|| format!("writing {path}"))?;
+
+        let stat = match entry.mode {
+            GITLINK_MODE => StatData::default(),
+            _ => StatData::from_metadata(&std::fs::symlink_metadata(&file)?),
+        };
+
+        index.add(IndexEntry::new(path, entry.mode, entry.hash.clone(), stat));
+    }
+
+    index.write(git_directory)
 }
 
-fn write_blob_to_file(path: PathBuf, bytes: &[u8]) -> Result<()> {
-    std::fs::write(path, bytes)?;
+/// Whether writing `path` would replace something in the work tree that isn't tracked, either
+/// at `path` or where one of its directories goes. Ignored files are fine to lose, like in git.
+fn is_untracked(
+    repository: &Repository,
+    staged: &BTreeMap<&str, (u32, &Hash)>,
+    path: &str,
+) -> Result<bool> {
+    let work_tree = repository.work_tree();
+    let directories = path
+        .match_indices('/')
+        .map(|(position, _)| &path[..position]);
+
+    for directory in directories {
+        let file = work_tree.join(directory);
+
+        match std::fs::symlink_metadata(&file) {
+            Ok(metadata) if metadata.is_dir() => continue,
+            Ok(_) => return Ok(!staged.contains_key(directory) && !repository.is_ignored(&file)?),
+            Err(_) => return Ok(false),
+        }
+    }
+
+    let file = work_tree.join(path);
+
+    match std::fs::symlink_metadata(&file) {
+        Ok(metadata) if metadata.is_dir() => {
+            for entry in repository.walk(&file) {
+                let entry = entry?;
+
+                if entry
+                    .file_type()
+                    .is_some_and(|file_type| file_type.is_dir())
+                {
+                    continue;
+                }
+
+                if !staged.contains_key(repository.index_path(entry.path())?.as_str()) {
+                    return Ok(true);
+                }
+            }
+
+            Ok(false)
+        }
+        Ok(_) => Ok(!repository.is_ignored(&file)?),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Removes whatever is at `path`, which is only ever a directory when nothing in it is worth
+/// keeping.
+fn remove(path: &Path) -> Result<()> {
+    let result = match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(_) => return Ok(()),
+    };
+
+    Ok(result?)
+}
+
+fn write_entry(objects: &impl ObjectStore, path: &Path, entry: &IndexEntry) -> Result<()> {
+    // a symlink could point anywhere, so it's replaced rather than written through
+    remove(path)?;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context("creating directory")?;
+    }
+
+    // submodule contents live in another repository, so leave an empty directory
+    if entry.mode == GITLINK_MODE {
+        return std::fs::create_dir(path).context("creating submodule directory");
+    }
+
+    let (_object_type, data) = objects.read_existing(&entry.hash)?;
+
+    #[cfg(unix)]
+    if entry.mode == SYMLINK_MODE {
+        use std::os::unix::ffi::OsStrExt;
+
+        return std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(&data), path)
+            .context("creating symlink");
+    }
+
+    std::fs::write(path, &data)?;
+
+    #[cfg(unix)]
+    if entry.mode == EXECUTABLE_FILE_MODE {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+            .context("making file executable")?;
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        add::add,
+        commit::commit,
+        config::ConfigFile,
+        init::init,
+        rm::{rm, RmOptions},
+        status::{status, UntrackedFiles},
+        test_utils::temp_directory,
+    };
+
+    /// A repository on master with a `topic` branch that changes README, deletes src/lib.rs
+    /// and adds NEW.
+    fn create_repository(name: &str) -> Result<Repository> {
+        let directory = temp_directory(&format!("checkout-{name}"));
+
+        std::fs::create_dir_all(directory.join("src"))?;
+        std::fs::write(directory.join("README"), "hello\n")?;
+        std::fs::write(directory.join("src/main.rs"), "fn main() {}\n")?;
+        std::fs::write(directory.join("src/lib.rs"), "\n")?;
+
+        let git_directory = init(directory.clone())?;
+        let config = ConfigFile::new(git_directory.join("config"));
+
+        config.set("user.name", "A U Thor")?;
+        config.set("user.email", "author@example.com")?;
+
+        let mut repository = Repository::open(git_directory, directory.clone())?;
+
+        add(&mut repository, &[&directory], false)?;
+        commit(&mut repository, "first")?;
+
+        let options = SwitchOptions {
+            create: Some("topic"),
+            ..SwitchOptions::default()
+        };
+
+        switch(&repository, HEAD, options)?;
+        std::fs::write(directory.join("README"), "goodbye\n")?;
+        std::fs::write(directory.join("NEW"), "new\n")?;
+        rm(
+            &repository,
+            &[directory.join("src/lib.rs")],
+            RmOptions::default(),
+        )?;
+        add(
+            &mut repository,
+            &[directory.join("README"), directory.join("NEW")],
+            false,
+        )?;
+        commit(&mut repository, "second")?;
+
+        Ok(repository)
+    }
+
+    #[test]
+    fn should_update_changed_paths_and_carry_local_changes() -> Result<()> {
+        let repository = create_repository("switch")?;
+        let work_tree = repository.work_tree().to_path_buf();
+
+        std::fs::write(work_tree.join("src/main.rs"), "fn main() { }\n")?;
+
+        assert_eq!(
+            switch(&repository, "master", SwitchOptions::default())?,
+            Switched::Branch("master".to_owned())
+        );
+        assert_eq!(
+            std::fs::read_to_string(work_tree.join("README"))?,
+            "hello\n"
+        );
+        assert!(work_tree.join("src/lib.rs").exists());
+        assert!(!work_tree.join("NEW").exists());
+        assert_eq!(
+            std::fs::read_to_string(work_tree.join("src/main.rs"))?,
+            "fn main() { }\n"
+        );
+
+        let status = status(&repository, UntrackedFiles::Normal)?;
+
+        assert_eq!(status.branch.as_deref(), Some("refs/heads/master"));
+        assert_eq!(
+            status
+                .entries
+                .iter()
+                .map(|entry| entry.path.as_str())
+                .collect::<Vec<_>>(),
+            ["src/main.rs"]
+        );
+        assert_eq!(
+            repository
+                .refs()
+                .reflog(HEAD)?
+                .last()
+                .map(|entry| entry.message.as_str()),
+            Some("checkout: moving from topic to master")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_refuse_to_overwrite_local_changes() -> Result<()> {
+        let repository = create_repository("refuse")?;
+        let work_tree = repository.work_tree().to_path_buf();
+
+        switch(&repository, "master", SwitchOptions::default())?;
+        std::fs::write(work_tree.join("README"), "changed\n")?;
+        std::fs::write(work_tree.join("NEW"), "mine\n")?;
+
+        assert!(matches!(
+            switch(&repository, "topic", SwitchOptions::default()),
+            Err(Error::WouldOverwrite(paths)) if paths == ["README"]
+        ));
+
+        std::fs::write(work_tree.join("README"), "hello\n")?;
+
+        assert!(matches!(
+            switch(&repository, "topic", SwitchOptions::default()),
+            Err(Error::WouldOverwriteUntracked(paths)) if paths == ["NEW"]
+        ));
+        assert_eq!(std::fs::read_to_string(work_tree.join("NEW"))?, "mine\n");
+
+        let options = SwitchOptions {
+            detach: true,
+            force: true,
+            ..SwitchOptions::default()
+        };
+
+        assert!(matches!(
+            switch(&repository, "topic", options)?,
+            Switched::Detached { subject, .. } if subject == "second"
+        ));
+        assert_eq!(std::fs::read_to_string(work_tree.join("NEW"))?, "new\n");
+        assert!(status(&repository, UntrackedFiles::Normal)?.is_clean());
+        Ok(())
+    }
+
+    #[test]
+    fn should_reset_the_current_branch_and_fail_before_touching_anything() -> Result<()> {
+        let repository = create_repository("reset")?;
+        let work_tree = repository.work_tree().to_path_buf();
+        let options = SwitchOptions {
+            create: Some("other"),
+            ..SwitchOptions::default()
+        };
+
+        assert!(matches!(
+            switch(&repository, "missing", options),
+            Err(Error::UnknownRevision(_))
+        ));
+        assert_eq!(
+            std::fs::read_to_string(work_tree.join("README"))?,
+            "goodbye\n"
+        );
+
+        let options = SwitchOptions {
+            create: Some("topic"),
+            force_create: true,
+            ..SwitchOptions::default()
+        };
+
+        assert_eq!(
+            switch(&repository, "master", options)?,
+            Switched::NewBranch {
+                name: "topic".to_owned(),
+                reset: true,
+                upstream: None,
+            }
+        );
+        assert_eq!(
+            std::fs::read_to_string(work_tree.join("README"))?,
+            "hello\n"
+        );
+        assert_eq!(repository.refs().resolve_name(HEAD)?, "refs/heads/topic");
+        assert_eq!(
+            repository.refs().resolve("refs/heads/topic")?,
+            repository.refs().resolve("refs/heads/master")?
+        );
+        assert!(status(&repository, UntrackedFiles::Normal)?.is_clean());
+        Ok(())
+    }
+}
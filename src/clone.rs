@@ -1,220 +1,198 @@
-use anyhow::Context;
-use anyhow::{bail, Result};
-use bytes::Bytes;
-use flate2::read::ZlibDecoder;
-use reqwest::get;
-use std::collections::HashMap;
-use std::io::Cursor;
-use std::io::Read;
-use std::io::Seek;
-use std::path::Path;
-use std::path::PathBuf;
+use crate::config::ConfigFile;
+use crate::error::{Context, Error, Result};
 use crate::hash::Hash;
-use crate::process_packfile::{apply_delta_instruction, ObjectType};
-use crate::process_packfile::{read_size_encoding, read_type_and_size};
+use crate::index_pack::index_pack;
+use crate::pack::PACK_SIGNATURE;
+use crate::refs::{RefTarget, Refs, HEAD};
+use crate::repository::Repository;
 use crate::utils::create_directory;
-use crate::utils::{get_hash, save_to_disk};
 use crate::{checkout, init};
-pub type GitObjects = HashMap<Hash, Vec<u8>>;
+use bytes::Bytes;
+use reqwest::{get, Response};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::path::PathBuf;
+
+const REMOTE_PREFIX: &str = "refs/remotes/origin/";
 
 pub async fn clone(uri: &str, target_dir: &str) -> Result<()> {
     // create directory
     let target_directory = Path::new(".").join(target_dir);
 
     create_directory(&target_directory).context("create directory")?;
-    init::init(target_directory.clone());
 
-    let refs = discover_references(uri)
+    let git_directory = init::init(target_directory.clone()).context("initializing repository")?;
+    let pack_directory = git_directory.join("objects").join("pack");
+
+    create_directory(&pack_directory).context("creating pack directory")?;
+
+    let config = ConfigFile::new(git_directory.join("config"));
+
+    config.set("remote.origin.url", uri)?;
+    config.set("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")?;
+
+    let remote = discover_references(uri)
         .await
         .context("discovering references")?;
-    let commit_hash = &refs.last().context("getting commit hash")?.commit_hash;
-    let commit = get_commit(commit_hash, uri)
-        .await
-        .context("getting commit")?;
-    let pack_file = Packfile::new(commit.slice(..21)).context("creating Packfile metadata")?;
-    let mut cursed_packfile = Cursor::new(&commit[20..]);
-    let mut git_objects: HashMap<Hash, Vec<u8>> = HashMap::new();
-
-    for _ in 0..pack_file.object_count {
-        let object_type =
-            read_type_and_size(&mut cursed_packfile).context("reading type and size")?;
-
-        let _hash = match &object_type {
-            ObjectType::Commit(size) => handle_normal_object_type(
-                *size,
-                "commit",
-                &mut cursed_packfile,
-                target_directory.clone(),
-                &mut git_objects,
-            )
-            .context("handling normal object type as commit")?,
-            ObjectType::Tree(size) => handle_normal_object_type(
-                *size,
-                "tree",
-                &mut cursed_packfile,
-                target_directory.clone(),
-                &mut git_objects,
-            )
-            .context("handling normal object type as tree")?,
-            ObjectType::Blob(size) => handle_normal_object_type(
-                *size,
-                "blob",
-                &mut cursed_packfile,
-                target_directory.clone(),
-                &mut git_objects,
-            )
-            .context("handling normal object type as blob")?,
-            ObjectType::Tag(size) => handle_normal_object_type(
-                *size,
-                "tag",
-                &mut cursed_packfile,
-                target_directory.clone(),
-                &mut git_objects,
-            )
-            .context("handling normal object type as tag")?,
-            ObjectType::OfsDelta(_) => {
-                handle_ofs_delta();
-                None
-            }
-            ObjectType::RefDelta(_) => {
-                handle_ref_delta(&mut cursed_packfile, &mut git_objects)
-                    .await
-                    .context("handling ref/hash delta")?;
-                None
-            }
-            ObjectType::Unknown => unreachable!(),
-        };
+
+    // an empty remote leaves us with an empty repository, like git
+    if remote.branches.is_empty() {
+        return Ok(());
     }
 
-    save_git_objects(&git_objects, target_directory.clone()).context("saving git objects")?;
-    checkout::checkout(target_directory.clone(), &git_objects, commit_hash.clone())
+    let wants = remote
+        .branches
+        .iter()
+        .map(|branch| &branch.commit_hash)
+        .collect::<Vec<_>>();
+    let response = get_commits(&wants, uri).await.context("getting commits")?;
+    // indexing renames the pack away, so this only removes what a failure left behind
+    let incoming_pack = TemporaryFile(pack_directory.join("tmp_pack_incoming"));
+
+    receive_pack(response, &incoming_pack.0)
+        .await
+        .context("receiving packfile")?;
+    index_pack(&incoming_pack.0).context("indexing packfile")?;
+
+    let repository =
+        Repository::open(git_directory, target_directory).context("opening repository")?;
+    let default_branch = remote.default_branch().context("choosing a branch")?;
+
+    write_refs(repository.refs(), uri, &remote.branches, default_branch).context("writing refs")?;
+    // so the checked out branch knows where to pull from
+    config.set(
+        &format!("branch.{}.remote", default_branch.branch_name),
+        "origin",
+    )?;
+    config.set(
+        &format!("branch.{}.merge", default_branch.branch_name),
+        &format!("refs/heads/{}", default_branch.branch_name),
+    )?;
+    checkout::checkout(&repository, default_branch.commit_hash.clone())
         .context("checking out commit")?;
 
     Ok(())
 }
 
-fn save_git_objects(git_objects: &GitObjects, path: PathBuf) -> Result<()> {
-    for git_object in git_objects.values() {
-        save_to_disk(git_object, path.clone()).context("saving git object to disk")?;
+/// Remote branches go under `refs/remotes/origin`, and the default branch is created locally
+/// and checked out.
+fn write_refs(refs: &Refs, uri: &str, branches: &[GitRef], default_branch: &GitRef) -> Result<()> {
+    let local_branch = format!("refs/heads/{}", default_branch.branch_name);
+
+    // HEAD goes first so the reflog for HEAD records the clone too
+    refs.write(HEAD, &RefTarget::Symbolic(local_branch.clone()))?;
+    refs.write(
+        &format!("{REMOTE_PREFIX}HEAD"),
+        &RefTarget::Symbolic(format!("{REMOTE_PREFIX}{}", default_branch.branch_name)),
+    )?;
+
+    let mut transaction = refs.transaction();
+
+    transaction.message(&format!("clone: from {uri}"));
+
+    for branch in branches {
+        transaction.create(
+            &format!("{REMOTE_PREFIX}{}", branch.branch_name),
+            branch.commit_hash.clone(),
+        );
     }
 
+    transaction.create(&local_branch, default_branch.commit_hash.clone());
+    transaction.commit()?;
+
     Ok(())
 }
 
-// Implement using delta instructions at https://dev.to/calebsander/git-internals-part-2-packfiles-1jg8
-fn handle_ofs_delta() {
-    panic!("attempting to handle ofs delta");
+/// A file that's removed when this is dropped, if it's still there.
+struct TemporaryFile(PathBuf);
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
 }
 
-async fn handle_ref_delta<R: Read + AsRef<[u8]>>(
-    mut cursed_packfile: &mut Cursor<R>,
-    git_objects: &mut GitObjects,
-) -> Result<()> {
-    let mut hash = [0; 20];
-
-    cursed_packfile
-        .read(&mut hash)
-        .context("reading hash from cursed packfile")?;
-
-    let current_cursed_packfile_position = cursed_packfile.position();
-    let mut decoder = ZlibDecoder::new(&mut cursed_packfile);
-    let _base_object_size = read_size_encoding(&mut decoder).context("reading base object size")?;
-    let new_object_size = read_size_encoding(&mut decoder).context("reading new object size")?;
-    let base = git_objects
-        .get(&hash)
-        .context("missing base object when handling ref delta")?;
-    let mut decompressed_object = Vec::with_capacity(new_object_size);
-
-    loop {
-        let is_more = apply_delta_instruction(&mut decoder, &base, &mut decompressed_object)
-            .context("applying delta instruction")?;
-
-        if !is_more {
-            break;
+/// Streams the packfile in the upload-pack response straight to `pack_path`, so it never has
+/// to fit in memory.
+async fn receive_pack(mut response: Response, pack_path: &Path) -> Result<()> {
+    let mut pack_file = BufWriter::new(File::create(pack_path).context("creating packfile")?);
+    let mut preamble = vec![];
+    let mut in_pack = false;
+
+    while let Some(chunk) = response.chunk().await.context("reading response chunk")? {
+        if in_pack {
+            pack_file.write_all(&chunk).context("writing packfile")?;
+            continue;
         }
-    }
 
-    let mut header = format!("blob {}\0", decompressed_object.len()).into_bytes();
+        preamble.extend_from_slice(&chunk);
 
-    header.extend_from_slice(&decompressed_object);
+        // the pack comes after pkt-lines like NAK, which we don't need
+        loop {
+            if preamble.starts_with(PACK_SIGNATURE) {
+                in_pack = true;
+                pack_file.write_all(&preamble).context("writing packfile")?;
+                break;
+            }
 
-    let hash = get_hash(&header).context("getting hash")?;
+            if preamble.len() < 4 {
+                break;
+            }
 
-    git_objects.insert(hash, decompressed_object);
+            let length = usize::from_str_radix(std::str::from_utf8(&preamble[..4])?, 16)
+                .context("parsing pkt-line length before packfile")?
+                .max(4);
 
-    let count = decoder.total_in();
-    cursed_packfile
-        .seek(std::io::SeekFrom::Start(
-            current_cursed_packfile_position + count,
-        ))
-        .context("skipping data that we just read")?;
+            if preamble.len() < length {
+                break;
+            }
 
-    Ok(())
-}
+            preamble.drain(..length);
+        }
+    }
+
+    if !in_pack {
+        return Err(Error::protocol("response didn't contain a packfile"));
+    }
 
-fn handle_normal_object_type<R: Read + AsRef<[u8]>>(
-    size: usize,
-    object_type: &str,
-    mut cursed_packfile: &mut Cursor<R>,
-    target_directory: PathBuf,
-    git_objects: &mut GitObjects,
-) -> Result<Option<Hash>> {
-    let current_cursed_packfile_position = cursed_packfile.position();
-    let mut decompressed_object = Vec::with_capacity(size);
-    let mut decoder = ZlibDecoder::new(&mut cursed_packfile);
-    decoder
-        .read_to_end(&mut decompressed_object)
-        .context("reading decoded bytes for normal object")?;
-    let count = decoder.total_in();
-
-    cursed_packfile
-        .seek(std::io::SeekFrom::Start(
-            current_cursed_packfile_position + count,
-        ))
-        .context("skipping bytes that we read")?;
-    let header = format!("{object_type} {size}\0",);
-    let mut commit = header.into_bytes();
-    commit.extend(decompressed_object.clone());
-
-    let hash = save_to_disk(&commit, target_directory).context("saving normal object to disk")?;
-
-    git_objects.insert(hash.clone(), decompressed_object);
-
-    Ok(Some(hash))
+    pack_file.flush().context("flushing packfile")?;
+
+    Ok(())
 }
 
-async fn discover_references(repo_uri: &str) -> Result<Vec<GitRef>> {
+async fn discover_references(repo_uri: &str) -> Result<RemoteRefs> {
     let uri = format!("{repo_uri}/info/refs?service=git-upload-pack");
     let result = get(&uri).await?;
     let status = result.status();
     let response = result.bytes().await?;
-    let header = response.slice(0..5);
+    let header = response.slice(..response.len().min(5));
 
     if !status.is_success() {
-        bail!("failed request to discover references");
+        return Err(Error::protocol(format!(
+            "reference discovery failed with status {status}"
+        )));
     }
 
     if !validate_header(&header) {
-        bail!("Invalid header");
+        return Err(Error::protocol("invalid reference discovery header"));
     }
 
-    let references = process_ref_discovery_response(&response.slice(34..))?;
+    // skip the service announcement and the flush packet after it
+    if response.len() < 34 {
+        return Err(Error::protocol("reference discovery response ended early"));
+    }
 
-    Ok(references)
+    let advertisement = response.slice(34..);
+    let branches = process_ref_discovery_response(&advertisement)?;
+    let head = default_branch_name(&advertisement);
+
+    Ok(RemoteRefs { head, branches })
 }
 
 fn validate_header(header: &Bytes) -> bool {
-    if header.len() != 5 {
-        eprintln!("got header length {}, needed 5", header.len());
-        return false;
-    };
-
-    if header[4] != b'#' {
-        eprintln!("header doesn't end with '#'");
-        return false;
-    };
-
-    true
+    header.len() == 5 && header[4] == b'#'
 }
 
 fn process_ref_discovery_response(response: &Bytes) -> Result<Vec<GitRef>> {
@@ -226,23 +204,21 @@ fn process_ref_discovery_response(response: &Bytes) -> Result<Vec<GitRef>> {
             break;
         }
 
-        let mode = String::from_utf8(line[0..4].to_vec())?;
-        let hash = hex::decode(&line[4..44])?.try_into()?;
-        let branches = &line[45..].split(|branch| *branch == b'/');
-
-        let branch_title = branches.clone().skip(1).next().expect("doesn't have title");
-        if branch_title != b"heads" {
-            break;
-        }
+        let malformed = || {
+            Error::protocol(format!(
+                "malformed ref line {}",
+                String::from_utf8_lossy(line)
+            ))
+        };
+        let mode = String::from_utf8(line.get(0..4).ok_or_else(malformed)?.to_vec())?;
+        let hash = hex::decode(line.get(4..44).ok_or_else(malformed)?)?.try_into()?;
+        let name = std::str::from_utf8(line.get(45..).ok_or_else(malformed)?)?;
 
-        let branch = String::from_utf8(
-            branches
-                .clone()
-                .last()
-                .expect("couldn't find the branch name")
-                .to_vec(),
-        )?;
-        let branch_ref = GitRef::new(&mode, &hash, &branch);
+        // tags, pull requests and the like aren't branches
+        let Some(branch) = name.strip_prefix("refs/heads/") else {
+            continue;
+        };
+        let branch_ref = GitRef::new(&mode, &hash, branch);
 
         branch_refs.push(branch_ref);
     }
@@ -250,6 +226,41 @@ fn process_ref_discovery_response(response: &Bytes) -> Result<Vec<GitRef>> {
     Ok(branch_refs)
 }
 
+/// The branch HEAD points at on the remote, from the `symref=HEAD:refs/heads/<branch>`
+/// capability on the first advertised line
+fn default_branch_name(response: &Bytes) -> Option<String> {
+    let first_line = response.split(|b| *b == b'\n').next()?;
+    let first_line = String::from_utf8_lossy(first_line);
+
+    first_line
+        .split([' ', '\0'])
+        .find_map(|capability| capability.strip_prefix("symref=HEAD:refs/heads/"))
+        .map(str::to_owned)
+}
+
+struct RemoteRefs {
+    head: Option<String>,
+    branches: Vec<GitRef>,
+}
+
+impl RemoteRefs {
+    /// The branch the remote's HEAD points at, falling back to master or the first branch
+    /// for servers that don't say
+    fn default_branch(&self) -> Option<&GitRef> {
+        let named = |name: &str| {
+            self.branches
+                .iter()
+                .find(|branch| branch.branch_name == name)
+        };
+
+        self.head
+            .as_deref()
+            .and_then(named)
+            .or_else(|| named("master"))
+            .or_else(|| self.branches.first())
+    }
+}
+
 #[derive(PartialEq, Debug)]
 struct GitRef {
     mode: String,
@@ -267,16 +278,30 @@ impl GitRef {
     }
 }
 
-#[derive(Default, Debug)]
-enum ReaderState {
-    #[default]
-    Mode,
-}
-
-async fn get_commit(commit_hash: &Hash, repo_uri: &str) -> Result<Bytes> {
+async fn get_commits(commit_hashes: &[&Hash], repo_uri: &str) -> Result<Response> {
     let uri = format!("{repo_uri}/git-upload-pack");
     let client = reqwest::Client::new();
-    let body = format!("0032want {commit_hash}\n00000009done\n");
+    let mut body = String::new();
+    let mut wanted = HashSet::new();
+
+    for commit_hash in commit_hashes {
+        if !wanted.insert(*commit_hash) {
+            continue;
+        }
+
+        // capabilities go on the first want, and servers only send offset deltas to clients
+        // that ask for them
+        let want = if body.is_empty() {
+            format!("want {commit_hash} ofs-delta\n")
+        } else {
+            format!("want {commit_hash}\n")
+        };
+
+        body.push_str(&format!("{:04x}{want}", want.len() + 4));
+    }
+
+    body.push_str("00000009done\n");
+
     let response = client
         .post(uri)
         .header("Content-Type", "application/x-git-upload-pack-request")
@@ -285,37 +310,13 @@ async fn get_commit(commit_hash: &Hash, repo_uri: &str) -> Result<Bytes> {
         .await?;
 
     if !response.status().is_success() {
-        bail!("Error response when getting commit");
+        return Err(Error::protocol(format!(
+            "fetching the packfile failed with status {}",
+            response.status()
+        )));
     }
 
-    let body = response.bytes().await?;
-
-    Ok(body)
-}
-
-#[derive(Debug)]
-#[allow(dead_code)]
-struct Packfile {
-    head: String,
-    signature: String,
-    version: u32,
-    object_count: u32,
-}
-
-impl Packfile {
-    pub fn new(commit: Bytes) -> Result<Self> {
-        let head = std::str::from_utf8(&commit[0..8])?.to_owned();
-        let signature = std::str::from_utf8(&commit[8..12])?.to_owned();
-        let version = u32::from_be_bytes(commit[12..16].try_into()?);
-        let object_count = u32::from_be_bytes(commit[16..20].try_into()?);
-
-        Ok(Self {
-            head,
-            signature,
-            version,
-            object_count,
-        })
-    }
+    Ok(response)
 }
 
 #[cfg(test)]
@@ -396,4 +397,35 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn should_find_default_branch_and_skip_tags() -> Result<()> {
+        let mock_response = Bytes::from("001e# service=git-upload-pack
+0000014ccb13b1d4e0751da3f6a3e0ba9ca9c61b9a1ee41f HEAD\0multi_ack ofs-delta symref=HEAD:refs/heads/main agent=git/2.43.0
+003ff995bad1cf42515e59934d0c24194402b5ea6e65 refs/heads/feature/x
+003dcb13b1d4e0751da3f6a3e0ba9ca9c61b9a1ee41f refs/heads/main
+003e51514685f102183cfa64df603560351a817b5093 refs/tags/v1.0
+0000");
+        let advertisement = mock_response.slice(34..);
+        let remote = RemoteRefs {
+            head: default_branch_name(&advertisement),
+            branches: process_ref_discovery_response(&advertisement)?,
+        };
+        let branch_names = remote
+            .branches
+            .iter()
+            .map(|branch| branch.branch_name.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(remote.head.as_deref(), Some("main"));
+        assert_eq!(branch_names, vec!["feature/x", "main"]);
+        assert_eq!(
+            remote
+                .default_branch()
+                .map(|branch| branch.branch_name.as_str()),
+            Some("main")
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,118 @@
+use crate::{
+    commit_tree::commit_tree, error::Result, hash::Hash, identity, ref_transaction::Expected,
+    refs::HEAD, repository::Repository, write_tree::write_tree,
+};
+
+/// What `commit` made, for the one line summary
+#[derive(Debug)]
+pub struct CommitSummary {
+    pub hash: Hash,
+    /// The branch that moved, or `HEAD` when it's detached
+    pub branch: String,
+    pub root: bool,
+}
+
+/// Snapshots the index, or the work tree without one, commits it on top of HEAD and moves the
+/// branch HEAD is on to the new commit.
+pub fn commit(repository: &mut Repository, message: &str) -> Result<CommitSummary> {
+    let parent = repository.refs().resolve(HEAD)?;
+    let tree = write_tree(repository)?;
+    let parents = parent.iter().cloned().collect::<Vec<_>>();
+    let author = identity::author(repository.config())?;
+    let committer = identity::committer(repository.config())?;
+    let hash = commit_tree(
+        &tree,
+        &parents,
+        message,
+        &author,
+        &committer,
+        repository.objects_mut(),
+    )?;
+    let subject = message.lines().next().unwrap_or_default();
+    let reflog_message = match parent {
+        Some(_) => format!("commit: {subject}"),
+        None => format!("commit (initial): {subject}"),
+    };
+    // someone else moving the branch while we were committing shouldn't be silently undone
+    let expected = parent
+        .as_ref()
+        .map_or(Expected::Missing, |parent| Expected::Hash(parent.clone()));
+    let mut transaction = repository.refs().transaction();
+
+    transaction
+        .update(HEAD, hash.clone(), expected)
+        .message(&reflog_message)
+        .committer(committer);
+
+    let branch = transaction.commit()?.remove(0);
+
+    Ok(CommitSummary {
+        hash,
+        branch,
+        root: parent.is_none(),
+    })
+}
+
+impl CommitSummary {
+    /// `[master (root-commit) 1a2b3c4]`, like git prints before the subject
+    pub fn header(&self) -> String {
+        let branch = self
+            .branch
+            .strip_prefix("refs/heads/")
+            .unwrap_or("detached HEAD");
+        let root = if self.root { " (root-commit)" } else { "" };
+
+        format!("[{branch}{root} {}]", &self.hash.to_string()[..7])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::ConfigFile, init::init, object_store::ObjectStore, test_utils::temp_directory,
+    };
+
+    #[test]
+    fn should_commit_on_top_of_head() -> Result<()> {
+        let directory = temp_directory("commit");
+
+        std::fs::create_dir_all(&directory)?;
+
+        let git_directory = init(directory.clone())?;
+        let config = ConfigFile::new(git_directory.join("config"));
+
+        config.set("user.name", "A U Thor")?;
+        config.set("user.email", "author@example.com")?;
+
+        let mut repository = Repository::open(git_directory, directory.clone())?;
+
+        std::fs::write(directory.join("file.txt"), "one\n")?;
+
+        let first = commit(&mut repository, "first")?;
+
+        assert!(first.root);
+        assert_eq!(
+            first.header(),
+            format!("[master (root-commit) {}]", &first.hash.to_string()[..7])
+        );
+
+        std::fs::write(directory.join("file.txt"), "two\n")?;
+
+        let second = commit(&mut repository, "second\n\nwith a body")?;
+        let commit = repository.objects().read_commit(&second.hash)?;
+        let refs = repository.refs();
+
+        assert!(!second.root);
+        assert_eq!(commit.parents, vec![first.hash]);
+        assert_eq!(refs.resolve("refs/heads/master")?, Some(second.hash));
+        assert_eq!(
+            refs.reflog(HEAD)?
+                .into_iter()
+                .map(|entry| entry.message)
+                .collect::<Vec<_>>(),
+            vec!["commit (initial): first", "commit: second"]
+        );
+        Ok(())
+    }
+}
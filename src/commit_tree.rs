@@ -1,37 +1,131 @@
-use std::{path::PathBuf, time::UNIX_EPOCH};
-
-use anyhow::Result;
-use hex::ToHex;
-
-use crate::utils::save_to_disk;
-
-pub fn commit_tree(tree: &str, parent: &str, message: &str) -> Result<String> {
-    let mut commit_body = vec![];
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(UNIX_EPOCH)?
-        .as_millis();
-
-    // put the commit into the body
-    commit_body.extend(format!("tree {tree}\n").into_bytes());
-    commit_body.extend(format!("parent {parent}\n").into_bytes());
-    commit_body.extend(
-        format!("author Brookzerker <brooks_not_real_address@mailinator.com> {timestamp} -0700\n",)
-            .into_bytes(),
-    );
-    commit_body.extend(
-        format!(
-            "committer brookzerker<brooks_not_real_address@mailinator.com> {timestamp} -0700\n\n"
-        )
-        .into_bytes(),
-    );
-    commit_body.extend(format!("{message}\n").into_bytes());
-
-    let size = commit_body.len();
-    let mut commit = format!("commit {size}\0").into_bytes();
-
-    commit.extend(&commit_body);
-
-    let hash = save_to_disk(&commit, PathBuf::new())?;
-
-    Ok(hash.encode_hex())
+use crate::error::{Context, Result};
+
+use crate::{
+    hash::Hash,
+    object::{Commit, Object, Signature},
+    object_store::ObjectStore,
+};
+
+/// Writes a commit of `tree` on top of `parents`, with no parents for a root commit and
+/// several for a merge. The tree and parents have to exist already.
+pub fn commit_tree(
+    tree: &Hash,
+    parents: &[Hash],
+    message: &str,
+    author: &Signature,
+    committer: &Signature,
+    objects: &mut impl ObjectStore,
+) -> Result<Hash> {
+    objects
+        .read_tree(tree)
+        .with_context(|| format!("checking tree {tree}"))?;
+
+    for parent in parents {
+        objects
+            .read_commit(parent)
+            .with_context(|| format!("checking parent {parent}"))?;
+    }
+
+    let mut message = message.to_owned();
+
+    // messages from files usually end in a newline already
+    if !message.ends_with('\n') {
+        message.push('\n');
+    }
+
+    let commit = Commit {
+        tree: tree.clone(),
+        parents: parents.to_vec(),
+        author: author.clone(),
+        committer: committer.clone(),
+        extra_headers: vec![],
+        message: message.into_bytes(),
+        has_message: true,
+    };
+
+    objects.write_object(&Object::Commit(commit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Error, object_store::MemoryObjectStore};
+
+    fn empty_tree(objects: &mut MemoryObjectStore) -> Result<Hash> {
+        objects.write("tree", &[])
+    }
+
+    fn commit(
+        tree: &Hash,
+        parents: &[Hash],
+        message: &str,
+        objects: &mut MemoryObjectStore,
+    ) -> Result<Hash> {
+        let author = Signature::new("A U Thor", "author@example.com", 1700000000, -420);
+        let committer = Signature::new("C O Mitter", "committer@example.com", 1700000060, 60);
+
+        commit_tree(tree, parents, message, &author, &committer, objects)
+    }
+
+    #[test]
+    fn should_write_commit_to_store() -> Result<()> {
+        let mut objects = MemoryObjectStore::new();
+        let tree = empty_tree(&mut objects)?;
+        let parent = commit(&tree, &[], "root", &mut objects)?;
+        let hash = commit(
+            &tree,
+            std::slice::from_ref(&parent),
+            "a message",
+            &mut objects,
+        )?;
+        let (object_type, content) = objects.read_existing(&hash)?;
+        let content = String::from_utf8(content)?;
+
+        assert_eq!(object_type, "commit");
+        assert!(content.starts_with(&format!("tree {tree}\nparent {parent}\n")));
+        assert!(content.contains(
+            "author A U Thor <author@example.com> 1700000000 -0700\n\
+             committer C O Mitter <committer@example.com> 1700000060 +0100\n"
+        ));
+        assert!(content.ends_with("\n\na message\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_write_merge_commits() -> Result<()> {
+        let mut objects = MemoryObjectStore::new();
+        let tree = empty_tree(&mut objects)?;
+        let first = commit(&tree, &[], "first\n", &mut objects)?;
+        let second = commit(&tree, &[], "second", &mut objects)?;
+        let merge = commit(
+            &tree,
+            &[first.clone(), second.clone()],
+            "merge\n",
+            &mut objects,
+        )?;
+        let commit = objects.read_commit(&merge)?;
+
+        assert_eq!(commit.parents, vec![first, second]);
+        assert_eq!(commit.message, b"merge\n");
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_missing_or_mistyped_objects() -> Result<()> {
+        let mut objects = MemoryObjectStore::new();
+        let tree = empty_tree(&mut objects)?;
+        let blob = objects.write("blob", b"not a commit")?;
+        let missing = commit(&Hash::new([1; 20]), &[], "", &mut objects).unwrap_err();
+        let not_a_parent = commit(&tree, &[blob], "", &mut objects).unwrap_err();
+
+        assert!(matches!(missing.root(), Error::ObjectNotFound(_)));
+        assert!(matches!(
+            not_a_parent.root(),
+            Error::WrongObjectType {
+                expected: "commit",
+                ..
+            }
+        ));
+        Ok(())
+    }
 }
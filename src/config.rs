@@ -0,0 +1,911 @@
+// The file format is git's, described at https://git-scm.com/docs/git-config
+
+use std::{
+    fmt::Display,
+    io::Write,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Context, Error, Result},
+    lockfile::LockFile,
+};
+
+const REPOSITORY_CONFIG_FILE: &str = "config";
+const GLOBAL_CONFIG_FILE: &str = ".vcconfig";
+const SYSTEM_CONFIG_PATH: &str = "/etc/vcconfig";
+// these point the global and system scopes somewhere else, mostly for tests and scripts
+pub(crate) const GLOBAL_CONFIG_ENVIRONMENT: &str = "VC_CONFIG_GLOBAL";
+pub(crate) const SYSTEM_CONFIG_ENVIRONMENT: &str = "VC_CONFIG_SYSTEM";
+// includes that include each other would never finish
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Which file a setting came from. Later scopes override earlier ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    System,
+    Global,
+    Local,
+}
+
+impl Scope {
+    /// Where the file for this scope lives, `None` for the local scope outside a repository or
+    /// the global scope without a home directory
+    pub fn path(self, git_directory: Option<&Path>) -> Option<PathBuf> {
+        match self {
+            Self::System => Some(
+                std::env::var_os(SYSTEM_CONFIG_ENVIRONMENT)
+                    .map_or_else(|| PathBuf::from(SYSTEM_CONFIG_PATH), PathBuf::from),
+            ),
+            Self::Global => std::env::var_os(GLOBAL_CONFIG_ENVIRONMENT)
+                .map(PathBuf::from)
+                .or_else(|| {
+                    std::env::var_os("HOME").map(|home| Path::new(&home).join(GLOBAL_CONFIG_FILE))
+                }),
+            Self::Local => {
+                git_directory.map(|git_directory| git_directory.join(REPOSITORY_CONFIG_FILE))
+            }
+        }
+    }
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::System => write!(f, "system"),
+            Self::Global => write!(f, "global"),
+            Self::Local => write!(f, "local"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigEntry {
+    /// Like `remote.origin.url`, with the section and name lowercased
+    pub key: String,
+    /// `None` for a name on its own, which is a boolean that's switched on
+    pub value: Option<String>,
+    pub scope: Scope,
+}
+
+/// Settings from the system, global and repository config files, in the order they were read.
+/// Single valued settings take the last value, so later files win.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    entries: Vec<ConfigEntry>,
+}
+
+impl Config {
+    /// Reads `/etc/vcconfig`, `~/.vcconfig` and, inside a repository, `.vc/config`.
+    pub fn load(git_directory: Option<&Path>) -> Result<Self> {
+        let mut config = Self::default();
+
+        for scope in [Scope::System, Scope::Global, Scope::Local] {
+            if let Some(path) = scope.path(git_directory) {
+                config
+                    .entries
+                    .extend(Self::read_file(&path, scope)?.entries);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Reads a single file and whatever it includes. A missing file is empty.
+    pub fn read_file(path: &Path, scope: Scope) -> Result<Self> {
+        let mut config = Self::default();
+
+        config.read_into(path, scope, 0)?;
+
+        Ok(config)
+    }
+
+    fn read_into(&mut self, path: &Path, scope: Scope, depth: usize) -> Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(Error::Config(format!(
+                "{} is included too deeply, do includes form a loop?",
+                path.display()
+            )));
+        }
+
+        if !path.is_file() {
+            return Ok(());
+        }
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config file {}", path.display()))?;
+        let document = Document::parse(&content)
+            .with_context(|| format!("parsing config file {}", path.display()))?;
+
+        for item in document.items {
+            let entry = ConfigEntry {
+                key: item.key,
+                value: item.value,
+                scope,
+            };
+
+            // included files are read in place, relative to the file including them
+            if entry.key == "include.path" {
+                if let Some(include) = entry.value.as_deref().map(expand_path) {
+                    let include = path.parent().unwrap_or(Path::new("")).join(include);
+
+                    self.read_into(&include, scope, depth + 1)?;
+                }
+            }
+
+            self.entries.push(entry);
+        }
+
+        Ok(())
+    }
+
+    pub fn parse(content: &str, scope: Scope) -> Result<Self> {
+        let entries = Document::parse(content)?
+            .items
+            .into_iter()
+            .map(|item| ConfigEntry {
+                key: item.key,
+                value: item.value,
+                scope,
+            })
+            .collect();
+
+        Ok(Self { entries })
+    }
+
+    pub fn entries(&self) -> &[ConfigEntry] {
+        &self.entries
+    }
+
+    /// The last value set for a key like `user.name`. A name on its own reads as `true`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_all(key).pop()
+    }
+
+    /// Every value of a key that can be given more than once, like `remote.origin.fetch`
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = normalize_key(key);
+
+        self.entries
+            .iter()
+            .filter(|entry| entry.key == key)
+            .map(|entry| entry.value.as_deref().unwrap_or("true"))
+            .collect()
+    }
+
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        self.get(key)
+            .map(|value| parse_bool(key, value))
+            .transpose()
+    }
+
+    pub fn get_int(&self, key: &str) -> Result<Option<i64>> {
+        self.get(key).map(|value| parse_int(key, value)).transpose()
+    }
+
+    /// A path, with a leading `~/` expanded to the home directory
+    pub fn get_path(&self, key: &str) -> Option<PathBuf> {
+        self.get(key).map(expand_path)
+    }
+}
+
+/// `true`, `yes`, `on` and non zero numbers are true, `false`, `no`, `off`, `0` and the empty
+/// string are false
+pub fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" | "" => Ok(false),
+        number => number
+            .parse::<i64>()
+            .map(|number| number != 0)
+            .map_err(|_error| Error::Config(format!("{key} = {value:?} is not a boolean"))),
+    }
+}
+
+/// Whole numbers, optionally scaled by a `k`, `m` or `g` suffix
+pub fn parse_int(key: &str, value: &str) -> Result<i64> {
+    let invalid = || Error::Config(format!("{key} = {value:?} is not a number"));
+    let (number, scale) = match value.char_indices().last() {
+        Some((index, 'k' | 'K')) => (&value[..index], 1 << 10),
+        Some((index, 'm' | 'M')) => (&value[..index], 1 << 20),
+        Some((index, 'g' | 'G')) => (&value[..index], 1 << 30),
+        _ => (value, 1),
+    };
+
+    number
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .and_then(|number| number.checked_mul(scale))
+        .ok_or_else(invalid)
+}
+
+fn expand_path(value: &str) -> PathBuf {
+    match (value.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(value),
+    }
+}
+
+/// Splits `section.subsection.name` into its parts. The subsection can contain dots.
+fn split_key(key: &str) -> Result<(&str, Option<&str>, &str)> {
+    let invalid = || Error::Config(format!("{key} is not a valid key, it needs a section"));
+    let (section, rest) = key.split_once('.').ok_or_else(invalid)?;
+
+    let (subsection, name) = match rest.rsplit_once('.') {
+        Some((subsection, name)) => (Some(subsection), name),
+        None => (None, rest),
+    };
+
+    if section.is_empty()
+        || name.is_empty()
+        || !name.starts_with(|c: char| c.is_ascii_alphabetic())
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(invalid());
+    }
+
+    Ok((section, subsection, name))
+}
+
+/// Section and key names are case insensitive, subsections aren't
+fn normalize_key(key: &str) -> String {
+    match split_key(key) {
+        Ok((section, Some(subsection), name)) => format!(
+            "{}.{subsection}.{}",
+            section.to_lowercase(),
+            name.to_lowercase()
+        ),
+        Ok((section, None, name)) => format!("{}.{}", section.to_lowercase(), name.to_lowercase()),
+        Err(_) => key.to_lowercase(),
+    }
+}
+
+/// A setting in a file, and the lines it takes up so it can be rewritten in place
+#[derive(Debug)]
+struct Item {
+    key: String,
+    value: Option<String>,
+    lines: Range<usize>,
+}
+
+/// A section header, and the lines of the section from its header to its last setting
+#[derive(Debug)]
+struct Section {
+    /// `section` or `section.subsection`, ready to have a name appended
+    prefix: String,
+    start: usize,
+    end: usize,
+}
+
+/// A config file as written, so settings can be changed without touching anything else
+#[derive(Debug)]
+struct Document {
+    lines: Vec<String>,
+    items: Vec<Item>,
+    sections: Vec<Section>,
+}
+
+impl Document {
+    fn parse(content: &str) -> Result<Self> {
+        let lines = content.lines().map(str::to_owned).collect::<Vec<_>>();
+        let mut items = vec![];
+        let mut sections: Vec<Section> = vec![];
+        let mut index = 0;
+
+        while index < lines.len() {
+            let start = index;
+            let line = lines[index].trim_start();
+            let invalid = || Error::Config(format!("bad config line {}: {line:?}", start + 1));
+
+            index += 1;
+
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                sections.push(Section {
+                    prefix: parse_section_header(header).ok_or_else(invalid)?,
+                    start,
+                    end: start,
+                });
+                continue;
+            }
+
+            let section = sections.last_mut().ok_or_else(invalid)?;
+            let name_length = line
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+                .unwrap_or(line.len());
+            let (name, rest) = line.split_at(name_length);
+
+            if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                return Err(invalid());
+            }
+
+            let rest = rest.trim_start();
+            let value = match rest.strip_prefix('=') {
+                Some(value) => {
+                    let mut value = value.to_owned();
+
+                    // a backslash at the end of the line carries the value on to the next
+                    while ends_with_continuation(&value) {
+                        let next = lines.get(index).ok_or_else(invalid)?;
+
+                        value.pop();
+                        value.push_str(next);
+                        index += 1;
+                    }
+
+                    Some(parse_value(&value).ok_or_else(invalid)?)
+                }
+                None if rest.is_empty() || rest.starts_with(['#', ';']) => None,
+                None => return Err(invalid()),
+            };
+
+            section.end = index - 1;
+            items.push(Item {
+                key: format!("{}.{}", section.prefix, name.to_lowercase()),
+                value,
+                lines: start..index,
+            });
+        }
+
+        Ok(Self {
+            lines,
+            items,
+            sections,
+        })
+    }
+
+    fn render(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| format!("{line}\n"))
+            .collect::<String>()
+    }
+}
+
+/// `core]`, `remote "origin"]` or the older `branch.main]`, after the opening bracket
+fn parse_section_header(header: &str) -> Option<String> {
+    let (inside, rest) = match header.split_once('"') {
+        Some((name, quoted)) => {
+            let mut subsection = String::new();
+            let mut characters = quoted.chars();
+
+            loop {
+                match characters.next()? {
+                    '\\' => subsection.push(characters.next()?),
+                    '"' => break,
+                    character => subsection.push(character),
+                }
+            }
+
+            let rest = characters.as_str().strip_prefix(']')?;
+            let name = name.trim();
+
+            if name.is_empty() || !name.chars().all(is_section_character) {
+                return None;
+            }
+
+            (format!("{}.{subsection}", name.to_lowercase()), rest)
+        }
+        None => {
+            let (name, rest) = header.split_once(']')?;
+
+            if name.is_empty() || !name.chars().all(|c| is_section_character(c) || c == '.') {
+                return None;
+            }
+
+            // the older dotted form's subsection is case insensitive too
+            (name.to_lowercase(), rest)
+        }
+    };
+    let rest = rest.trim();
+
+    (rest.is_empty() || rest.starts_with(['#', ';'])).then_some(inside)
+}
+
+fn is_section_character(character: char) -> bool {
+    character.is_ascii_alphanumeric() || character == '-'
+}
+
+fn ends_with_continuation(value: &str) -> bool {
+    let backslashes = value.chars().rev().take_while(|c| *c == '\\').count();
+
+    backslashes % 2 == 1
+}
+
+/// Unquotes and unescapes a value, dropping comments and the whitespace around it
+fn parse_value(raw: &str) -> Option<String> {
+    let mut value = String::new();
+    let mut pending_space = String::new();
+    let mut quoted = false;
+    let mut characters = raw.chars();
+
+    while let Some(character) = characters.next() {
+        match character {
+            '"' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                quoted = !quoted;
+            }
+            '\\' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(match characters.next()? {
+                    'n' => '\n',
+                    't' => '\t',
+                    'b' => '\u{8}',
+                    character @ ('\\' | '"') => character,
+                    _ => return None,
+                });
+            }
+            '#' | ';' if !quoted => break,
+            character if character.is_whitespace() && !quoted => {
+                if !value.is_empty() {
+                    pending_space.push(character);
+                }
+            }
+            character => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(character);
+            }
+        }
+    }
+
+    (!quoted).then_some(value)
+}
+
+/// How `config` reads and checks values, like git's `--type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Bool,
+    Int,
+    Path,
+}
+
+/// Checks `value` is a `value_type` and writes it the canonical way, `true` for `yes` and so on
+pub fn canonicalize(key: &str, value: &str, value_type: Option<ValueType>) -> Result<String> {
+    match value_type {
+        None => Ok(value.to_owned()),
+        Some(ValueType::Bool) => parse_bool(key, value).map(|value| value.to_string()),
+        Some(ValueType::Int) => parse_int(key, value).map(|value| value.to_string()),
+        Some(ValueType::Path) => Ok(expand_path(value).display().to_string()),
+    }
+}
+
+/// What the `config` command does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigAction {
+    Get(String),
+    GetAll(String),
+    Set(String, String),
+    Add(String, String),
+    Unset(String),
+    UnsetAll(String),
+    List,
+}
+
+/// Runs the `config` command. Reads look at every scope unless one is given, writes go to the
+/// repository's config unless one is given.
+pub fn config(
+    git_directory: Option<&Path>,
+    scope: Option<Scope>,
+    value_type: Option<ValueType>,
+    action: ConfigAction,
+    output: &mut impl Write,
+) -> Result<()> {
+    let read = || match scope {
+        Some(scope) => match scope.path(git_directory) {
+            Some(path) => Config::read_file(&path, scope),
+            None => Ok(Config::default()),
+        },
+        None => Config::load(git_directory),
+    };
+    let file = || {
+        scope
+            .unwrap_or(Scope::Local)
+            .path(git_directory)
+            .map(ConfigFile::new)
+            .ok_or_else(|| {
+                Error::Config("not in a repository, use --global or --system".to_owned())
+            })
+    };
+    let not_set = |key: &str| Error::ConfigNotSet(key.to_owned());
+
+    match action {
+        ConfigAction::Get(key) => {
+            let config = read()?;
+            let value = config.get(&key).ok_or_else(|| not_set(&key))?;
+
+            writeln!(output, "{}", canonicalize(&key, value, value_type)?)?;
+        }
+        ConfigAction::GetAll(key) => {
+            let config = read()?;
+            let values = config.get_all(&key);
+
+            if values.is_empty() {
+                return Err(not_set(&key));
+            }
+
+            for value in values {
+                writeln!(output, "{}", canonicalize(&key, value, value_type)?)?;
+            }
+        }
+        ConfigAction::Set(key, value) => {
+            file()?.set(&key, &canonicalize(&key, &value, value_type)?)?;
+        }
+        ConfigAction::Add(key, value) => {
+            file()?.add(&key, &canonicalize(&key, &value, value_type)?)?;
+        }
+        ConfigAction::Unset(key) => {
+            if !file()?.unset(&key, false)? {
+                return Err(not_set(&key));
+            }
+        }
+        ConfigAction::UnsetAll(key) => {
+            if !file()?.unset(&key, true)? {
+                return Err(not_set(&key));
+            }
+        }
+        ConfigAction::List => {
+            for entry in read()?.entries() {
+                match &entry.value {
+                    Some(value) => writeln!(output, "{}={value}", entry.key)?,
+                    None => writeln!(output, "{}", entry.key)?,
+                }
+            }
+        }
+    }
+
+    output.flush().context("flushing config output")?;
+
+    Ok(())
+}
+
+/// Quotes a value when it would otherwise lose whitespace or look like a comment
+fn format_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    let needs_quotes = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';']);
+
+    if needs_quotes {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
+
+fn format_section_header(section: &str, subsection: Option<&str>) -> String {
+    match subsection {
+        Some(subsection) => format!(
+            "[{section} \"{}\"]",
+            subsection.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        None => format!("[{section}]"),
+    }
+}
+
+/// Changes a config file in place, keeping its comments and layout. Writes go through a lock
+/// file, so concurrent writers can't lose each other's changes.
+pub struct ConfigFile {
+    path: PathBuf,
+}
+
+impl ConfigFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Runs `change` on the current content and writes the result back, all under the lock
+    fn edit<T>(&self, change: impl FnOnce(&mut Document) -> Result<T>) -> Result<T> {
+        let mut lock = LockFile::acquire(&self.path)?;
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(error) => {
+                return Err(error).with_context(|| format!("reading {}", self.path.display()))
+            }
+        };
+        let mut document = Document::parse(&content)
+            .with_context(|| format!("parsing config file {}", self.path.display()))?;
+        let result = change(&mut document)?;
+
+        lock.write_all(document.render().as_bytes())
+            .with_context(|| format!("writing {}", self.path.display()))?;
+        lock.commit()?;
+
+        Ok(result)
+    }
+
+    /// Sets a single valued key, replacing its value if it has one.
+    pub fn set(&self, key: &str, value: &str) -> Result<()> {
+        let (section, subsection, name) = split_key(key)?;
+        let normalized = normalize_key(key);
+        let line = format!("\t{name} = {}", format_value(value));
+
+        self.edit(|document| {
+            let existing = document
+                .items
+                .iter()
+                .filter(|item| item.key == normalized)
+                .collect::<Vec<_>>();
+
+            match existing.as_slice() {
+                [] => insert(document, section, subsection, line),
+                [item] => {
+                    document.lines.splice(item.lines.clone(), [line]);
+                }
+                _ => {
+                    return Err(Error::Config(format!(
+                        "{key} has multiple values, unset them first"
+                    )))
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Adds another value to a key that can have several, like `remote.origin.fetch`.
+    pub fn add(&self, key: &str, value: &str) -> Result<()> {
+        let (section, subsection, name) = split_key(key)?;
+        let line = format!("\t{name} = {}", format_value(value));
+
+        self.edit(|document| {
+            insert(document, section, subsection, line);
+
+            Ok(())
+        })
+    }
+
+    /// Removes a key, returning whether it was set. Keys with several values are only
+    /// removed with `all`.
+    pub fn unset(&self, key: &str, all: bool) -> Result<bool> {
+        let normalized = normalize_key(key);
+
+        self.edit(|document| {
+            let ranges = document
+                .items
+                .iter()
+                .filter(|item| item.key == normalized)
+                .map(|item| item.lines.clone())
+                .collect::<Vec<_>>();
+
+            if ranges.len() > 1 && !all {
+                return Err(Error::Config(format!(
+                    "{key} has multiple values, unset them all instead"
+                )));
+            }
+
+            for range in ranges.iter().rev() {
+                document.lines.drain(range.clone());
+            }
+
+            Ok(!ranges.is_empty())
+        })
+    }
+
+    /// Renames every `[section "subsection"]` called `old`, like `branch.topic`, to `new`,
+    /// returning whether there were any.
+    pub fn rename_section(&self, old: &str, new: &str) -> Result<bool> {
+        let prefix = section_prefix(old);
+        let (section, subsection) = match new.split_once('.') {
+            Some((section, subsection)) => (section, Some(subsection)),
+            None => (new, None),
+        };
+
+        self.edit(|document| {
+            let starts = document
+                .sections
+                .iter()
+                .filter(|existing| existing.prefix == prefix)
+                .map(|existing| existing.start)
+                .collect::<Vec<_>>();
+
+            for start in &starts {
+                document.lines[*start] = format_section_header(section, subsection);
+            }
+
+            Ok(!starts.is_empty())
+        })
+    }
+
+    /// Removes every section called `name` with all of its settings, returning whether there
+    /// were any.
+    pub fn remove_section(&self, name: &str) -> Result<bool> {
+        let prefix = section_prefix(name);
+
+        self.edit(|document| {
+            let ranges = document
+                .sections
+                .iter()
+                .filter(|existing| existing.prefix == prefix)
+                .map(|existing| existing.start..existing.end + 1)
+                .collect::<Vec<_>>();
+
+            for range in ranges.iter().rev() {
+                document.lines.drain(range.clone());
+            }
+
+            Ok(!ranges.is_empty())
+        })
+    }
+}
+
+/// `branch.topic` as sections are matched, with only the section name lowercased
+fn section_prefix(name: &str) -> String {
+    match name.split_once('.') {
+        Some((section, subsection)) => format!("{}.{subsection}", section.to_lowercase()),
+        None => name.to_lowercase(),
+    }
+}
+
+/// Puts `line` at the end of the last matching section, or in a new one at the end of the file
+fn insert(document: &mut Document, section: &str, subsection: Option<&str>, line: String) {
+    let prefix = match subsection {
+        Some(subsection) => format!("{}.{subsection}", section.to_lowercase()),
+        None => section.to_lowercase(),
+    };
+
+    match document
+        .sections
+        .iter()
+        .rev()
+        .find(|existing| existing.prefix == prefix)
+    {
+        Some(existing) => document.lines.insert(existing.end + 1, line),
+        None => {
+            document
+                .lines
+                .push(format_section_header(section, subsection));
+            document.lines.push(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_directory;
+
+    #[test]
+    fn should_read_sections_and_keys() -> Result<()> {
+        let config = Config::parse(
+            "# identity\n[user]\n\tname = A U Thor\n\tEmail = author@example.com ; work\n\
+             [remote \"origin\"]\n\turl = https://example.com/repo.git\n\
+             \tfetch = +refs/heads/*:refs/remotes/origin/*\n\tfetch = +refs/tags/*:refs/tags/*\n\
+             [User]\n\tname = \"Someone  Else\"\n[core]\n\tbare\n\tbigFileThreshold = 512k\n\
+             [branch.Main]\n\tremote = origin\n[alias]\n\tlg = log \\\n  --oneline \"# not a comment\"\n",
+            Scope::Local,
+        )?;
+
+        assert_eq!(config.get("user.name"), Some("Someone  Else"));
+        assert_eq!(config.get("USER.EMAIL"), Some("author@example.com"));
+        assert_eq!(
+            config.get("remote.origin.url"),
+            Some("https://example.com/repo.git")
+        );
+        assert_eq!(config.get_all("remote.origin.fetch").len(), 2);
+        assert_eq!(config.get("remote.Origin.url"), None);
+        assert_eq!(config.get_bool("core.bare")?, Some(true));
+        assert_eq!(config.get_int("core.bigfilethreshold")?, Some(512 * 1024));
+        assert_eq!(config.get("branch.main.remote"), Some("origin"));
+        assert_eq!(
+            config.get("alias.lg"),
+            Some("log   --oneline # not a comment")
+        );
+        assert!(config.get_bool("user.name").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_malformed_files() {
+        for content in [
+            "name = outside of a section\n",
+            "[unclosed\n",
+            "[core]\n\t1name = x\n",
+            "[core]\n\tname = \"unterminated\n",
+        ] {
+            assert!(Config::parse(content, Scope::Local).is_err(), "{content}");
+        }
+    }
+
+    #[test]
+    fn should_follow_includes() -> Result<()> {
+        let directory = temp_directory("config-include");
+
+        std::fs::create_dir_all(&directory)?;
+        std::fs::write(
+            directory.join("config"),
+            "[user]\n\tname = Before\n[include]\n\tpath = extra\n[core]\n\tabbrev = 12\n",
+        )?;
+        std::fs::write(
+            directory.join("extra"),
+            "[user]\n\tname = Included\n\temail = included@example.com\n",
+        )?;
+
+        let config = Config::read_file(&directory.join("config"), Scope::Global)?;
+
+        assert_eq!(config.get("user.name"), Some("Included"));
+        assert_eq!(config.get_int("core.abbrev")?, Some(12));
+        assert!(config
+            .entries()
+            .iter()
+            .all(|entry| entry.scope == Scope::Global));
+        Ok(())
+    }
+
+    #[test]
+    fn should_edit_files_in_place() -> Result<()> {
+        let directory = temp_directory("config-edit");
+        let path = directory.join("config");
+
+        std::fs::create_dir_all(&directory)?;
+        std::fs::write(
+            &path,
+            "# keep me\n[core]\n\tbare = false\n[user]\n\tname = Old\n",
+        )?;
+
+        let file = ConfigFile::new(path.clone());
+
+        file.set("user.name", "New Name")?;
+        file.set("core.editor", "vim # really")?;
+        file.set("remote.origin.url", "https://example.com/repo.git")?;
+        file.add("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")?;
+        file.add("remote.origin.fetch", "+refs/tags/*:refs/tags/*")?;
+
+        assert!(file.set("remote.origin.fetch", "x").is_err());
+        assert!(file.unset("remote.origin.fetch", false).is_err());
+        assert!(file.unset("remote.origin.fetch", true)?);
+        assert!(!file.unset("user.email", false)?);
+
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "# keep me\n[core]\n\tbare = false\n\teditor = \"vim # really\"\n[user]\n\t\
+             name = New Name\n[remote \"origin\"]\n\turl = https://example.com/repo.git\n"
+        );
+
+        let config = Config::read_file(&path, Scope::Local)?;
+
+        assert_eq!(config.get("core.editor"), Some("vim # really"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_rename_and_remove_sections() -> Result<()> {
+        let directory = temp_directory("config-sections");
+        let path = directory.join("config");
+
+        std::fs::create_dir_all(&directory)?;
+        std::fs::write(
+            &path,
+            "[branch \"old\"]\n\tremote = origin\n[core]\n\tbare = false\n\
+             [Branch \"old\"]\n\tmerge = refs/heads/old\n[branch \"other\"]\n\tremote = .\n",
+        )?;
+
+        let file = ConfigFile::new(path.clone());
+
+        assert!(file.rename_section("branch.old", "branch.new")?);
+        assert!(file.remove_section("branch.other")?);
+        assert!(!file.remove_section("branch.old")?);
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "[branch \"new\"]\n\tremote = origin\n[core]\n\tbare = false\n\
+             [branch \"new\"]\n\tmerge = refs/heads/old\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use std::{fmt::Display, path::PathBuf};
+
+use crate::hash::Hash;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("object {0} not found")]
+    ObjectNotFound(Hash),
+    #[error("{hash} is a {found}, not a {expected}")]
+    WrongObjectType {
+        hash: Hash,
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// Objects, packs, indexes and other repository data that don't parse
+    #[error("corrupt object: {0}")]
+    CorruptObject(String),
+    #[error("invalid object hash: {0}")]
+    InvalidHash(String),
+    #[error("not a vc repository (or any of the parent directories): {}", .0.display())]
+    NotARepository(PathBuf),
+    #[error("invalid ref: {0}")]
+    InvalidRef(String),
+    /// A config file that doesn't parse, or a setting with the wrong type of value
+    #[error("bad config: {0}")]
+    Config(String),
+    #[error("{0} is not set")]
+    ConfigNotSet(String),
+    /// A date in the environment that isn't in any format git accepts
+    #[error("invalid date {0}")]
+    InvalidDate(String),
+    /// Neither the environment nor config says who is committing, which git won't guess either
+    #[error("{0} identity unknown, set user.name and user.email with config --global")]
+    UnknownIdentity(&'static str),
+    /// A conflicted path that has to be resolved before it can be committed
+    #[error("{0} needs merge")]
+    Unmerged(String),
+    #[error("{} is outside the repository", .0.display())]
+    OutsideRepository(PathBuf),
+    /// Index paths have to be UTF-8
+    #[error("invalid path {}", .0.display())]
+    InvalidPath(PathBuf),
+    #[error("pathspec '{0}' did not match any files")]
+    PathspecMismatch(String),
+    #[error("{0} is ignored, use -f to add it anyway")]
+    Ignored(String),
+    /// Removing the file would lose changes that were never staged
+    #[error("{0} has local modifications, use --cached to keep the file or -f to remove it")]
+    LocalChanges(String),
+    /// Removing the file would lose changes that were staged but never committed
+    #[error(
+        "{0} has changes staged in the index, use --cached to keep the file or -f to remove it"
+    )]
+    StagedChanges(String),
+    #[error("{0} has staged content different from both the file and HEAD, use -f to remove it")]
+    StagedAndLocalChanges(String),
+    #[error("not removing '{0}' recursively without -r")]
+    NotRecursive(String),
+    #[error("bad move: {0}")]
+    BadMove(String),
+    #[error("a branch named '{0}' already exists")]
+    BranchExists(String),
+    #[error("branch '{0}' not found")]
+    BranchNotFound(String),
+    /// Deleting the branch would lose commits that neither its upstream nor HEAD has
+    #[error("the branch '{0}' is not fully merged, use -D to delete it anyway")]
+    NotMerged(String),
+    /// A branch operation that doesn't make sense, like deleting the branch HEAD is on
+    #[error("{0}")]
+    BadBranch(String),
+    /// Switching would lose changes that were never committed
+    #[error("your local changes to these files would be overwritten by checkout:\n\t{}\ncommit them before you switch branches", .0.join("\n\t"))]
+    WouldOverwrite(Vec<String>),
+    /// Switching would replace files that aren't tracked
+    #[error("these untracked files would be overwritten by checkout:\n\t{}\nmove or remove them before you switch branches", .0.join("\n\t"))]
+    WouldOverwriteUntracked(Vec<String>),
+    /// A `--author` or `--grep` pattern that isn't a valid regular expression
+    #[error(transparent)]
+    Pattern(#[from] regex::Error),
+    #[error("invalid --format: {0}")]
+    InvalidFormat(String),
+    #[error("unknown revision {0}")]
+    UnknownRevision(String),
+    /// An abbreviated hash that more than one object starts with
+    #[error("short object ID {prefix} is ambiguous, it could be {}", candidates.iter().map(Hash::to_string).collect::<Vec<_>>().join(", "))]
+    AmbiguousHash {
+        prefix: String,
+        candidates: Vec<Hash>,
+    },
+    /// A ref didn't hold the value a compare-and-swap update expected
+    #[error("cannot update {name}: expected {expected} but found {actual}")]
+    RefConflict {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+    /// Someone else is updating the same file, or crashed while doing so
+    #[error("unable to create {}: another process holds the lock", .0.display())]
+    Locked(PathBuf),
+    /// The remote sent something we didn't expect
+    #[error("protocol error: {0}")]
+    Protocol(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Walk(#[from] ignore::Error),
+    /// What we were doing when `source` happened
+    #[error("{context}")]
+    Context {
+        context: String,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    pub fn corrupt(message: impl Display) -> Self {
+        Self::CorruptObject(message.to_string())
+    }
+
+    pub fn protocol(message: impl Display) -> Self {
+        Self::Protocol(message.to_string())
+    }
+
+    /// The error underneath any context that was added on the way up
+    pub fn root(&self) -> &Error {
+        match self {
+            Self::Context { source, .. } => source.root(),
+            error => error,
+        }
+    }
+}
+
+// data that fails to parse as text, numbers or fixed size fields is corrupt
+macro_rules! corrupt_from {
+    ($($error:ty),*) => {
+        $(
+            impl From<$error> for Error {
+                fn from(error: $error) -> Self {
+                    Self::corrupt(error)
+                }
+            }
+        )*
+    };
+}
+
+corrupt_from!(
+    std::str::Utf8Error,
+    std::string::FromUtf8Error,
+    std::num::ParseIntError,
+    std::num::TryFromIntError,
+    std::array::TryFromSliceError,
+    hex::FromHexError
+);
+
+/// Adds context to errors on their way up, the way `anyhow::Context` does.
+pub trait Context<T> {
+    fn context<C: Display>(self, context: C) -> Result<T>;
+
+    fn with_context<C: Display, F: FnOnce() -> C>(self, context: F) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for std::result::Result<T, E> {
+    fn context<C: Display>(self, context: C) -> Result<T> {
+        self.map_err(|error| Error::Context {
+            context: context.to_string(),
+            source: Box::new(error.into()),
+        })
+    }
+
+    fn with_context<C: Display, F: FnOnce() -> C>(self, context: F) -> Result<T> {
+        self.map_err(|error| Error::Context {
+            context: context().to_string(),
+            source: Box::new(error.into()),
+        })
+    }
+}
+
+/// Something missing from data we're parsing means the data is corrupt.
+impl<T> Context<T> for Option<T> {
+    fn context<C: Display>(self, context: C) -> Result<T> {
+        self.ok_or_else(|| Error::corrupt(context))
+    }
+
+    fn with_context<C: Display, F: FnOnce() -> C>(self, context: F) -> Result<T> {
+        self.ok_or_else(|| Error::corrupt(context()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_find_root_error_under_context() {
+        let result: Result<()> = Err(Error::ObjectNotFound(Hash::default()));
+        let error = result.context("reading commit").unwrap_err();
+
+        assert!(matches!(error.root(), Error::ObjectNotFound(_)));
+        assert_eq!(error.to_string(), "reading commit");
+    }
+
+    #[test]
+    fn should_treat_missing_values_as_corrupt() {
+        let error = None::<u8>
+            .context("tree entry is missing its hash")
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "corrupt object: tree entry is missing its hash"
+        );
+    }
+}
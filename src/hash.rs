@@ -1,8 +1,8 @@
-use std::{borrow::Borrow, fmt::Display, ops::Deref};
+use std::{borrow::Borrow, fmt::Display, ops::Deref, str::FromStr};
 
-use anyhow::anyhow;
+use crate::error::Error;
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone, Default)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Default)]
 pub struct Hash {
     hash: [u8; 20],
 }
@@ -44,27 +44,43 @@ impl IntoIterator for &Hash {
 }
 
 impl TryFrom<Vec<u8>> for Hash {
-    type Error = anyhow::Error;
+    type Error = Error;
 
+    /// Takes either the 20 raw bytes or 40 hex digits
     fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
         let value = if value.len() == 40 {
-            hex::decode(value).map_err(|_error| {
-                anyhow!("Error decoding value in preparation for converting to Hash")
+            hex::decode(&value).map_err(|_error| {
+                Error::InvalidHash(String::from_utf8_lossy(&value).into_owned())
             })?
         } else {
             value
         };
 
         Ok(Self {
-            hash: value
-                .try_into()
-                .map_err(|_error| anyhow!("Error attempting to convert Vec<u8> to [u8; 20]"))?,
+            hash: value.try_into().map_err(|value: Vec<u8>| {
+                Error::InvalidHash(format!("expected 20 bytes, got {}", value.len()))
+            })?,
         })
     }
 }
 
+impl FromStr for Hash {
+    type Err = Error;
+
+    /// Parses a full 40 character hex hash, like the ones given on the command line
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.len() != 40 || !value.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(Error::InvalidHash(format!(
+                "{value} is not 40 hex characters"
+            )));
+        }
+
+        value.as_bytes().to_vec().try_into()
+    }
+}
+
 impl Display for Hash {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", hex::encode(&self.hash))
+        write!(f, "{}", hex::encode(self.hash))
     }
 }
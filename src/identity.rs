@@ -0,0 +1,260 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, Offset, TimeZone};
+
+use crate::{
+    config::Config,
+    error::{Error, Result},
+    object::Signature,
+};
+
+/// Whose name goes on a commit. Git lets the author and committer be different people, like
+/// when applying someone else's patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    fn environment_prefix(self) -> &'static str {
+        match self {
+            Self::Author => "VC_AUTHOR",
+            Self::Committer => "VC_COMMITTER",
+        }
+    }
+
+    fn config_section(self) -> &'static str {
+        match self {
+            Self::Author => "author",
+            Self::Committer => "committer",
+        }
+    }
+}
+
+pub fn author(config: &Config) -> Result<Signature> {
+    signature(config, Role::Author, environment)
+}
+
+pub fn committer(config: &Config) -> Result<Signature> {
+    signature(config, Role::Committer, environment)
+}
+
+/// The committer for reflog entries. Like git, a missing name or email falls back to the login
+/// name instead of failing, since moving a ref doesn't make anything anyone else will see.
+pub fn reflog_committer(config: &Config) -> Result<Signature> {
+    identify(config, Role::Committer, environment, false)
+}
+
+/// Builds a signature from `VC_AUTHOR_NAME`, `VC_AUTHOR_EMAIL` and `VC_AUTHOR_DATE` (or the
+/// `VC_COMMITTER_` ones), which `environment` looks up, then `author.name` or `user.name` style
+/// config and the current time in the local timezone. Errors when there's no name or email.
+pub fn signature(
+    config: &Config,
+    role: Role,
+    environment: impl Fn(&str) -> Option<String>,
+) -> Result<Signature> {
+    identify(config, role, environment, true)
+}
+
+fn identify(
+    config: &Config,
+    role: Role,
+    environment: impl Fn(&str) -> Option<String>,
+    strict: bool,
+) -> Result<Signature> {
+    let prefix = role.environment_prefix();
+    let section = role.config_section();
+    let setting = |field: &str| {
+        environment(&format!("{prefix}_{}", field.to_uppercase()))
+            .or_else(|| config.get(&format!("{section}.{field}")).map(str::to_owned))
+            .or_else(|| config.get(&format!("user.{field}")).map(str::to_owned))
+            .filter(|value| !value.is_empty())
+    };
+    let unknown = || Error::UnknownIdentity(section);
+    let login = || environment("USER").unwrap_or_else(|| "unknown".to_owned());
+    let name = match setting("name") {
+        Some(name) => name,
+        None if strict => return Err(unknown()),
+        None => login(),
+    };
+    let email = match setting("email") {
+        Some(email) => email,
+        None if strict => return Err(unknown()),
+        None => format!("{}@localhost", login()),
+    };
+    let (time, offset_minutes) = match environment(&format!("{prefix}_DATE")) {
+        Some(date) => parse_date(&date)?,
+        None => now(),
+    };
+
+    Ok(Signature::new(&name, &email, time, offset_minutes))
+}
+
+fn environment(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// Seconds since the epoch and the local offset from UTC in minutes, which is what commits
+/// and reflogs record
+pub fn now() -> (i64, i32) {
+    let now = Local::now();
+
+    (now.timestamp(), now.offset().local_minus_utc() / 60)
+}
+
+/// Parses the date formats git accepts in `GIT_AUTHOR_DATE`: its own `<seconds> <+hhmm>`,
+/// `@<seconds>`, RFC 2822 and ISO 8601.
+pub fn parse_date(date: &str) -> Result<(i64, i32)> {
+    let date = date.trim();
+    let invalid = || Error::InvalidDate(date.to_owned());
+    let (seconds, offset) = date.split_once(' ').unwrap_or((date, ""));
+
+    if let Ok(seconds) = seconds.trim_start_matches('@').parse::<i64>() {
+        let offset = if offset.is_empty() {
+            Local
+                .timestamp_opt(seconds, 0)
+                .single()
+                .ok_or_else(invalid)?
+                .offset()
+                .fix()
+        } else {
+            DateTime::parse_from_str(&format!("0 {offset}"), "%s %z")
+                .map_err(|_error| invalid())?
+                .offset()
+                .fix()
+        };
+
+        return Ok((seconds, offset.local_minus_utc() / 60));
+    }
+
+    let parsed = DateTime::parse_from_rfc2822(date)
+        .or_else(|_error| DateTime::parse_from_rfc3339(date))
+        .or_else(|_error| DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z"))
+        .or_else(|_error| DateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%z"));
+
+    let parsed: DateTime<FixedOffset> = match parsed {
+        Ok(parsed) => parsed,
+        // no timezone means local time
+        Err(_) => NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S")
+            .ok()
+            .and_then(|naive| Local.from_local_datetime(&naive).single())
+            .map(|local| local.fixed_offset())
+            .ok_or_else(invalid)?,
+    };
+
+    Ok((parsed.timestamp(), parsed.offset().local_minus_utc() / 60))
+}
+
+/// Seconds since the epoch for the looser dates `log --since` takes: anything `parse_date`
+/// does, a bare `YYYY-MM-DD` meaning local midnight, `now`, `yesterday` and `<n> <unit>s ago`.
+pub fn parse_approximate_date(date: &str) -> Result<i64> {
+    let date = date.trim();
+    let (now, _) = now();
+    let invalid = || Error::InvalidDate(date.to_owned());
+
+    match date {
+        "now" => return Ok(now),
+        "yesterday" => return Ok(now - 24 * 60 * 60),
+        _ => {}
+    }
+
+    // git also takes dots for spaces, like `2.weeks.ago`
+    let spaced = date.replace('.', " ");
+
+    if let Some(ago) = spaced.strip_suffix(" ago") {
+        let (count, unit) = ago.split_once(' ').ok_or_else(invalid)?;
+        let count: i64 = count.parse().map_err(|_error| invalid())?;
+        let seconds = match unit.trim().trim_end_matches('s') {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 60 * 60,
+            "day" => 24 * 60 * 60,
+            "week" => 7 * 24 * 60 * 60,
+            "month" => 30 * 24 * 60 * 60,
+            "year" => 365 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+
+        return Ok(now - count * seconds);
+    }
+
+    if let Ok(day) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        return day
+            .and_hms_opt(0, 0, 0)
+            .and_then(|midnight| Local.from_local_datetime(&midnight).earliest())
+            .map(|midnight| midnight.timestamp())
+            .ok_or_else(invalid);
+    }
+
+    parse_date(date).map(|(seconds, _)| seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Scope;
+
+    #[test]
+    fn should_parse_git_date_formats() -> Result<()> {
+        assert_eq!(parse_date("1700000000 -0700")?, (1700000000, -420));
+        assert_eq!(parse_date("@1700000000 +0530")?, (1700000000, 330));
+        assert_eq!(
+            parse_date("Tue, 14 Nov 2023 22:13:20 +0000")?,
+            (1700000000, 0)
+        );
+        assert_eq!(parse_date("2023-11-14T15:13:20-07:00")?, (1700000000, -420));
+        assert_eq!(parse_date("2023-11-14 23:13:20 +0100")?, (1700000000, 60));
+        assert!(matches!(
+            parse_date("last tuesday"),
+            Err(Error::InvalidDate(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_approximate_dates() -> Result<()> {
+        let (now, _) = now();
+
+        assert!((parse_approximate_date("2 weeks ago")? - (now - 14 * 24 * 60 * 60)).abs() < 5);
+        assert!((parse_approximate_date("3.days.ago")? - (now - 3 * 24 * 60 * 60)).abs() < 5);
+        assert_eq!(parse_approximate_date("@1700000000")?, 1700000000);
+        assert!(parse_approximate_date("2023-11-14")? < 1700000000 + 24 * 60 * 60);
+        assert!(matches!(
+            parse_approximate_date("2 fortnights ago"),
+            Err(Error::InvalidDate(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn should_prefer_role_config_over_user_config() -> Result<()> {
+        let config = Config::parse(
+            "[user]\n\tname = A U Thor\n\temail = author@example.com\n\
+             [committer]\n\tname = C O Mitter\n",
+            Scope::Local,
+        )?;
+        let author = signature(&config, Role::Author, |_| None)?;
+        let committer = signature(&config, Role::Committer, |_| None)?;
+
+        assert_eq!(author.name, "A U Thor");
+        assert_eq!(committer.name, "C O Mitter");
+        assert_eq!(committer.email, "author@example.com");
+        Ok(())
+    }
+
+    #[test]
+    fn should_only_guess_an_identity_for_reflogs() -> Result<()> {
+        let config = Config::parse("[user]\n\tname = A U Thor\n", Scope::Local)?;
+        let environment = |name: &str| (name == "USER").then(|| "login".to_owned());
+
+        assert!(matches!(
+            signature(&config, Role::Author, environment),
+            Err(Error::UnknownIdentity("author"))
+        ));
+
+        let committer = identify(&config, Role::Committer, environment, false)?;
+
+        assert_eq!(committer.name, "A U Thor");
+        assert_eq!(committer.email, "login@localhost");
+        Ok(())
+    }
+}
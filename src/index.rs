@@ -0,0 +1,713 @@
+// The index format is described at https://git-scm.com/docs/index-format
+
+use std::{
+    fs::Metadata,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    error::{Context, Error, Result},
+    hash::Hash,
+    lockfile::LockFile,
+    object_store::ObjectStore,
+    tree::{Tree, TreeObject, TreeObjectType},
+};
+
+const INDEX_FILE: &str = "index";
+const INDEX_SIGNATURE: &[u8; 4] = b"DIRC";
+const HEADER_SIZE: usize = 12;
+const CHECKSUM_SIZE: usize = 20;
+const NAME_LENGTH_MASK: u16 = 0xfff;
+const ASSUME_VALID_FLAG: u16 = 0x8000;
+const EXTENDED_FLAG: u16 = 0x4000;
+const STAGE_SHIFT: u16 = 12;
+const SKIP_WORKTREE_FLAG: u16 = 0x4000;
+const INTENT_TO_ADD_FLAG: u16 = 0x2000;
+
+pub const REGULAR_FILE_MODE: u32 = 0o100644;
+pub const EXECUTABLE_FILE_MODE: u32 = 0o100755;
+pub const SYMLINK_MODE: u32 = 0o120000;
+pub const GITLINK_MODE: u32 = 0o160000;
+const DIRECTORY_MODE: u32 = 0o40000;
+
+/// What the file system said about a file when it was staged. If it still says the same, the
+/// file hasn't changed and doesn't need hashing again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatData {
+    pub ctime_seconds: u32,
+    pub ctime_nanoseconds: u32,
+    pub mtime_seconds: u32,
+    pub mtime_nanoseconds: u32,
+    pub dev: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Truncated to 32 bits, like git
+    pub size: u32,
+}
+
+impl StatData {
+    // the index only has room for 32 bits of each, so everything is truncated like git does
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+
+        Self {
+            ctime_seconds: metadata.ctime() as u32,
+            ctime_nanoseconds: metadata.ctime_nsec() as u32,
+            mtime_seconds: metadata.mtime() as u32,
+            mtime_nanoseconds: metadata.mtime_nsec() as u32,
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.len() as u32,
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+            .unwrap_or_default();
+
+        Self {
+            mtime_seconds: mtime.as_secs() as u32,
+            mtime_nanoseconds: mtime.subsec_nanos(),
+            size: metadata.len() as u32,
+            ..Self::default()
+        }
+    }
+
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        *self == Self::from_metadata(metadata)
+    }
+
+    /// A file modified in the same instant the index was written can look unchanged even
+    /// though it isn't, so its stat data can't be trusted. `index` is the index file's own,
+    /// and without one nothing can be.
+    pub fn is_racy(&self, index: Option<&StatData>) -> bool {
+        index.is_none_or(|index| {
+            (self.mtime_seconds, self.mtime_nanoseconds)
+                >= (index.mtime_seconds, index.mtime_nanoseconds)
+        })
+    }
+}
+
+/// The mode a file is staged with: symlink, executable or regular
+pub fn mode_from_metadata(metadata: &Metadata) -> u32 {
+    if metadata.file_type().is_symlink() {
+        return SYMLINK_MODE;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if metadata.permissions().mode() & 0o100 == 0o100 {
+            return EXECUTABLE_FILE_MODE;
+        }
+    }
+
+    REGULAR_FILE_MODE
+}
+
+/// Trees write modes as octal digits, so 0o100644 is stored as 100644
+pub fn tree_mode(mode: u32) -> u32 {
+    format!("{mode:o}").parse().unwrap_or_default()
+}
+
+/// The opposite of `tree_mode`
+pub fn index_mode(tree_mode: u32) -> u32 {
+    u32::from_str_radix(&tree_mode.to_string(), 8).unwrap_or_default()
+}
+
+/// The mode git stages for a file from a tree, turning legacy modes like 0o100664 or 0o644
+/// into a regular or executable file
+pub fn canonical_mode(mode: u32) -> u32 {
+    match mode & 0o170000 {
+        0o120000 => SYMLINK_MODE,
+        0o160000 => GITLINK_MODE,
+        _ if mode & 0o100 == 0o100 => EXECUTABLE_FILE_MODE,
+        _ => REGULAR_FILE_MODE,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub stat: StatData,
+    /// A real mode like 0o100644, not the digits trees use
+    pub mode: u32,
+    pub hash: Hash,
+    /// 0 normally, 1 to 3 for the base, ours and theirs sides of a conflict
+    pub stage: u8,
+    pub assume_valid: bool,
+    pub skip_worktree: bool,
+    pub intent_to_add: bool,
+    /// Relative to the work tree, with `/` between directories
+    pub path: String,
+}
+
+impl IndexEntry {
+    pub fn new(path: &str, mode: u32, hash: Hash, stat: StatData) -> Self {
+        Self {
+            stat,
+            mode,
+            hash,
+            stage: 0,
+            assume_valid: false,
+            skip_worktree: false,
+            intent_to_add: false,
+            path: path.to_owned(),
+        }
+    }
+
+    fn is_extended(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+}
+
+/// The staging area between the work tree and the next commit, `.vc/index`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Index {
+    /// Sorted by path, then stage
+    entries: Vec<IndexEntry>,
+}
+
+impl Index {
+    pub fn path(git_directory: &Path) -> PathBuf {
+        git_directory.join(INDEX_FILE)
+    }
+
+    /// The stat data of the index file itself, to tell which entries are racy
+    pub fn stat(git_directory: &Path) -> Result<Option<StatData>> {
+        match std::fs::metadata(Self::path(git_directory)) {
+            Ok(metadata) => Ok(Some(StatData::from_metadata(&metadata))),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error).context("reading index stat"),
+        }
+    }
+
+    /// Reads `.vc/index`, or `None` if nothing has been staged yet.
+    pub fn read(git_directory: &Path) -> Result<Option<Self>> {
+        let path = Self::path(git_directory);
+
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let bytes = std::fs::read(&path).context("reading index")?;
+
+        Self::parse(&bytes).context("parsing index").map(Some)
+    }
+
+    /// Replaces `.vc/index` through `index.lock`, the lock git uses too.
+    pub fn write(&self, git_directory: &Path) -> Result<()> {
+        let mut lock = LockFile::acquire(&Self::path(git_directory))?;
+
+        lock.write_all(&self.serialize()).context("writing index")?;
+        lock.commit()
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < HEADER_SIZE + CHECKSUM_SIZE {
+            return Err(Error::corrupt("index is too short"));
+        }
+
+        let (content, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+
+        if Sha1::digest(content).as_slice() != checksum {
+            return Err(Error::corrupt("index checksum doesn't match"));
+        }
+
+        let mut reader = Reader::new(content);
+
+        if reader.take(4)? != INDEX_SIGNATURE {
+            return Err(Error::corrupt("index doesn't start with DIRC"));
+        }
+
+        let version = reader.u32()?;
+
+        // version 4 compresses paths, which we don't support yet
+        if !(2..=3).contains(&version) {
+            return Err(Error::corrupt(format!(
+                "index version {version} isn't supported"
+            )));
+        }
+
+        let count = reader.u32()?;
+        let mut entries = Vec::with_capacity(count as usize);
+
+        for _ in 0..count {
+            entries.push(parse_entry(&mut reader, version)?);
+        }
+
+        while !reader.is_empty() {
+            let signature = reader.take(4)?;
+            let size = reader.u32()? as usize;
+
+            // extensions starting with a capital letter are caches that can be dropped
+            if !signature[0].is_ascii_uppercase() {
+                return Err(Error::corrupt(format!(
+                    "index extension {} isn't supported",
+                    String::from_utf8_lossy(signature)
+                )));
+            }
+
+            reader.take(size)?;
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Version 2, or 3 when an entry has flags that need it
+    pub fn serialize(&self) -> Vec<u8> {
+        let version: u32 = if self.entries.iter().any(IndexEntry::is_extended) {
+            3
+        } else {
+            2
+        };
+        let mut bytes = INDEX_SIGNATURE.to_vec();
+
+        bytes.extend(version.to_be_bytes());
+        bytes.extend((self.entries.len() as u32).to_be_bytes());
+
+        for entry in &self.entries {
+            serialize_entry(entry, &mut bytes);
+        }
+
+        let checksum = Sha1::digest(&bytes);
+
+        bytes.extend(checksum);
+
+        bytes
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// The staged, unconflicted entry for `path`
+    pub fn entry(&self, path: &str) -> Option<&IndexEntry> {
+        self.position(path, 0)
+            .ok()
+            .map(|index| &self.entries[index])
+    }
+
+    fn position(&self, path: &str, stage: u8) -> std::result::Result<usize, usize> {
+        self.entries.binary_search_by(|entry| {
+            entry
+                .path
+                .as_bytes()
+                .cmp(path.as_bytes())
+                .then(entry.stage.cmp(&stage))
+        })
+    }
+
+    /// Stages `entry`, replacing whatever was at its path, including conflicts, and anything
+    /// that was in the way as a file or directory.
+    pub fn add(&mut self, entry: IndexEntry) {
+        let directory = format!("{}/", entry.path);
+
+        self.entries.retain(|existing| {
+            existing.path != entry.path
+                && !existing.path.starts_with(&directory)
+                && !entry
+                    .path
+                    .strip_prefix(&existing.path)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        });
+
+        let index = self
+            .position(&entry.path, entry.stage)
+            .unwrap_or_else(|index| index);
+
+        self.entries.insert(index, entry);
+    }
+
+    /// Unstages `path` at every stage, returning whether it was staged.
+    pub fn remove(&mut self, path: &str) -> bool {
+        let length = self.entries.len();
+
+        self.entries.retain(|entry| entry.path != path);
+
+        self.entries.len() != length
+    }
+
+    /// An index holding everything in `tree`, with no stat data, like after `read-tree`
+    pub fn from_tree(objects: &impl ObjectStore, tree: &Hash) -> Result<Self> {
+        let mut index = Self::default();
+
+        add_tree(&mut index, objects, tree, "")?;
+        // trees sort directories as if they ended in a slash, the index sorts plain bytes
+        index
+            .entries
+            .sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+
+        Ok(index)
+    }
+
+    /// Writes the staged content as trees, returning the root tree, like `git write-tree`.
+    pub fn write_tree(&self, objects: &mut impl ObjectStore) -> Result<Hash> {
+        if let Some(entry) = self.entries.iter().find(|entry| entry.stage != 0) {
+            return Err(Error::Unmerged(entry.path.clone()));
+        }
+
+        // intent to add only reserves the path, there's nothing to commit yet
+        let entries = self
+            .entries
+            .iter()
+            .filter(|entry| !entry.intent_to_add)
+            .collect::<Vec<_>>();
+
+        write_tree_level(&entries, 0, objects)
+    }
+}
+
+fn add_tree(
+    index: &mut Index,
+    objects: &impl ObjectStore,
+    tree: &Hash,
+    prefix: &str,
+) -> Result<()> {
+    for tree_object in objects.read_tree(tree)?.tree_objects {
+        let name = std::str::from_utf8(&tree_object.filename)
+            .with_context(|| format!("reading a filename in {prefix}"))?;
+        let path = format!("{prefix}{name}");
+
+        match tree_object.object_type {
+            TreeObjectType::Tree => {
+                add_tree(index, objects, &tree_object.checksum, &format!("{path}/"))?
+            }
+            TreeObjectType::Blob | TreeObjectType::Commit => index.entries.push(IndexEntry::new(
+                &path,
+                canonical_mode(index_mode(tree_object.mode())),
+                tree_object.checksum,
+                StatData::default(),
+            )),
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the tree for entries that all start with the same `prefix_length` bytes of path.
+fn write_tree_level(
+    entries: &[&IndexEntry],
+    prefix_length: usize,
+    objects: &mut impl ObjectStore,
+) -> Result<Hash> {
+    let mut tree_objects = vec![];
+    let mut index = 0;
+
+    while index < entries.len() {
+        let name = &entries[index].path[prefix_length..];
+
+        match name.split_once('/') {
+            Some((directory, _rest)) => {
+                let directory_prefix = &entries[index].path[..prefix_length + directory.len() + 1];
+                let count = entries[index..]
+                    .iter()
+                    .take_while(|entry| entry.path.starts_with(directory_prefix))
+                    .count();
+                let hash = write_tree_level(
+                    &entries[index..index + count],
+                    directory_prefix.len(),
+                    objects,
+                )?;
+
+                tree_objects.push(TreeObject::new(
+                    tree_mode(DIRECTORY_MODE),
+                    directory.to_owned(),
+                    hash,
+                )?);
+                index += count;
+            }
+            None => {
+                let entry = entries[index];
+
+                tree_objects.push(TreeObject::new(
+                    tree_mode(entry.mode),
+                    name.to_owned(),
+                    entry.hash.clone(),
+                )?);
+                index += 1;
+            }
+        }
+    }
+
+    objects.write("tree", &Tree::new(tree_objects).serialize())
+}
+
+fn parse_entry(reader: &mut Reader, version: u32) -> Result<IndexEntry> {
+    let start = reader.offset;
+    let stat = StatData {
+        ctime_seconds: reader.u32()?,
+        ctime_nanoseconds: reader.u32()?,
+        mtime_seconds: reader.u32()?,
+        mtime_nanoseconds: reader.u32()?,
+        dev: reader.u32()?,
+        ino: reader.u32()?,
+        ..StatData::default()
+    };
+    let mode = reader.u32()?;
+    let stat = StatData {
+        uid: reader.u32()?,
+        gid: reader.u32()?,
+        size: reader.u32()?,
+        ..stat
+    };
+    let hash = Hash::try_from(reader.take(20)?.to_vec())?;
+    let flags = reader.u16()?;
+    let extended_flags = if flags & EXTENDED_FLAG != 0 {
+        if version < 3 {
+            return Err(Error::corrupt("extended index flags need version 3"));
+        }
+
+        reader.u16()?
+    } else {
+        0
+    };
+    let name_length = match flags & NAME_LENGTH_MASK {
+        // names too long for the flags run up to the padding instead
+        NAME_LENGTH_MASK => reader
+            .remaining()
+            .iter()
+            .position(|byte| *byte == 0)
+            .context("index entry name isn't terminated")?,
+        length => length as usize,
+    };
+    let path = String::from_utf8(reader.take(name_length)?.to_vec())?;
+    let entry_length = reader.offset - start;
+
+    reader.take(padded_length(entry_length) - entry_length)?;
+
+    Ok(IndexEntry {
+        stat,
+        mode,
+        hash,
+        stage: ((flags >> STAGE_SHIFT) & 0b11) as u8,
+        assume_valid: flags & ASSUME_VALID_FLAG != 0,
+        skip_worktree: extended_flags & SKIP_WORKTREE_FLAG != 0,
+        intent_to_add: extended_flags & INTENT_TO_ADD_FLAG != 0,
+        path,
+    })
+}
+
+fn serialize_entry(entry: &IndexEntry, bytes: &mut Vec<u8>) {
+    let start = bytes.len();
+    let stat = &entry.stat;
+
+    for field in [
+        stat.ctime_seconds,
+        stat.ctime_nanoseconds,
+        stat.mtime_seconds,
+        stat.mtime_nanoseconds,
+        stat.dev,
+        stat.ino,
+        entry.mode,
+        stat.uid,
+        stat.gid,
+        stat.size,
+    ] {
+        bytes.extend(field.to_be_bytes());
+    }
+
+    bytes.extend(entry.hash.iter());
+
+    let mut flags = (entry.path.len().min(NAME_LENGTH_MASK as usize) as u16)
+        | (u16::from(entry.stage & 0b11) << STAGE_SHIFT);
+
+    if entry.assume_valid {
+        flags |= ASSUME_VALID_FLAG;
+    }
+
+    if entry.is_extended() {
+        flags |= EXTENDED_FLAG;
+    }
+
+    bytes.extend(flags.to_be_bytes());
+
+    if entry.is_extended() {
+        let mut extended_flags = 0;
+
+        if entry.skip_worktree {
+            extended_flags |= SKIP_WORKTREE_FLAG;
+        }
+
+        if entry.intent_to_add {
+            extended_flags |= INTENT_TO_ADD_FLAG;
+        }
+
+        bytes.extend(u16::to_be_bytes(extended_flags));
+    }
+
+    bytes.extend(entry.path.as_bytes());
+
+    let entry_length = bytes.len() - start;
+
+    bytes.resize(start + padded_length(entry_length), 0);
+}
+
+/// Entries are padded with one to eight NULs to a multiple of eight bytes
+fn padded_length(length: usize) -> usize {
+    (length + 8) & !7
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.offset >= self.bytes.len()
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.bytes[self.offset.min(self.bytes.len())..]
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + length)
+            .context("index ends early")?;
+
+        self.offset += length;
+
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::MemoryObjectStore;
+
+    fn entry(path: &str, byte: u8) -> IndexEntry {
+        let stat = StatData {
+            mtime_seconds: 1700000000,
+            size: 3,
+            ..StatData::default()
+        };
+
+        IndexEntry::new(path, REGULAR_FILE_MODE, Hash::new([byte; 20]), stat)
+    }
+
+    #[test]
+    fn should_round_trip_versions_two_and_three() -> Result<()> {
+        let mut index = Index::default();
+
+        index.add(entry("src/main.rs", 1));
+        index.add(entry("README.md", 2));
+        index.add(entry(&"x".repeat(5000), 3));
+
+        let bytes = index.serialize();
+
+        assert_eq!(&bytes[4..8], &2u32.to_be_bytes());
+        assert_eq!(Index::parse(&bytes)?, index);
+        assert_eq!(
+            index
+                .entries()
+                .iter()
+                .map(|entry| entry.path.len())
+                .collect::<Vec<_>>(),
+            vec![9, 11, 5000]
+        );
+
+        let mut intent_to_add = entry("new.txt", 4);
+
+        intent_to_add.intent_to_add = true;
+        index.add(intent_to_add);
+
+        let bytes = index.serialize();
+
+        assert_eq!(&bytes[4..8], &3u32.to_be_bytes());
+        assert_eq!(Index::parse(&bytes)?, index);
+        Ok(())
+    }
+
+    #[test]
+    fn should_distrust_stat_data_as_new_as_the_index() {
+        let stat = |mtime_seconds| StatData {
+            mtime_seconds,
+            ..StatData::default()
+        };
+
+        assert!(!stat(9).is_racy(Some(&stat(10))));
+        assert!(stat(10).is_racy(Some(&stat(10))));
+        assert!(stat(9).is_racy(None));
+    }
+
+    #[test]
+    fn should_reject_bad_checksums() {
+        let mut bytes = Index::default().serialize();
+        let last = bytes.len() - 1;
+
+        bytes[last] ^= 1;
+
+        assert!(Index::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn should_replace_files_and_directories_in_the_way() {
+        let mut index = Index::default();
+
+        index.add(entry("a/b", 1));
+        index.add(entry("a/c", 2));
+        index.add(entry("a", 3));
+
+        assert_eq!(index.entries().len(), 1);
+
+        index.add(entry("a/d/e", 4));
+
+        assert_eq!(index.entries().len(), 1);
+        assert_eq!(
+            index.entry("a/d/e").map(|entry| entry.hash.clone()),
+            Some(Hash::new([4; 20]))
+        );
+        assert!(index.remove("a/d/e"));
+        assert!(!index.remove("a/d/e"));
+    }
+
+    #[test]
+    fn should_write_trees_from_entries() -> Result<()> {
+        let mut objects = MemoryObjectStore::new();
+        let blob = objects.write("blob", b"hi\n")?;
+        let mut index = Index::default();
+
+        for path in ["a-b", "a/x", "a/y/z", "b"] {
+            index.add(IndexEntry::new(
+                path,
+                REGULAR_FILE_MODE,
+                blob.clone(),
+                StatData::default(),
+            ));
+        }
+
+        let root = index.write_tree(&mut objects)?;
+        let tree = objects.read_tree(&root)?;
+
+        assert_eq!(tree.filenames(), vec!["a-b", "a", "b"]);
+        assert_eq!(Index::from_tree(&objects, &root)?, index);
+        Ok(())
+    }
+}
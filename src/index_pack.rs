@@ -0,0 +1,248 @@
+use crate::error::{Context, Error, Result};
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    hash::Hash,
+    pack::{DeltaBase, PackReader, PACK_CHECKSUM_SIZE, PACK_HEADER_SIZE},
+    pack_index::{PackIndex, PackIndexEntry},
+    utils::{add_header, get_hash},
+};
+
+#[derive(Debug)]
+pub struct IndexedPack {
+    pub pack_path: PathBuf,
+    pub index_path: PathBuf,
+}
+
+/// Builds a `.idx` for the packfile at `pack_path`, the way `git index-pack` does.
+///
+/// The pack is renamed to `pack-<checksum>.pack` next to where it was, with the index
+/// written alongside it.
+pub fn index_pack(pack_path: &Path) -> Result<IndexedPack> {
+    let pack_checksum = verify_pack_checksum(pack_path).context("verifying pack checksum")?;
+    let file = File::open(pack_path).context("opening packfile")?;
+    let pack_length = file.metadata()?.len();
+    let mut reader = PackReader::new(BufReader::new(file));
+    let index =
+        build_index(&mut reader, pack_length, pack_checksum).context("building pack index")?;
+    let directory = pack_path
+        .parent()
+        .context("getting directory of packfile")?;
+    let name = format!("pack-{}", index.pack_checksum());
+    let indexed_pack = IndexedPack {
+        pack_path: directory.join(format!("{name}.pack")),
+        index_path: directory.join(format!("{name}.idx")),
+    };
+
+    index
+        .write(&indexed_pack.index_path)
+        .context("writing pack index")?;
+    std::fs::rename(pack_path, &indexed_pack.pack_path).context("renaming packfile")?;
+
+    Ok(indexed_pack)
+}
+
+/// The last 20 bytes of a pack are the SHA-1 of everything before them.
+fn verify_pack_checksum(pack_path: &Path) -> Result<Hash> {
+    let mut file = File::open(pack_path)?;
+    let pack_length = file.metadata()?.len();
+    let content_length = pack_length
+        .checked_sub(PACK_CHECKSUM_SIZE)
+        .filter(|&length| length >= PACK_HEADER_SIZE)
+        .context("packfile is too short")?;
+    let mut hasher = Sha1::new();
+    let mut remaining = content_length;
+    let mut buffer = vec![0; 64 * 1024];
+
+    while remaining > 0 {
+        let chunk_length = remaining.min(buffer.len() as u64) as usize;
+
+        file.read_exact(&mut buffer[..chunk_length])?;
+        hasher.update(&buffer[..chunk_length]);
+        remaining -= chunk_length as u64;
+    }
+
+    let mut expected = [0; 20];
+
+    file.read_exact(&mut expected)?;
+
+    let actual: [u8; 20] = hasher.finalize().into();
+
+    if actual != expected {
+        return Err(Error::corrupt(format!(
+            "pack checksum mismatch, expected {} but got {}",
+            hex::encode(expected),
+            hex::encode(actual)
+        )));
+    }
+
+    Ok(Hash::new(actual))
+}
+
+/// Walks every entry in the pack, hashing whole objects as they come and resolving deltas
+/// once their bases are known, so only one object chain is in memory at a time.
+pub fn build_index<R: BufRead + Seek>(
+    reader: &mut PackReader<R>,
+    pack_length: u64,
+    pack_checksum: Hash,
+) -> Result<PackIndex> {
+    let header = reader.read_header().context("reading pack header")?;
+    let mut offset = PACK_HEADER_SIZE;
+    let mut crcs = HashMap::new();
+    let mut hashes: HashMap<Hash, u64> = HashMap::new();
+    let mut resolved = vec![];
+    let mut waiting: HashMap<DeltaBase, Vec<u64>> = HashMap::new();
+
+    for _ in 0..header.object_count {
+        let entry = reader
+            .read_entry(offset)
+            .with_context(|| format!("reading pack entry at offset {offset}"))?;
+
+        crcs.insert(offset, entry.crc32);
+
+        match entry.base {
+            None => {
+                let object = add_header(entry.object_type.get_type(), &entry.data);
+
+                hashes.insert(get_hash(&object)?, offset);
+                resolved.push(offset);
+            }
+            Some(base) => waiting.entry(base).or_default().push(offset),
+        }
+
+        offset = entry.end;
+    }
+
+    if offset + PACK_CHECKSUM_SIZE != pack_length {
+        return Err(Error::corrupt(
+            "packfile has unexpected data after its last object",
+        ));
+    }
+
+    let mut hashes_by_offset: HashMap<u64, Hash> = hashes
+        .iter()
+        .map(|(hash, &offset)| (offset, hash.clone()))
+        .collect();
+
+    // every delta hangs off either an offset or a hash, so walk outwards from the whole
+    // objects, resolving each delta as soon as its base has been resolved
+    while let Some(base_offset) = resolved.pop() {
+        let base_hash = hashes_by_offset[&base_offset].clone();
+        let children = [DeltaBase::Offset(base_offset), DeltaBase::Hash(base_hash)]
+            .into_iter()
+            .filter_map(|base| waiting.remove(&base))
+            .flatten()
+            .collect::<Vec<_>>();
+
+        for child_offset in children {
+            let (object_type, data) = reader
+                .read_object(child_offset, |hash| hashes.get(hash).copied())
+                .with_context(|| format!("resolving delta at offset {child_offset}"))?;
+            let hash = get_hash(&add_header(object_type, &data))?;
+
+            hashes.insert(hash.clone(), child_offset);
+            hashes_by_offset.insert(child_offset, hash);
+            resolved.push(child_offset);
+        }
+    }
+
+    let unresolved = waiting.values().map(Vec::len).sum::<usize>();
+
+    if unresolved > 0 {
+        return Err(Error::corrupt(format!(
+            "{unresolved} deltas reference base objects missing from the packfile"
+        )));
+    }
+
+    let entries = hashes
+        .into_iter()
+        .map(|(hash, offset)| PackIndexEntry {
+            hash,
+            crc32: crcs[&offset],
+            offset,
+        })
+        .collect();
+
+    Ok(PackIndex::new(entries, pack_checksum))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    fn compress(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // type and size header for sizes below 16
+    fn entry(object_type: u8, data: &[u8], base: &[u8]) -> Vec<u8> {
+        let mut entry = vec![(object_type << 4) | data.len() as u8];
+
+        entry.extend_from_slice(base);
+        entry.extend(compress(data));
+        entry
+    }
+
+    fn build_pack(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut pack = b"PACK".to_vec();
+
+        pack.extend_from_slice(&2_u32.to_be_bytes());
+        pack.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        entries.iter().for_each(|entry| pack.extend(entry));
+
+        let checksum = get_hash(&pack).unwrap();
+
+        pack.extend_from_slice(checksum.as_ref());
+        pack
+    }
+
+    #[test]
+    fn should_index_whole_objects_and_deltas() -> Result<()> {
+        let blob = entry(3, b"hello world", &[]);
+        let blob_hash = get_hash(&add_header("blob", b"hello world"))?;
+        // copy "hello" from the base then insert "!"
+        let delta = [11, 6, 0b1001_0000, 5, 1, b'!'];
+        let ofs_delta = entry(6, &delta, &[blob.len() as u8]);
+        // the ref delta comes first, so it has to wait for its base. It copies "world"
+        let ref_delta = entry(7, &[11, 5, 0b1001_0001, 6, 5], blob_hash.as_ref());
+        let ref_delta_length = ref_delta.len() as u64;
+        let pack = build_pack(&[ref_delta, blob, ofs_delta]);
+        let pack_length = pack.len() as u64;
+        let mut reader = PackReader::new(Cursor::new(pack));
+        let index = build_index(&mut reader, pack_length, Hash::default())?;
+        let ofs_delta_hash = get_hash(&add_header("blob", b"hello!"))?;
+        let ref_delta_hash = get_hash(&add_header("blob", b"world"))?;
+
+        assert_eq!(index.entries().len(), 3);
+        assert_eq!(index.find_offset(&ref_delta_hash), Some(PACK_HEADER_SIZE));
+        assert_eq!(
+            index.find_offset(&blob_hash),
+            Some(PACK_HEADER_SIZE + ref_delta_length)
+        );
+        assert!(index.find_offset(&ofs_delta_hash).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn should_fail_on_missing_delta_base() {
+        let delta = [11, 6, 0b1001_0000, 5, 1, b'!'];
+        let pack = build_pack(&[entry(7, &delta, &[7; 20])]);
+        let pack_length = pack.len() as u64;
+        let mut reader = PackReader::new(Cursor::new(pack));
+
+        assert!(build_index(&mut reader, pack_length, Hash::default()).is_err());
+    }
+}
@@ -1,19 +1,40 @@
+use crate::config::{Config, ConfigFile};
+use crate::error::{Context, Result};
+use crate::refs::check_ref_name;
 use std::{fs, path::PathBuf};
 
-pub fn init(mut path: PathBuf) {
+const DEFAULT_BRANCH: &str = "master";
+
+/// Creates an empty repository in `path`, returning its `.vc` directory. HEAD points at the
+/// branch named by `init.defaultBranch` in the global config, `master` otherwise.
+pub fn init(mut path: PathBuf) -> Result<PathBuf> {
+    let config = Config::load(None)?;
+    let branch = format!(
+        "refs/heads/{}",
+        config.get("init.defaultBranch").unwrap_or(DEFAULT_BRANCH)
+    );
+
+    check_ref_name(&branch).context("checking init.defaultBranch")?;
+
     path.push(".vc");
-    fs::create_dir_all(&path).expect("error creating .vc directory");
+    fs::create_dir_all(&path).context("creating .vc directory")?;
 
     let mut objects = path.clone();
     objects.push("objects");
-    fs::create_dir(&objects).expect("error creating objects directory");
+    fs::create_dir(&objects).context("creating objects directory")?;
 
     let mut refs = path.clone();
     refs.push("refs");
-    fs::create_dir(&refs).expect("error creating refs directory");
+    fs::create_dir(&refs).context("creating refs directory")?;
 
     let mut head = path.clone();
     head.push("HEAD");
-    fs::write(&head, "ref: refs/heads/master\n").expect("error writing head info to refs");
-    println!("Initialized vc directory");
+    fs::write(&head, format!("ref: {branch}\n")).context("writing HEAD")?;
+
+    let config_file = ConfigFile::new(path.join("config"));
+
+    config_file.set("core.repositoryformatversion", "0")?;
+    config_file.set("core.bare", "false")?;
+
+    Ok(path)
 }
@@ -1,12 +1,41 @@
+pub mod add;
+pub mod branch;
 pub mod cat_file;
 pub mod checkout;
 pub mod clone;
+pub mod commit;
 pub mod commit_tree;
+pub mod config;
+pub mod error;
 pub mod hash;
 pub mod hash_object;
+pub mod identity;
+pub mod index;
+pub mod index_pack;
 pub mod init;
+pub mod lockfile;
+pub mod log;
 pub mod ls_tree;
+pub mod mv;
+pub mod object;
+pub mod object_store;
+pub mod pack;
+pub mod pack_index;
 pub mod process_packfile;
+pub mod ref_transaction;
+pub mod reflog;
+pub mod refs;
+pub mod repack;
+pub mod repository;
+pub mod rev_list;
+pub mod rev_parse;
+pub mod rev_walk;
+pub mod revision;
+pub mod rm;
+pub mod status;
+#[cfg(test)]
+mod test_utils;
 pub mod tree;
+pub mod update_ref;
 pub mod utils;
 pub mod write_tree;
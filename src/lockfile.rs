@@ -0,0 +1,115 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::error::{Context, Error, Result};
+
+/// Replaces a file by writing `<path>.lock` and renaming it over the original, the way git
+/// does. Only one writer can hold the lock at a time, and readers never see a half written
+/// file. The lock is released without touching the original if it's dropped before `commit`.
+pub struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: File,
+    committed: bool,
+}
+
+impl LockFile {
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let mut lock_path = path.as_os_str().to_owned();
+
+        lock_path.push(".lock");
+
+        let lock_path = PathBuf::from(lock_path);
+
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory).context("creating directory for lock file")?;
+        }
+
+        let file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                return Err(Error::Locked(lock_path))
+            }
+            Err(error) => return Err(error).context("creating lock file"),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            lock_path,
+            file,
+            committed: false,
+        })
+    }
+
+    /// The file that will be replaced
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the new content into place and releases the lock.
+    pub fn commit(mut self) -> Result<()> {
+        self.file.sync_all().context("syncing lock file")?;
+        std::fs::rename(&self.lock_path, &self.path)
+            .with_context(|| format!("renaming lock file over {}", self.path.display()))?;
+        self.committed = true;
+
+        Ok(())
+    }
+}
+
+impl Write for LockFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_directory;
+
+    #[test]
+    fn should_only_replace_file_on_commit() -> Result<()> {
+        let directory = temp_directory("lockfile");
+        let path = directory.join("HEAD");
+
+        std::fs::create_dir_all(&directory)?;
+        std::fs::write(&path, "old")?;
+
+        let mut lock = LockFile::acquire(&path)?;
+
+        lock.write_all(b"new")?;
+
+        assert!(matches!(LockFile::acquire(&path), Err(Error::Locked(_))));
+        assert_eq!(std::fs::read_to_string(&path)?, "old");
+
+        lock.commit()?;
+
+        assert_eq!(std::fs::read_to_string(&path)?, "new");
+
+        drop(LockFile::acquire(&path)?);
+
+        assert_eq!(std::fs::read_to_string(&path)?, "new");
+        assert!(!directory.join("HEAD.lock").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,786 @@
+use std::{
+    collections::{BinaryHeap, HashMap, HashSet},
+    io::Write,
+    path::PathBuf,
+};
+
+use chrono::{FixedOffset, TimeZone};
+use regex::Regex;
+
+use crate::{
+    error::{Error, Result},
+    hash::Hash,
+    identity,
+    object::{Commit, Signature},
+    object_store::ObjectStore,
+    repository::Repository,
+    revision::{ancestors, peel, Tip},
+};
+
+/// The order commits come out in
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LogOrder {
+    /// Newest commit date first, as the walk reaches them
+    #[default]
+    Default,
+    /// Newest commit date first, but never a parent before all of its children
+    Date,
+    /// Like `Date`, by author date
+    AuthorDate,
+    /// Never a parent before all of its children, keeping each line of history together
+    Topo,
+}
+
+/// How each commit is shown, like `--format`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    Oneline,
+    Short,
+    #[default]
+    Medium,
+    Full,
+    /// `%H`, `%an`, `%s` and the other placeholders git has, one commit per line
+    Placeholders(String),
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct LogOptions {
+    pub order: LogOrder,
+    pub format: LogFormat,
+    /// Stop after this many commits, like `-n`
+    pub max_count: Option<usize>,
+    /// Only commits made at or after this many seconds since the epoch
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    /// A pattern the author's `Name <email>` has to match
+    pub author: Option<String>,
+    /// A pattern the message has to match
+    pub grep: Option<String>,
+    pub graph: bool,
+    /// Only commits changing these paths, relative to the current directory
+    pub paths: Vec<PathBuf>,
+}
+
+/// A commit that's shown, with its parents rewritten to skip the ones that aren't
+struct Node {
+    commit: Commit,
+    parents: Vec<Hash>,
+}
+
+/// Shows the history reachable from `tips`, like `git log`, leaving out what the excluded ones
+/// reach. Paths limit it to the commits that changed them, and a merge whose paths match one
+/// parent only follows that parent, the way git simplifies history by default.
+pub fn log(
+    repository: &Repository,
+    tips: &[Tip],
+    options: &LogOptions,
+    output: &mut impl Write,
+) -> Result<()> {
+    let paths = options
+        .paths
+        .iter()
+        .map(|path| repository.index_path(path))
+        .collect::<Result<Vec<_>>>()?;
+    let filter = Filter {
+        since: options.since,
+        until: options.until,
+        author: options.author.as_deref().map(Regex::new).transpose()?,
+        grep: options.grep.as_deref().map(Regex::new).transpose()?,
+        paths,
+    };
+    let objects = repository.objects();
+    let mut included = vec![];
+    let mut excluded = HashSet::new();
+
+    for tip in tips {
+        let hash = peel(objects, tip.hash.clone(), "commit")?;
+
+        if tip.excluded {
+            excluded.extend(ancestors(objects, &hash)?.into_keys());
+        } else {
+            included.push(hash);
+        }
+    }
+
+    let (nodes, tips) = walk(objects, &included, &excluded, &filter)?;
+    // like git, the graph only makes sense when children come before their parents
+    let order = match options.order {
+        LogOrder::Default if options.graph => LogOrder::Topo,
+        order => order,
+    };
+    let mut commits = sort(&nodes, &tips, order);
+
+    if let Some(max_count) = options.max_count {
+        commits.truncate(max_count);
+    }
+
+    let mut graph = options.graph.then(Graph::default);
+    let separated = !matches!(
+        options.format,
+        LogFormat::Oneline | LogFormat::Placeholders(_)
+    );
+
+    for (position, hash) in commits.iter().enumerate() {
+        let node = &nodes[*hash];
+        let text = format_commit(hash, &node.commit, &options.format);
+        let separator = separated && position + 1 < commits.len();
+
+        let Some(graph) = &mut graph else {
+            writeln!(output, "{text}")?;
+
+            if separator {
+                writeln!(output)?;
+            }
+
+            continue;
+        };
+
+        let (lines, padding) = graph.next(hash, &node.parents);
+        let text = text.split('\n').collect::<Vec<_>>();
+        let width = lines
+            .iter()
+            .map(String::len)
+            .chain([padding.len()])
+            .max()
+            .unwrap_or_default();
+
+        for line in 0..text.len().max(lines.len()) {
+            let graph = lines.get(line).unwrap_or(&padding);
+            let text = text.get(line).unwrap_or(&"");
+
+            writeln!(output, "{graph:<width$}{text}")?;
+        }
+
+        // the blank line between commits is drawn with the columns as they are now
+        if separator {
+            writeln!(output, "{padding}")?;
+        }
+    }
+
+    Ok(())
+}
+
+struct Filter {
+    since: Option<i64>,
+    until: Option<i64>,
+    author: Option<Regex>,
+    grep: Option<Regex>,
+    /// Index paths, the empty path being everything
+    paths: Vec<String>,
+}
+
+impl Filter {
+    fn matches(&self, commit: &Commit) -> bool {
+        let time = commit.committer.time;
+        let author = format!("{} <{}>", commit.author.name, commit.author.email);
+
+        self.since.is_none_or(|since| time >= since)
+            && self.until.is_none_or(|until| time <= until)
+            && self
+                .author
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(&author))
+            && self
+                .grep
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(&String::from_utf8_lossy(&commit.message)))
+    }
+
+    /// What `paths` are in `tree`, to tell whether two commits differ in them
+    fn snapshot(&self, objects: &impl ObjectStore, tree: &Hash) -> Result<Vec<Option<Hash>>> {
+        self.paths
+            .iter()
+            .map(|path| match path.as_str() {
+                "" => Ok(Some(tree.clone())),
+                path => Ok(objects
+                    .read_tree_entry(tree, path)?
+                    .map(|entry| entry.checksum)),
+            })
+            .collect()
+    }
+}
+
+/// Reads every commit reachable from `tips`, returning the ones to show with their parents
+/// rewritten to the nearest shown ancestors, and the tips rewritten the same way. Nothing in
+/// `excluded` is walked.
+fn walk(
+    objects: &impl ObjectStore,
+    tips: &[Hash],
+    excluded: &HashSet<Hash>,
+    filter: &Filter,
+) -> Result<(HashMap<Hash, Node>, Vec<Hash>)> {
+    // commits followed, with the parents followed from them and whether they're shown
+    let mut walked = HashMap::<Hash, (Commit, Vec<Hash>, bool)>::new();
+    let mut pending = tips.to_vec();
+
+    while let Some(hash) = pending.pop() {
+        if walked.contains_key(&hash) || excluded.contains(&hash) {
+            continue;
+        }
+
+        let commit = objects.read_commit(&hash)?;
+        let (followed, changed) = simplify(objects, &commit, filter)?;
+        let shown = changed && filter.matches(&commit);
+
+        pending.extend(followed.iter().cloned());
+        walked.insert(hash, (commit, followed, shown));
+    }
+
+    let rewrite = |hashes: &[Hash]| {
+        let mut rewritten = vec![];
+        let mut seen = HashSet::new();
+        let mut pending = hashes.iter().rev().cloned().collect::<Vec<_>>();
+
+        while let Some(hash) = pending.pop() {
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+
+            match walked.get(&hash) {
+                Some((_, _, true)) => rewritten.push(hash),
+                Some((_, followed, false)) => pending.extend(followed.iter().rev().cloned()),
+                None => {}
+            }
+        }
+
+        rewritten
+    };
+    let nodes = walked
+        .iter()
+        .filter(|(_, (_, _, shown))| *shown)
+        .map(|(hash, (commit, followed, _))| {
+            let node = Node {
+                commit: commit.clone(),
+                parents: rewrite(followed),
+            };
+
+            (hash.clone(), node)
+        })
+        .collect();
+    let tips = rewrite(tips);
+
+    Ok((nodes, tips))
+}
+
+/// Which parents of `commit` to follow, and whether it changed the filtered paths. A commit
+/// with a parent the paths are the same in only follows that parent.
+fn simplify(
+    objects: &impl ObjectStore,
+    commit: &Commit,
+    filter: &Filter,
+) -> Result<(Vec<Hash>, bool)> {
+    if filter.paths.is_empty() {
+        return Ok((commit.parents.clone(), true));
+    }
+
+    let snapshot = filter.snapshot(objects, &commit.tree)?;
+
+    if commit.parents.is_empty() {
+        return Ok((vec![], snapshot.iter().any(Option::is_some)));
+    }
+
+    for parent in &commit.parents {
+        let tree = objects.read_commit(parent)?.tree;
+
+        if filter.snapshot(objects, &tree)? == snapshot {
+            return Ok((vec![parent.clone()], false));
+        }
+    }
+
+    Ok((commit.parents.clone(), true))
+}
+
+fn sort<'a>(nodes: &'a HashMap<Hash, Node>, tips: &'a [Hash], order: LogOrder) -> Vec<&'a Hash> {
+    let mut sorted = vec![];
+    let mut queue = BinaryHeap::new();
+    let mut sequence = 0;
+    let key = |hash: &Hash| {
+        let commit = &nodes[hash].commit;
+
+        match order {
+            LogOrder::AuthorDate => commit.author.time,
+            LogOrder::Topo => 0,
+            LogOrder::Default | LogOrder::Date => commit.committer.time,
+        }
+    };
+    let mut push = |queue: &mut BinaryHeap<_>, hash: &'a Hash| {
+        // commits found first go first when dates tie, but topological order is a stack so
+        // the parent found last, and its line of history, is shown next
+        sequence += 1;
+
+        let tie_break = if order == LogOrder::Topo {
+            sequence
+        } else {
+            -sequence
+        };
+
+        queue.push((key(hash), tie_break, hash));
+    };
+
+    if order == LogOrder::Default {
+        let mut seen = HashSet::new();
+
+        for tip in tips {
+            if seen.insert(tip) {
+                push(&mut queue, tip);
+            }
+        }
+
+        while let Some((_, _, hash)) = queue.pop() {
+            sorted.push(hash);
+
+            for parent in &nodes[hash].parents {
+                if seen.insert(parent) {
+                    push(&mut queue, parent);
+                }
+            }
+        }
+
+        return sorted;
+    }
+
+    let mut children = HashMap::<&Hash, usize>::new();
+
+    for node in nodes.values() {
+        for parent in &node.parents {
+            *children.entry(parent).or_default() += 1;
+        }
+    }
+
+    let mut seen = HashSet::new();
+
+    for tip in tips {
+        if !children.contains_key(tip) && seen.insert(tip) {
+            push(&mut queue, tip);
+        }
+    }
+
+    while let Some((_, _, hash)) = queue.pop() {
+        sorted.push(hash);
+
+        for parent in &nodes[hash].parents {
+            let remaining = children.entry(parent).or_default();
+
+            *remaining -= 1;
+
+            if *remaining == 0 {
+                push(&mut queue, parent);
+            }
+        }
+    }
+
+    sorted
+}
+
+fn format_commit(hash: &Hash, commit: &Commit, format: &LogFormat) -> String {
+    let abbreviated = |hash: &Hash| hash.to_string()[..7].to_owned();
+    let message = String::from_utf8_lossy(&commit.message);
+    let mut text = match format {
+        LogFormat::Oneline => return format!("{hash} {}", subject(&message)),
+        LogFormat::Placeholders(placeholders) => return expand(placeholders, hash, commit),
+        _ => format!("commit {hash}\n"),
+    };
+
+    if commit.parents.len() > 1 {
+        let parents = commit.parents.iter().map(abbreviated).collect::<Vec<_>>();
+
+        text.push_str(&format!("Merge: {}\n", parents.join(" ")));
+    }
+
+    text.push_str(&format!("Author: {}\n", person(&commit.author)));
+
+    match format {
+        LogFormat::Medium => text.push_str(&format!("Date:   {}\n", date(&commit.author))),
+        LogFormat::Full => text.push_str(&format!("Commit: {}\n", person(&commit.committer))),
+        _ => {}
+    }
+
+    let message = match format {
+        LogFormat::Short => subject(&message),
+        _ => message.trim_end().to_owned(),
+    };
+
+    text.push('\n');
+
+    for line in message.lines() {
+        text.push_str(&format!("    {line}\n"));
+    }
+
+    text.pop();
+
+    text
+}
+
+fn person(signature: &Signature) -> String {
+    format!("{} <{}>", signature.name, signature.email)
+}
+
+/// The first paragraph of a message on one line
+fn subject(message: &str) -> String {
+    message
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Everything after the first paragraph
+fn body(message: &str) -> String {
+    let mut lines = message
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .skip_while(|line| !line.trim().is_empty())
+        .skip_while(|line| line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if !lines.is_empty() {
+        lines.push('\n');
+    }
+
+    lines
+}
+
+/// Formats the time of `signature` in its own timezone, `format` being a chrono one
+fn formatted_date(signature: &Signature, format: &str) -> String {
+    FixedOffset::east_opt(signature.offset_minutes * 60)
+        .and_then(|offset| offset.timestamp_opt(signature.time, 0).single())
+        .map(|time| time.format(format).to_string())
+        .unwrap_or_default()
+}
+
+/// `Thu Oct 17 10:00:00 2026 +0200`, git's default date format
+fn date(signature: &Signature) -> String {
+    formatted_date(signature, "%a %b %-d %H:%M:%S %Y %z")
+}
+
+/// `3 days ago`, or however far back the signature is
+fn relative_date(signature: &Signature) -> String {
+    let (now, _) = identity::now();
+    let seconds = (now - signature.time).max(0);
+    let (count, unit) = match seconds {
+        0..90 => (seconds, "second"),
+        90..5_400 => (seconds / 60, "minute"),
+        5_400..129_600 => (seconds / 3_600, "hour"),
+        129_600..1_209_600 => (seconds / 86_400, "day"),
+        1_209_600..5_184_000 => (seconds / 604_800, "week"),
+        5_184_000..31_536_000 => (seconds / 2_592_000, "month"),
+        _ => (seconds / 31_536_000, "year"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+
+    format!("{count} {unit}{plural} ago")
+}
+
+/// Replaces git's `--format` placeholders, leaving ones it doesn't know as they are
+fn expand(placeholders: &str, hash: &Hash, commit: &Commit) -> String {
+    let message = String::from_utf8_lossy(&commit.message);
+    let join = |hashes: &mut dyn Iterator<Item = String>| hashes.collect::<Vec<_>>().join(" ");
+    let mut expanded = String::new();
+    let mut rest = placeholders;
+
+    while let Some(start) = rest.find('%') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let signature = match rest.as_bytes().first() {
+            Some(b'a') => Some(&commit.author),
+            Some(b'c') => Some(&commit.committer),
+            _ => None,
+        };
+        let (value, length) = match (signature, rest.get(..1), rest.get(1..2)) {
+            (Some(signature), _, Some(field)) => match field {
+                "n" => (signature.name.clone(), 2),
+                "e" => (signature.email.clone(), 2),
+                "d" => (date(signature), 2),
+                "t" => (signature.time.to_string(), 2),
+                "i" => (formatted_date(signature, "%Y-%m-%d %H:%M:%S %z"), 2),
+                "I" => (formatted_date(signature, "%Y-%m-%dT%H:%M:%S%:z"), 2),
+                "r" => (relative_date(signature), 2),
+                _ => (format!("%{}", &rest[..2]), 2),
+            },
+            (_, Some("H"), _) => (hash.to_string(), 1),
+            (_, Some("h"), _) => (hash.to_string()[..7].to_owned(), 1),
+            (_, Some("T"), _) => (commit.tree.to_string(), 1),
+            (_, Some("t"), _) => (commit.tree.to_string()[..7].to_owned(), 1),
+            (_, Some("P"), _) => (join(&mut commit.parents.iter().map(Hash::to_string)), 1),
+            (_, Some("p"), _) => (
+                join(
+                    &mut commit
+                        .parents
+                        .iter()
+                        .map(|parent| parent.to_string()[..7].to_owned()),
+                ),
+                1,
+            ),
+            (_, Some("s"), _) => (subject(&message), 1),
+            (_, Some("b"), _) => (body(&message), 1),
+            (_, Some("B"), _) => (message.to_string(), 1),
+            (_, Some("n"), _) => ("\n".to_owned(), 1),
+            (_, Some("x"), _) => match rest.get(1..3).map(|hex| u8::from_str_radix(hex, 16)) {
+                Some(Ok(byte)) => (char::from(byte).to_string(), 3),
+                _ => ("%x".to_owned(), 1),
+            },
+            (_, Some("%"), _) => ("%".to_owned(), 1),
+            _ => ("%".to_owned(), 0),
+        };
+
+        expanded.push_str(&value);
+        rest = &rest[length..];
+    }
+
+    expanded.push_str(rest);
+
+    expanded
+}
+
+impl LogFormat {
+    /// Reads `--format` values: `oneline`, `short`, `medium` and `full`, `format:` or
+    /// `tformat:` followed by placeholders, or just placeholders.
+    pub fn parse(format: &str) -> Result<Self> {
+        match format {
+            "oneline" => Ok(Self::Oneline),
+            "short" => Ok(Self::Short),
+            "medium" => Ok(Self::Medium),
+            "full" => Ok(Self::Full),
+            _ => match format
+                .strip_prefix("format:")
+                .or_else(|| format.strip_prefix("tformat:"))
+            {
+                Some(placeholders) => Ok(Self::Placeholders(placeholders.to_owned())),
+                None if format.contains('%') => Ok(Self::Placeholders(format.to_owned())),
+                None => Err(Error::InvalidFormat(format.to_owned())),
+            },
+        }
+    }
+}
+
+/// Draws the lines of history to the left of each commit, like `git log --graph`. Each
+/// column is waiting for the commit it leads to.
+#[derive(Default)]
+struct Graph {
+    columns: Vec<Hash>,
+}
+
+impl Graph {
+    /// The lines to show next to the text of `hash`: its own row, then any lines opening
+    /// columns for a merge's other parents or closing columns that lead to the same commit.
+    /// Text lines after those get the padding.
+    fn next(&mut self, hash: &Hash, parents: &[Hash]) -> (Vec<String>, String) {
+        let column = match self.columns.iter().position(|waiting| waiting == hash) {
+            Some(column) => column,
+            None => {
+                self.columns.push(hash.clone());
+                self.columns.len() - 1
+            }
+        };
+        let row = (0..self.columns.len())
+            .map(|index| if index == column { "* " } else { "| " })
+            .collect::<String>();
+        let mut lines = vec![row];
+
+        match parents.split_first() {
+            Some((first, others)) => {
+                self.columns[column] = first.clone();
+
+                let mut position = column;
+
+                // a parent another column already leads to gets its own column, which then
+                // joins that one
+                for parent in others {
+                    position += 1;
+                    self.columns.insert(position, parent.clone());
+                    lines.push(self.open_line(position));
+                }
+            }
+            None => {
+                self.columns.remove(column);
+
+                if column < self.columns.len() {
+                    lines.push(self.close_line(column, false));
+                }
+            }
+        }
+
+        while let Some(duplicate) = (1..self.columns.len())
+            .find(|&index| self.columns[..index].contains(&self.columns[index]))
+        {
+            let target = self.columns[..duplicate]
+                .iter()
+                .position(|waiting| *waiting == self.columns[duplicate])
+                .unwrap_or_default();
+
+            self.columns.remove(duplicate);
+            lines.push(self.close_line(duplicate, true));
+            lines.extend(
+                ((target + 1)..duplicate)
+                    .rev()
+                    .map(|at| self.cross_line(at)),
+            );
+        }
+
+        let padding = "| ".repeat(self.columns.len());
+
+        (lines, padding)
+    }
+
+    /// `|\` for the column just opened at `opened`, shifting the ones after it right
+    fn open_line(&self, opened: usize) -> String {
+        let mut line = vec![b' '; self.columns.len() * 2];
+
+        for index in 0..opened {
+            line[index * 2] = b'|';
+        }
+
+        for index in opened..self.columns.len() {
+            line[index * 2 - 1] = b'\\';
+        }
+
+        String::from_utf8_lossy(&line).into_owned()
+    }
+
+    /// `|/` for the column just closed at `closed`, shifting the ones after it left. A
+    /// column that merged into another one draws its own line over.
+    fn close_line(&self, closed: usize, merged: bool) -> String {
+        let mut line = vec![b' '; (self.columns.len() + 1) * 2];
+        let first_moved = if merged { closed } else { closed + 1 };
+
+        for index in 0..closed {
+            line[index * 2] = b'|';
+        }
+
+        for index in first_moved..=self.columns.len() {
+            line[index * 2 - 1] = b'/';
+        }
+
+        String::from_utf8_lossy(&line).into_owned()
+    }
+
+    /// `|/|`, a merged column on its way left past the column at `at`, one column per line
+    fn cross_line(&self, at: usize) -> String {
+        let mut line = vec![b' '; self.columns.len() * 2];
+
+        for index in 0..self.columns.len() {
+            line[index * 2] = b'|';
+        }
+
+        line[at * 2 - 1] = b'/';
+
+        String::from_utf8_lossy(&line).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{commit_tree::commit_tree, object_store::MemoryObjectStore, tree::Tree};
+
+    /// `base`, then `main` and `side` on top of it, merged by `merge`, a second apart
+    fn history(objects: &mut MemoryObjectStore) -> Result<Vec<Hash>> {
+        let empty = objects.write("tree", &Tree::new(vec![]).serialize())?;
+        let mut commit = |message: &str, parents: &[Hash], time: i64| {
+            let signature = Signature::new("A U Thor", "author@example.com", time, 0);
+
+            commit_tree(&empty, parents, message, &signature, &signature, objects)
+        };
+        let base = commit("base", &[], 1_700_000_000)?;
+        let side = commit("side", std::slice::from_ref(&base), 1_700_000_001)?;
+        let main = commit(
+            "main\n\nwith a body",
+            std::slice::from_ref(&base),
+            1_700_000_002,
+        )?;
+        let merge = commit("merge", &[main.clone(), side.clone()], 1_700_000_003)?;
+
+        Ok(vec![base, side, main, merge])
+    }
+
+    #[test]
+    fn should_order_by_date_and_topology() -> Result<()> {
+        let mut objects = MemoryObjectStore::new();
+        let [base, side, main, merge] = <[Hash; 4]>::try_from(history(&mut objects)?).unwrap();
+        let filter = Filter {
+            since: None,
+            until: None,
+            author: None,
+            grep: None,
+            paths: vec![],
+        };
+        let (nodes, tips) = walk(
+            &objects,
+            std::slice::from_ref(&merge),
+            &HashSet::new(),
+            &filter,
+        )?;
+
+        assert_eq!(
+            sort(&nodes, &tips, LogOrder::Default),
+            vec![&merge, &main, &side, &base]
+        );
+        assert_eq!(
+            sort(&nodes, &tips, LogOrder::Topo),
+            vec![&merge, &side, &main, &base]
+        );
+
+        // like `main..merge`, leaving out what main reaches
+        let excluded = HashSet::from([main.clone(), base.clone()]);
+        let (nodes, tips) = walk(&objects, std::slice::from_ref(&merge), &excluded, &filter)?;
+
+        assert_eq!(sort(&nodes, &tips, LogOrder::Default), vec![&merge, &side]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_draw_graph_and_expand_placeholders() -> Result<()> {
+        let mut objects = MemoryObjectStore::new();
+        let hashes = history(&mut objects)?;
+        let filter = Filter {
+            since: Some(1_700_000_001),
+            until: None,
+            author: Some(Regex::new("^A U")?),
+            grep: None,
+            paths: vec![],
+        };
+        let (nodes, tips) = walk(&objects, &hashes[3..], &HashSet::new(), &filter)?;
+        let mut graph = Graph::default();
+        let mut lines = vec![];
+
+        for hash in sort(&nodes, &tips, LogOrder::Topo) {
+            let node = &nodes[hash];
+            let text = expand("%s by %an%n%ai", hash, &node.commit);
+            let (graph, _) = graph.next(hash, &node.parents);
+
+            lines.push(format!("{}{}", graph[0], text.replace('\n', " at ")));
+            lines.extend(graph[1..].iter().map(|line| line.trim_end().to_owned()));
+        }
+
+        // base is filtered out by date, so nothing leads to it
+        assert_eq!(
+            lines,
+            vec![
+                "* merge by A U Thor at 2023-11-14 22:13:23 +0000",
+                "|\\",
+                "| * side by A U Thor at 2023-11-14 22:13:21 +0000",
+                "* main by A U Thor at 2023-11-14 22:13:22 +0000",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_formats() -> Result<()> {
+        assert_eq!(LogFormat::parse("oneline")?, LogFormat::Oneline);
+        assert_eq!(
+            LogFormat::parse("format:%h %s")?,
+            LogFormat::Placeholders("%h %s".to_owned())
+        );
+        assert_eq!(
+            LogFormat::parse("%H")?,
+            LogFormat::Placeholders("%H".to_owned())
+        );
+        assert!(LogFormat::parse("fancy").is_err());
+        Ok(())
+    }
+}
@@ -1,35 +1,38 @@
-use std::{fs::File, io::Read, path::Path};
+use crate::error::{Context, Error, Result};
+use std::io::Write;
 
-use anyhow::Context;
+use crate::{hash::Hash, object::Object, object_store::ObjectStore, repository::Repository};
 
-use crate::{
-    tree::Tree,
-    utils::{decompress, get_object_directory_name, get_object_file_name, remove_header},
-};
+/// Lists the entries of a tree, or a commit's tree, like `git ls-tree`, or just their names with
+/// `name_only`.
+pub fn ls_tree(
+    repository: &Repository,
+    hash: &Hash,
+    name_only: bool,
+    output: &mut impl Write,
+) -> Result<()> {
+    let objects = repository.objects();
+    // like git, a commit lists its root tree
+    let tree = match objects.read_object(hash).context("reading tree object")? {
+        Object::Commit(commit) => objects.read_tree(&commit.tree)?,
+        Object::Tree(tree) => tree,
+        object => {
+            return Err(Error::WrongObjectType {
+                hash: hash.clone(),
+                expected: "tree",
+                found: object.object_type(),
+            })
+        }
+    };
 
-pub fn ls_tree(args: &[String]) {
-    let _option = &args[0];
-    let hash = &args[1];
-    let directory_name = get_object_directory_name(hash);
-    let file_name = get_object_file_name(hash);
-    let path = Path::new(".git")
-        .join("objects")
-        .join(directory_name)
-        .join(file_name);
-    let mut file = File::open(path).expect("error opening file");
-    let mut compressed_bytes = vec![];
+    for tree_object in &tree.tree_objects {
+        if name_only {
+            output.write_all(&tree_object.filename)?;
+            writeln!(output)?;
+        } else {
+            writeln!(output, "{tree_object}")?;
+        }
+    }
 
-    file.read_to_end(&mut compressed_bytes)
-        .expect("error reading file to end");
-
-    let bytes = decompress(&compressed_bytes);
-    let tree = Tree::from(
-        remove_header(&bytes)
-            .context("removing header")
-            .expect("attempting to remove header"),
-    );
-
-    tree.filenames()
-        .iter()
-        .for_each(|filename| println!("{filename}"));
+    Ok(())
 }
@@ -1,59 +1,994 @@
-use versionControl::{
-    cat_file::cat_file, clone::clone, commit_tree::commit_tree, hash_object::hash_object,
-    init::init, ls_tree::ls_tree, write_tree::write_tree,
+use anyhow::{Context, Result};
+use clap::{ArgGroup, Parser, Subcommand};
+use std::{io, path::PathBuf, process::ExitCode};
+use version_control::{
+    add::add,
+    branch::{self, Branches, Track},
+    cat_file::{cat_file, CatFileMode},
+    checkout::{switch, SwitchOptions, Switched},
+    clone::clone,
+    commit::commit,
+    commit_tree::commit_tree,
+    config::{config, ConfigAction, Scope, ValueType},
+    error::Error,
+    hash::Hash,
+    hash_object::hash_object,
+    identity,
+    init::init,
+    log::{log, LogFormat, LogOptions, LogOrder},
+    ls_tree::ls_tree,
+    mv::mv,
+    object_store::MemoryObjectStore,
+    reflog::show as show_reflog,
+    refs::HEAD,
+    repack::repack,
+    repository::Repository,
+    rev_list::{rev_list, RevListOptions},
+    rev_parse::{rev_parse, RevParseOptions},
+    revision::{expand_ref, resolve_range, resolve_revision, Tip},
+    rm::{rm, RmOptions},
+    status::{status, StatusFormat, UntrackedFiles},
+    update_ref::{parse_expected, update_ref, update_refs_from},
+    write_tree::write_tree,
 };
-use hex::ToHex;
-use std::{env, path::PathBuf};
+
+/// A version control system that speaks git's object and pack formats
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Create an empty repository
+    Init {
+        /// Where to create the repository, the current directory by default
+        directory: Option<PathBuf>,
+    },
+    /// Show the content, type or size of an object
+    #[command(group(ArgGroup::new("mode").required(true)))]
+    CatFile {
+        /// Pretty print the content, listing tree entries one per line
+        #[arg(short, group = "mode")]
+        pretty: bool,
+        /// Show the object type
+        #[arg(short = 't', group = "mode")]
+        object_type: bool,
+        /// Show the object size
+        #[arg(short, group = "mode")]
+        size: bool,
+        /// A hash, ref name or other revision
+        object: String,
+    },
+    /// Compute the blob hash of a file
+    HashObject {
+        /// Also write the blob into the object store
+        #[arg(short)]
+        write: bool,
+        file: PathBuf,
+    },
+    /// List the entries of a tree
+    LsTree {
+        /// Only show file names
+        #[arg(long)]
+        name_only: bool,
+        /// A tree, or a revision naming one
+        tree: String,
+    },
+    /// Write the work tree as tree objects and print the root tree hash
+    WriteTree,
+    /// List, create, rename and delete branches
+    #[command(group(ArgGroup::new("action").args([
+        "delete",
+        "force_delete",
+        "rename",
+        "force_rename",
+        "set_upstream_to",
+        "unset_upstream",
+    ])))]
+    Branch {
+        /// Delete branches, if they're merged into their upstream or else HEAD
+        #[arg(short, long)]
+        delete: bool,
+        /// Delete branches even if they aren't merged
+        #[arg(short = 'D')]
+        force_delete: bool,
+        /// Rename a branch, the current one when only the new name is given
+        #[arg(short = 'm', long = "move")]
+        rename: bool,
+        /// Rename a branch even if the new name is taken
+        #[arg(short = 'M')]
+        force_rename: bool,
+        /// Reset a branch that already exists
+        #[arg(short, long)]
+        force: bool,
+        /// List or delete remote-tracking branches
+        #[arg(short, long)]
+        remotes: bool,
+        /// List local and remote-tracking branches
+        #[arg(short, long, conflicts_with = "remotes")]
+        all: bool,
+        /// Show the hash and subject of each branch and how far it is from its upstream, and
+        /// name the upstream when given twice
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+        /// Make a new branch follow the branch it starts from
+        #[arg(short, long)]
+        track: bool,
+        /// Don't make a new branch follow anything, even a remote-tracking branch
+        #[arg(long, conflicts_with = "track")]
+        no_track: bool,
+        /// Make a branch, the current one by default, follow this one
+        #[arg(short = 'u', long, value_name = "UPSTREAM")]
+        set_upstream_to: Option<String>,
+        /// Stop a branch, the current one by default, from following anything
+        #[arg(long)]
+        unset_upstream: bool,
+        /// Branch names, then where a new branch starts, HEAD by default
+        names: Vec<String>,
+    },
+    /// Switch the work tree, the index and HEAD to another branch
+    Switch {
+        /// Create this branch at the start revision, HEAD by default, and switch to it
+        #[arg(
+            short = 'c',
+            long,
+            value_name = "BRANCH",
+            conflicts_with = "force_create"
+        )]
+        create: Option<String>,
+        /// Like -c, but reset the branch if it already exists
+        #[arg(short = 'C', long, value_name = "BRANCH")]
+        force_create: Option<String>,
+        /// Switch to a commit rather than a branch, leaving HEAD detached
+        #[arg(short, long)]
+        detach: bool,
+        /// Throw away local changes rather than refusing to switch
+        #[arg(short, long, alias = "discard-changes")]
+        force: bool,
+        /// The branch, or the commit to start from or detach at
+        target: Option<String>,
+    },
+    /// Switch to a branch, or detach HEAD at any other revision
+    Checkout {
+        /// Create this branch at the start revision, HEAD by default, and switch to it
+        #[arg(short = 'b', value_name = "BRANCH", conflicts_with = "force_create")]
+        create: Option<String>,
+        /// Like -b, but reset the branch if it already exists
+        #[arg(short = 'B', value_name = "BRANCH")]
+        force_create: Option<String>,
+        /// Leave HEAD detached even when switching to a branch
+        #[arg(long)]
+        detach: bool,
+        /// Throw away local changes rather than refusing to switch
+        #[arg(short, long)]
+        force: bool,
+        /// The branch or revision, or the commit to start from
+        target: Option<String>,
+    },
+    /// Stage files for the next commit
+    Add {
+        /// Add files even if they're ignored
+        #[arg(short, long)]
+        force: bool,
+        /// Stage every change in the work tree
+        #[arg(short = 'A', long, conflicts_with = "pathspec")]
+        all: bool,
+        /// Files, or directories to add everything inside
+        #[arg(required_unless_present = "all")]
+        pathspec: Vec<PathBuf>,
+    },
+    /// Remove files from the index and the work tree
+    Rm {
+        /// Only unstage, keeping the files
+        #[arg(long)]
+        cached: bool,
+        /// Remove directories and everything staged inside them
+        #[arg(short)]
+        recursive: bool,
+        /// Remove files even if they have changes that were never committed
+        #[arg(short, long)]
+        force: bool,
+        #[arg(required = true)]
+        pathspec: Vec<PathBuf>,
+    },
+    /// Move or rename files and directories
+    Mv {
+        /// Overwrite files that are in the way
+        #[arg(short, long)]
+        force: bool,
+        /// The sources, then where to move them
+        #[arg(num_args = 2.., required = true, value_name = "PATH")]
+        paths: Vec<PathBuf>,
+    },
+    /// Show commit history
+    #[command(group(ArgGroup::new("order")))]
+    Log {
+        /// Show each commit as its abbreviated hash and subject
+        #[arg(long, conflicts_with = "format")]
+        oneline: bool,
+        /// oneline, short, medium, full, or placeholders like `format:%h %an %s`
+        #[arg(long, alias = "pretty")]
+        format: Option<String>,
+        /// Show at most this many commits
+        #[arg(short = 'n', long)]
+        max_count: Option<usize>,
+        /// Only commits made since this date, like `2 weeks ago` or 2024-01-31
+        #[arg(long, alias = "after")]
+        since: Option<String>,
+        /// Only commits made until this date
+        #[arg(long, alias = "before")]
+        until: Option<String>,
+        /// Only commits whose author matches this pattern
+        #[arg(long)]
+        author: Option<String>,
+        /// Only commits whose message matches this pattern
+        #[arg(long)]
+        grep: Option<String>,
+        /// Draw the history of merges next to the commits
+        #[arg(long)]
+        graph: bool,
+        /// Show no parents before all of their children, newest first
+        #[arg(long, group = "order")]
+        date_order: bool,
+        /// Like --date-order, by author date
+        #[arg(long, group = "order")]
+        author_date_order: bool,
+        /// Show no parents before all of their children, keeping lines of history together
+        #[arg(long, group = "order")]
+        topo_order: bool,
+        /// Where to start, HEAD by default. `^A`, `A..B` and `A...B` leave out history
+        revisions: Vec<String>,
+        /// Only commits that changed these paths
+        #[arg(last = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Show staged, unstaged and untracked changes
+    Status {
+        /// Show `XY path` lines
+        #[arg(short, long)]
+        short: bool,
+        /// Stable output for scripts, v1 by default
+        #[arg(
+            long,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "v1",
+            value_parser = ["v1", "v2"],
+            value_name = "VERSION"
+        )]
+        porcelain: Option<String>,
+        /// Show the branch in the short and porcelain formats too
+        #[arg(short, long)]
+        branch: bool,
+        /// Which untracked files to show
+        #[arg(
+            short = 'u',
+            long,
+            num_args = 0..=1,
+            default_value = "normal",
+            default_missing_value = "all",
+            value_parser = ["no", "normal", "all"],
+            value_name = "MODE"
+        )]
+        untracked_files: String,
+    },
+    /// Commit the index, or the work tree without one, on top of HEAD and move the current
+    /// branch to it
+    Commit {
+        /// The commit message, several are joined as paragraphs
+        #[arg(short, required = true)]
+        message: Vec<String>,
+    },
+    /// Create a commit object from a tree
+    CommitTree {
+        tree: String,
+        /// A parent commit, none for a root commit and several for a merge
+        #[arg(short)]
+        parent: Vec<String>,
+        /// The commit message, several are joined as paragraphs. Read from standard input
+        /// when there's no -m or -F.
+        #[arg(short)]
+        message: Vec<String>,
+        /// Read the commit message from a file, or standard input for `-`
+        #[arg(short = 'F', conflicts_with = "message")]
+        file: Option<PathBuf>,
+    },
+    /// Clone a repository over smart HTTP
+    Clone {
+        url: String,
+        /// Where to clone into, named after the repository by default
+        directory: Option<String>,
+    },
+    /// Safely update the hash stored in a ref, optionally checking its old value first
+    UpdateRef {
+        /// Delete the ref instead, the second value is then the old value
+        #[arg(short, conflicts_with = "stdin")]
+        delete: bool,
+        /// Read update, create, delete and verify instructions from standard input and apply
+        /// them all or not at all
+        #[arg(long)]
+        stdin: bool,
+        /// Why the ref is changing, for the reflog
+        #[arg(short, default_value = "")]
+        message: String,
+        #[arg(required_unless_present = "stdin", conflicts_with = "stdin")]
+        reference: Option<String>,
+        /// The new value, then the value the ref must currently hold. The all zero hash means
+        /// the ref must not exist.
+        #[arg(num_args = 0..=2, value_name = "VALUE")]
+        values: Vec<String>,
+    },
+    /// List the commits reachable from some revisions but not others, newest first
+    RevList {
+        /// List the trees and blobs of the commits too, with their paths
+        #[arg(long)]
+        objects: bool,
+        /// Print how many there are instead
+        #[arg(long)]
+        count: bool,
+        /// Stop after this many commits
+        #[arg(short = 'n', long)]
+        max_count: Option<usize>,
+        /// Only follow the first parent of merges
+        #[arg(long)]
+        first_parent: bool,
+        /// Show no parents before all of their children
+        #[arg(long)]
+        topo_order: bool,
+        /// Oldest first
+        #[arg(long)]
+        reverse: bool,
+        /// Start from HEAD and every ref too
+        #[arg(long, required_unless_present = "revisions")]
+        all: bool,
+        /// Where to start. `^A`, `A..B` and `A...B` leave out history
+        revisions: Vec<String>,
+    },
+    /// Print the hashes revisions and ranges name, like `HEAD~2`, `v1.0^{tree}` or `A..B`
+    RevParse {
+        /// Take exactly one revision and check that it exists
+        #[arg(long)]
+        verify: bool,
+        /// Abbreviate hashes, to 7 digits by default
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "7")]
+        short: Option<usize>,
+        /// Print the full ref names revisions stand for, like `refs/heads/master`
+        #[arg(long, conflicts_with = "abbrev_ref")]
+        symbolic_full_name: bool,
+        /// Print ref names as short as they can be without being ambiguous
+        #[arg(long)]
+        abbrev_ref: bool,
+        #[arg(required = true)]
+        revisions: Vec<String>,
+    },
+    /// Show where a ref has pointed, newest first
+    Reflog {
+        /// `[show] [<ref>]`, where the ref is HEAD by default
+        #[arg(num_args = 0..=2, value_name = "ARGS")]
+        arguments: Vec<String>,
+    },
+    /// Get, set, unset and list settings in the repository, global and system config files
+    #[command(
+        group(ArgGroup::new("scope")),
+        group(ArgGroup::new("type")),
+        group(ArgGroup::new("action"))
+    )]
+    Config {
+        /// Use ~/.vcconfig
+        #[arg(long, group = "scope")]
+        global: bool,
+        /// Use the system wide config file
+        #[arg(long, group = "scope")]
+        system: bool,
+        /// Use the repository's .vc/config
+        #[arg(long, group = "scope")]
+        local: bool,
+        /// Check and show values as booleans
+        #[arg(long, group = "type")]
+        bool: bool,
+        /// Check and show values as whole numbers, allowing k, m and g suffixes
+        #[arg(long, group = "type")]
+        int: bool,
+        /// Show values as paths, expanding ~/
+        #[arg(long, group = "type")]
+        path: bool,
+        /// Show every value of a key that has several
+        #[arg(long, group = "action")]
+        get_all: bool,
+        /// Add another value to a key instead of replacing it
+        #[arg(long, group = "action", requires = "value")]
+        add: bool,
+        /// Remove a key
+        #[arg(long, group = "action", conflicts_with = "value")]
+        unset: bool,
+        /// Remove every value of a key
+        #[arg(long, group = "action", conflicts_with = "value")]
+        unset_all: bool,
+        /// List every setting
+        #[arg(short, long, group = "action", conflicts_with_all = ["name", "value"])]
+        list: bool,
+        /// A key like user.name
+        #[arg(required_unless_present = "list")]
+        name: Option<String>,
+        /// Set the key to this value
+        value: Option<String>,
+    },
+    /// Pack every reachable object into a single packfile
+    #[command(alias = "gc")]
+    Repack,
+}
 
 #[tokio::main]
-async fn main() {
-    // Uncomment this block to pass the first stage
-    let args: Vec<String> = env::args().collect();
-    let rest_of_args = &args[2..];
-
-    match args[1].as_str() {
-        "init" => {
-            let path = PathBuf::default();
-            init(path);
-        }
-        "cat-file" => cat_file(rest_of_args),
-        "hash-object" => {
-            let write_flag = args[2].as_str() == "-w";
-            let path = PathBuf::new().join(&args[3]);
-            let checksum = hash_object(write_flag, path)
-                .expect("error running hash object command")
-                .encode_hex::<String>();
-            println!("{checksum:?}");
-        }
-        "ls-tree" => ls_tree(rest_of_args),
-        "write-tree" => {
-            let checksum = write_tree().expect("error running write tree command");
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli.command).await {
+        Ok(()) => ExitCode::SUCCESS,
+        // like git, output cut short by something like `| head` isn't an error
+        Err(error) if is_broken_pipe(&error) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(command: Commands) -> Result<()> {
+    match command {
+        Commands::Init { directory } => {
+            let git_directory = init(directory.unwrap_or_default())?;
+            let git_directory = std::path::absolute(&git_directory).unwrap_or(git_directory);
+
+            println!(
+                "Initialized empty vc repository in {}",
+                git_directory.display()
+            );
+        }
+        Commands::CatFile {
+            pretty,
+            object_type,
+            size: _,
+            object,
+        } => {
+            let mode = if pretty {
+                CatFileMode::Pretty
+            } else if object_type {
+                CatFileMode::Type
+            } else {
+                CatFileMode::Size
+            };
+
+            let repository = open_repository()?;
+            let object = resolve_revision(&repository, &object)?;
+
+            cat_file(&repository, mode, &object, &mut io::stdout())?;
+        }
+        Commands::HashObject { write, file } => {
+            let checksum = if write {
+                hash_object(true, &file, open_repository()?.objects_mut())?
+            } else {
+                // hashing alone works outside of a repository too
+                hash_object(false, &file, &mut MemoryObjectStore::new())?
+            };
+
             println!("{checksum}");
         }
-        "commit-tree" => {
-            let tree = &args[2];
-            let parent = if &args[3] == "-p" {
-                &args[4]
+        Commands::LsTree { name_only, tree } => {
+            let repository = open_repository()?;
+            let tree = resolve_revision(&repository, &tree)?;
+
+            ls_tree(&repository, &tree, name_only, &mut io::stdout())?;
+        }
+        Commands::WriteTree => {
+            println!("{}", write_tree(&mut open_repository()?)?);
+        }
+        Commands::Branch {
+            delete,
+            force_delete,
+            rename,
+            force_rename,
+            force,
+            remotes,
+            all,
+            verbose,
+            track,
+            no_track,
+            set_upstream_to,
+            unset_upstream,
+            names,
+        } => {
+            let repository = open_repository()?;
+
+            match (names.as_slice(), set_upstream_to) {
+                (names, _) if delete || force_delete => {
+                    let kind = if remotes {
+                        "remote-tracking branch"
+                    } else {
+                        "branch"
+                    };
+
+                    for (name, hash) in branch::delete(&repository, names, force_delete, remotes)? {
+                        println!("Deleted {kind} {name} (was {}).", &hash.to_string()[..7]);
+                    }
+                }
+                ([new], _) if rename || force_rename => {
+                    branch::rename(&repository, None, new, force_rename)?;
+                }
+                ([old, new], _) if rename || force_rename => {
+                    branch::rename(&repository, Some(old), new, force_rename)?;
+                }
+                ([] | [_], Some(upstream)) => {
+                    let names = names.first().map(String::as_str);
+                    let (name, upstream) = branch::set_upstream(&repository, names, &upstream)?;
+
+                    println!("branch '{name}' set up to track '{upstream}'.");
+                }
+                ([] | [_], None) if unset_upstream => {
+                    branch::unset_upstream(&repository, names.first().map(String::as_str))?;
+                }
+                ([], None) if !rename && !force_rename => {
+                    let branches = match (remotes, all) {
+                        (true, _) => Branches::Remote,
+                        (_, true) => Branches::All,
+                        _ => Branches::Local,
+                    };
+
+                    branch::list(&repository, branches, verbose, &mut io::stdout())?;
+                }
+                ([name] | [name, _], None) if !rename && !force_rename && !unset_upstream => {
+                    let start = names.get(1).map_or(HEAD, String::as_str);
+                    let track = match (track, no_track) {
+                        (true, _) => Track::Always,
+                        (_, true) => Track::Never,
+                        _ => Track::Remote,
+                    };
+
+                    if let Some(upstream) = branch::create(&repository, name, start, force, track)?
+                    {
+                        println!("branch '{name}' set up to track '{upstream}'.");
+                    }
+                }
+                _ => anyhow::bail!(
+                    "usage: branch [-r | -a] [-v] | [-f] [-t | --no-track] <name> [<start>] | \
+                     (-d | -D) [-r] <name>... | (-m | -M) [<old>] <new> | \
+                     (-u <upstream> | --unset-upstream) [<name>]"
+                ),
+            }
+        }
+        Commands::Switch {
+            create,
+            force_create,
+            detach,
+            force,
+            target,
+        } => switch_to(create, force_create, detach, force, target, false)?,
+        Commands::Checkout {
+            create,
+            force_create,
+            detach,
+            force,
+            target,
+        } => switch_to(create, force_create, detach, force, target, true)?,
+        Commands::Add {
+            force,
+            all,
+            pathspec,
+        } => {
+            let mut repository = open_repository()?;
+            let pathspec = if all {
+                vec![repository.work_tree().to_path_buf()]
             } else {
-                panic!("missing parent argument");
+                pathspec
             };
-            let message = if &args[5] == "-m" {
-                &args[6]
+
+            add(&mut repository, &pathspec, force)?;
+        }
+        Commands::Rm {
+            cached,
+            recursive,
+            force,
+            pathspec,
+        } => {
+            let options = RmOptions {
+                cached,
+                recursive,
+                force,
+            };
+
+            for path in rm(&open_repository()?, &pathspec, options)? {
+                println!("rm '{path}'");
+            }
+        }
+        Commands::Mv { force, mut paths } => {
+            let destination = paths.pop().unwrap_or_default();
+
+            mv(&open_repository()?, &paths, &destination, force)?;
+        }
+        Commands::Log {
+            oneline,
+            format,
+            max_count,
+            since,
+            until,
+            author,
+            grep,
+            graph,
+            date_order,
+            author_date_order,
+            topo_order,
+            revisions,
+            paths,
+        } => {
+            let repository = open_repository()?;
+            let revisions = if revisions.is_empty() {
+                vec![HEAD.to_owned()]
             } else {
-                panic!("missing message argument");
+                revisions
+            };
+            let tips = revisions
+                .iter()
+                .map(|revision| resolve_range(&repository, revision))
+                .collect::<Result<Vec<_>, _>>()?
+                .concat();
+            let format = match format {
+                _ if oneline => LogFormat::Placeholders("%h %s".to_owned()),
+                Some(format) => LogFormat::parse(&format)?,
+                None => LogFormat::Medium,
+            };
+            let order = match (date_order, author_date_order, topo_order) {
+                (true, _, _) => LogOrder::Date,
+                (_, true, _) => LogOrder::AuthorDate,
+                (_, _, true) => LogOrder::Topo,
+                _ => LogOrder::Default,
+            };
+            let options = LogOptions {
+                order,
+                format,
+                max_count,
+                since: since
+                    .as_deref()
+                    .map(identity::parse_approximate_date)
+                    .transpose()?,
+                until: until
+                    .as_deref()
+                    .map(identity::parse_approximate_date)
+                    .transpose()?,
+                author,
+                grep,
+                graph,
+                paths,
+            };
+
+            log(&repository, &tips, &options, &mut io::stdout())?;
+        }
+        Commands::Status {
+            short,
+            porcelain,
+            branch,
+            untracked_files,
+        } => {
+            let format = match porcelain.as_deref() {
+                Some("v2") => StatusFormat::PorcelainV2,
+                Some(_) => StatusFormat::Porcelain,
+                None if short => StatusFormat::Short,
+                None => StatusFormat::Long,
+            };
+            let untracked = match untracked_files.as_str() {
+                "no" => UntrackedFiles::No,
+                "all" => UntrackedFiles::All,
+                _ => UntrackedFiles::Normal,
+            };
+
+            status(&open_repository()?, untracked)?.write(format, branch, &mut io::stdout())?;
+        }
+        Commands::Commit { message } => {
+            let message = message.join("\n\n");
+            let summary = commit(&mut open_repository()?, &message)?;
+
+            println!(
+                "{} {}",
+                summary.header(),
+                message.lines().next().unwrap_or_default()
+            );
+        }
+        Commands::CommitTree {
+            tree,
+            parent,
+            message,
+            file,
+        } => {
+            let mut repository = open_repository()?;
+            let tree = resolve_revision(&repository, &tree)?;
+            let parents = parent
+                .iter()
+                .map(|parent| resolve_revision(&repository, parent))
+                .collect::<Result<Vec<_>, _>>()?;
+            let message = match file {
+                _ if !message.is_empty() => message.join("\n\n"),
+                Some(file) if file.as_os_str() != "-" => std::fs::read_to_string(&file)
+                    .with_context(|| format!("reading message from {}", file.display()))?,
+                _ => io::read_to_string(io::stdin()).context("reading message")?,
             };
-            let hash = commit_tree(tree, parent, message).expect("error running hash command");
+            let author = identity::author(repository.config())?;
+            let committer = identity::committer(repository.config())?;
+            let hash = commit_tree(
+                &tree,
+                &parents,
+                &message,
+                &author,
+                &committer,
+                repository.objects_mut(),
+            )?;
 
             println!("{hash}");
         }
-        "clone" => {
-            let uri = &args[2];
-            let target_directory = &args[3];
+        Commands::Clone { url, directory } => {
+            let directory = match directory {
+                Some(directory) => directory,
+                None => directory_from_url(&url)?,
+            };
+
+            clone(&url, &directory).await?;
+        }
+        Commands::UpdateRef {
+            delete,
+            stdin,
+            message,
+            reference,
+            values,
+        } => {
+            let repository = open_repository()?;
+            // an empty or all zero value means no ref, anything else can be any revision
+            let resolve = |value: &String| -> Result<String> {
+                match value.parse::<Hash>() {
+                    _ if value.is_empty() => Ok(String::new()),
+                    Ok(hash) if hash == Hash::default() => Ok(value.clone()),
+                    _ => Ok(resolve_revision(&repository, value)?.to_string()),
+                }
+            };
+
+            match (stdin, delete, reference, values.as_slice()) {
+                (true, ..) => update_refs_from(repository.refs(), io::stdin().lock(), &message)?,
+                (false, true, Some(reference), [] | [_]) => {
+                    let old = values.first().map(resolve).transpose()?;
+                    let expected = parse_expected(old.as_deref())?;
+
+                    update_ref(repository.refs(), &reference, "", expected, &message)?;
+                }
+                (false, false, Some(reference), [new] | [new, _]) => {
+                    let old = values.get(1).map(resolve).transpose()?;
+                    let expected = parse_expected(old.as_deref())?;
+
+                    update_ref(
+                        repository.refs(),
+                        &reference,
+                        &resolve(new)?,
+                        expected,
+                        &message,
+                    )?;
+                }
+                _ => anyhow::bail!("usage: update-ref [-d] <ref> [<new>] [<old>] | --stdin"),
+            }
+        }
+        Commands::RevList {
+            objects,
+            count,
+            max_count,
+            first_parent,
+            topo_order,
+            reverse,
+            all,
+            revisions,
+        } => {
+            let repository = open_repository()?;
+            let mut tips = revisions
+                .iter()
+                .map(|revision| resolve_range(&repository, revision))
+                .collect::<Result<Vec<_>, _>>()?
+                .concat();
+
+            if all {
+                let refs = repository.refs();
+                let hashes = refs
+                    .resolve(HEAD)?
+                    .into_iter()
+                    .chain(refs.list("refs/")?.into_iter().map(|(_, hash)| hash));
+
+                tips.extend(hashes.map(|hash| Tip {
+                    hash,
+                    excluded: false,
+                }));
+            }
+
+            let options = RevListOptions {
+                objects,
+                count,
+                max_count,
+                first_parent,
+                topo_order,
+                reverse,
+            };
+
+            rev_list(&repository, &tips, options, &mut io::stdout())?;
+        }
+        Commands::RevParse {
+            verify,
+            short,
+            symbolic_full_name,
+            abbrev_ref,
+            revisions,
+        } => {
+            let options = RevParseOptions {
+                verify,
+                short,
+                symbolic_full_name,
+                abbrev_ref,
+            };
+
+            rev_parse(&open_repository()?, &revisions, options, &mut io::stdout())?;
+        }
+        Commands::Reflog { arguments } => {
+            let reference = match arguments.as_slice() {
+                [] => HEAD,
+                [show] if show == "show" => HEAD,
+                [show, reference] if show == "show" => reference,
+                [reference] => reference,
+                _ => anyhow::bail!("usage: reflog [show] [<ref>]"),
+            };
+            let repository = open_repository()?;
+            let name = expand_ref(repository.refs(), reference)?
+                .with_context(|| format!("unknown ref {reference}"))?;
+
+            show_reflog(
+                repository.git_directory(),
+                &name,
+                reference,
+                &mut io::stdout(),
+            )?;
+        }
+        Commands::Config {
+            global,
+            system,
+            local,
+            bool,
+            int,
+            path,
+            get_all,
+            add,
+            unset,
+            unset_all,
+            list,
+            name,
+            value,
+        } => {
+            let scope = match (global, system, local) {
+                (true, _, _) => Some(Scope::Global),
+                (_, true, _) => Some(Scope::System),
+                (_, _, true) => Some(Scope::Local),
+                _ => None,
+            };
+            let value_type = match (bool, int, path) {
+                (true, _, _) => Some(ValueType::Bool),
+                (_, true, _) => Some(ValueType::Int),
+                (_, _, true) => Some(ValueType::Path),
+                _ => None,
+            };
+            let name = name.unwrap_or_default();
+            let action = match value {
+                _ if list => ConfigAction::List,
+                _ if get_all => ConfigAction::GetAll(name),
+                _ if unset => ConfigAction::Unset(name),
+                _ if unset_all => ConfigAction::UnsetAll(name),
+                Some(value) if add => ConfigAction::Add(name, value),
+                Some(value) => ConfigAction::Set(name, value),
+                None => ConfigAction::Get(name),
+            };
+            // settings outside of a repository only have the global and system scopes
+            let repository = Repository::discover_from_current_directory().ok();
+            let git_directory = repository.as_ref().map(Repository::git_directory);
+
+            config(git_directory, scope, value_type, action, &mut io::stdout())?;
+        }
+        Commands::Repack => match repack(&open_repository()?)? {
+            Some(summary) => println!(
+                "Packed {} objects ({} deltas) into {}, removed {} loose objects and {} packs",
+                summary.object_count,
+                summary.delta_count,
+                summary.pack_path.display(),
+                summary.removed_loose_objects,
+                summary.removed_packs
+            ),
+            None => println!("Nothing to pack"),
+        },
+    }
+
+    Ok(())
+}
+
+/// Runs `switch`, or `checkout` when `checkout` is set, which detaches HEAD at anything that
+/// isn't a branch rather than refusing to.
+fn switch_to(
+    create: Option<String>,
+    force_create: Option<String>,
+    detach: bool,
+    force: bool,
+    target: Option<String>,
+    checkout: bool,
+) -> Result<()> {
+    let repository = open_repository()?;
+    let create = create.as_deref().or(force_create.as_deref());
+    let target = match (target, create) {
+        (Some(target), _) => target,
+        (None, Some(_)) => HEAD.to_owned(),
+        (None, None) => anyhow::bail!("missing branch or commit argument"),
+    };
+    let detach = detach
+        || (checkout
+            && create.is_none()
+            && !matches!(
+                repository.refs().read(&format!("refs/heads/{target}")),
+                Ok(Some(_))
+            ));
+    let options = SwitchOptions {
+        create,
+        force_create: force_create.is_some(),
+        detach,
+        force,
+    };
+
+    match switch(&repository, &target, options)? {
+        Switched::AlreadyOn(name) => eprintln!("Already on '{name}'"),
+        Switched::Branch(name) => eprintln!("Switched to branch '{name}'"),
+        Switched::NewBranch {
+            name,
+            reset,
+            upstream,
+        } => {
+            if let Some(upstream) = upstream {
+                println!("branch '{name}' set up to track '{upstream}'.");
+            }
 
-            clone(uri, target_directory)
-                .await
-                .expect("error running clone command");
+            if reset {
+                eprintln!("Switched to and reset branch '{name}'");
+            } else {
+                eprintln!("Switched to a new branch '{name}'");
+            }
+        }
+        Switched::Detached { hash, subject } => {
+            eprintln!("HEAD is now at {} {subject}", &hash.to_string()[..7]);
         }
-        _ => println!("unknown command: {}", args[1]),
     }
+
+    Ok(())
+}
+
+fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        let io_error = match cause.downcast_ref::<Error>().map(Error::root) {
+            Some(Error::Io(io_error)) => Some(io_error),
+            _ => cause.downcast_ref::<io::Error>(),
+        };
+
+        io_error.is_some_and(|io_error| io_error.kind() == io::ErrorKind::BrokenPipe)
+    })
+}
+
+fn open_repository() -> Result<Repository> {
+    Ok(Repository::discover_from_current_directory()?)
+}
+
+/// `https://example.com/team/project.git` clones into `project`, like git does
+fn directory_from_url(url: &str) -> Result<String> {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .map(|name| name.trim_end_matches(".git"))
+        .filter(|name| !name.is_empty())
+        .with_context(|| format!("can't work out a directory name from {url}, pass one"))?;
+
+    Ok(name.to_owned())
 }
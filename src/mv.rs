@@ -0,0 +1,200 @@
+use std::path::Path;
+
+use crate::{
+    add::tracked_under,
+    error::{Context, Error, Result},
+    index::Index,
+    repository::Repository,
+};
+
+/// Renames `sources` to `destination` in the work tree and the index, like `git mv`. With
+/// several sources, or when `destination` is a directory, they're moved into it. Existing
+/// files are only overwritten with `force`.
+pub fn mv(
+    repository: &Repository,
+    sources: &[impl AsRef<Path>],
+    destination: &Path,
+    force: bool,
+) -> Result<()> {
+    let mut index = Index::read(repository.git_directory())?.unwrap_or_default();
+    let destination = repository.index_path(destination)?;
+    let into_directory = repository.work_tree().join(&destination).is_dir();
+
+    if sources.len() > 1 && !into_directory {
+        return Err(Error::BadMove(format!(
+            "destination '{destination}' is not a directory"
+        )));
+    }
+
+    let mut moves = vec![];
+
+    for source in sources {
+        let source = repository.index_path(source.as_ref())?;
+        let target = match source.rsplit('/').next() {
+            Some(name) if into_directory => join(&destination, name),
+            _ => destination.clone(),
+        };
+        let bad_move = |reason: &str| {
+            Err(Error::BadMove(format!(
+                "{reason}, source={source}, destination={target}"
+            )))
+        };
+
+        if source.is_empty() || tracked_under(&index, &source).is_empty() {
+            return bad_move("not under version control");
+        }
+
+        if target == source || target.starts_with(&format!("{source}/")) {
+            return bad_move("can not move directory into itself");
+        }
+
+        let target_file = repository.work_tree().join(&target);
+
+        if target_file.symlink_metadata().is_ok() && (!force || target_file.is_dir()) {
+            return bad_move("destination exists");
+        }
+
+        moves.push((source, target));
+    }
+
+    for (source, target) in moves {
+        let target_file = repository.work_tree().join(&target);
+
+        if let Some(parent) = target_file.parent() {
+            std::fs::create_dir_all(parent).with_context(|| format!("creating {target}"))?;
+        }
+
+        std::fs::rename(repository.work_tree().join(&source), &target_file)
+            .with_context(|| format!("renaming {source} to {target}"))?;
+
+        let directory = format!("{source}/");
+        let entries = index
+            .entries()
+            .iter()
+            .filter(|entry| entry.path == source || entry.path.starts_with(&directory))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // the stat data stays, so the next look at the file notices the new ctime and rehashes
+        for mut entry in entries {
+            index.remove(&entry.path);
+            entry.path = format!("{target}{}", &entry.path[source.len()..]);
+            index.add(entry);
+        }
+    }
+
+    index.write(repository.git_directory())
+}
+
+fn join(directory: &str, name: &str) -> String {
+    if directory.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{directory}/{name}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{add::add, init::init, test_utils::temp_directory};
+
+    fn create_repository(name: &str) -> Result<Repository> {
+        let directory = temp_directory(&format!("mv-{name}"));
+
+        std::fs::create_dir_all(directory.join("src/nested"))?;
+        std::fs::write(directory.join("README"), "hello\n")?;
+        std::fs::write(directory.join("src/nested/main.rs"), "fn main() {}\n")?;
+
+        let git_directory = init(directory.clone())?;
+        let mut repository = Repository::open(git_directory, directory.clone())?;
+
+        add(&mut repository, &[&directory], false)?;
+
+        Ok(repository)
+    }
+
+    fn staged(repository: &Repository) -> Result<Vec<String>> {
+        let index = Index::read(repository.git_directory())?.unwrap_or_default();
+
+        Ok(index
+            .entries()
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect())
+    }
+
+    #[test]
+    fn should_rename_files_and_directories() -> Result<()> {
+        let repository = create_repository("rename")?;
+        let work_tree = repository.work_tree().to_path_buf();
+
+        mv(
+            &repository,
+            &[work_tree.join("README")],
+            &work_tree.join("README.md"),
+            false,
+        )?;
+        mv(
+            &repository,
+            &[work_tree.join("src/nested")],
+            &work_tree.join("lib"),
+            false,
+        )?;
+
+        assert_eq!(staged(&repository)?, vec!["README.md", "lib/main.rs"]);
+        assert!(work_tree.join("lib/main.rs").is_file());
+        assert!(!work_tree.join("README").exists());
+
+        mv(
+            &repository,
+            &[work_tree.join("README.md")],
+            &work_tree.join("lib"),
+            false,
+        )?;
+
+        assert_eq!(staged(&repository)?, vec!["lib/README.md", "lib/main.rs"]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_refuse_bad_moves() -> Result<()> {
+        let repository = create_repository("refuse")?;
+        let work_tree = repository.work_tree().to_path_buf();
+
+        std::fs::write(work_tree.join("untracked"), "")?;
+
+        for (source, destination) in [
+            ("untracked", "elsewhere"),
+            ("README", "untracked"),
+            ("src", "src/nested/src"),
+        ] {
+            assert!(matches!(
+                mv(
+                    &repository,
+                    &[work_tree.join(source)],
+                    &work_tree.join(destination),
+                    false
+                ),
+                Err(Error::BadMove(_))
+            ));
+        }
+
+        mv(
+            &repository,
+            &[work_tree.join("README")],
+            &work_tree.join("untracked"),
+            true,
+        )?;
+
+        assert_eq!(
+            staged(&repository)?,
+            vec!["src/nested/main.rs", "untracked"]
+        );
+        assert_eq!(
+            std::fs::read_to_string(work_tree.join("untracked"))?,
+            "hello\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,476 @@
+// Object formats are described at https://git-scm.com/book/en/v2/Git-Internals-Git-Objects
+
+use crate::error::{Context, Error, Result};
+use std::fmt::Display;
+
+use crate::{hash::Hash, object_store::object_type_name, tree::Tree};
+
+/// A parsed git object. Serializing gives back the exact bytes it was parsed from, so the
+/// hash doesn't change when an object passes through here.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Object {
+    Blob(Blob),
+    Tree(Tree),
+    Commit(Commit),
+    Tag(Tag),
+}
+
+impl Object {
+    pub fn parse(object_type: &str, data: &[u8]) -> Result<Self> {
+        Ok(match object_type {
+            "blob" => Self::Blob(Blob {
+                data: data.to_vec(),
+            }),
+            "tree" => Self::Tree(Tree::parse(data).context("parsing tree")?),
+            "commit" => Self::Commit(Commit::parse(data).context("parsing commit")?),
+            "tag" => Self::Tag(Tag::parse(data).context("parsing tag")?),
+            _ => return Err(Error::corrupt(format!("unknown object type {object_type}"))),
+        })
+    }
+
+    pub fn object_type(&self) -> &'static str {
+        match self {
+            Self::Blob(_) => "blob",
+            Self::Tree(_) => "tree",
+            Self::Commit(_) => "commit",
+            Self::Tag(_) => "tag",
+        }
+    }
+
+    /// The object content, without the `<type> <size>\0` header
+    pub fn serialize(&self) -> Vec<u8> {
+        match self {
+            Self::Blob(blob) => blob.data.clone(),
+            Self::Tree(tree) => tree.serialize(),
+            Self::Commit(commit) => commit.serialize(),
+            Self::Tag(tag) => tag.serialize(),
+        }
+    }
+}
+
+/// Header names and values, in the order they appear
+pub type Headers = Vec<(String, Vec<u8>)>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blob {
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub tree: Hash,
+    pub parents: Vec<Hash>,
+    pub author: Signature,
+    pub committer: Signature,
+    /// Headers after the committer, like `encoding`, `mergetag` and `gpgsig`, in their
+    /// original order. Multi-line values are stored without the continuation spaces.
+    pub extra_headers: Headers,
+    pub message: Vec<u8>,
+    /// Whether an empty line ends the headers. Only objects made by other tools leave it out.
+    pub has_message: bool,
+}
+
+impl Commit {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let (headers, message) = parse_headers(data)?;
+        let mut headers = headers.into_iter().peekable();
+        let tree = match headers.next() {
+            Some((key, value)) if key == "tree" => parse_hash(&value)?,
+            _ => return Err(Error::corrupt("commit doesn't start with a tree")),
+        };
+        let mut parents = vec![];
+
+        while let Some((_, value)) = headers.next_if(|(key, _)| key == "parent") {
+            parents.push(parse_hash(&value)?);
+        }
+
+        let author = match headers.next() {
+            Some((key, value)) if key == "author" => Signature::parse(&value)?,
+            _ => return Err(Error::corrupt("commit is missing its author")),
+        };
+        let committer = match headers.next() {
+            Some((key, value)) if key == "committer" => Signature::parse(&value)?,
+            _ => return Err(Error::corrupt("commit is missing its committer")),
+        };
+
+        Ok(Self {
+            tree,
+            parents,
+            author,
+            committer,
+            extra_headers: headers.collect(),
+            has_message: message.is_some(),
+            message: message.unwrap_or_default(),
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = vec![];
+
+        write_header(&mut data, "tree", self.tree.to_string().as_bytes());
+
+        for parent in &self.parents {
+            write_header(&mut data, "parent", parent.to_string().as_bytes());
+        }
+
+        write_header(&mut data, "author", &self.author.serialize());
+        write_header(&mut data, "committer", &self.committer.serialize());
+
+        for (key, value) in &self.extra_headers {
+            write_header(&mut data, key, value);
+        }
+
+        if self.has_message {
+            data.push(b'\n');
+            data.extend_from_slice(&self.message);
+        }
+
+        data
+    }
+
+    /// The signature made with `commit -S`, if there is one
+    pub fn gpgsig(&self) -> Option<&[u8]> {
+        self.extra_headers
+            .iter()
+            .find(|(key, _)| key == "gpgsig")
+            .map(|(_, value)| value.as_slice())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub object: Hash,
+    pub object_type: &'static str,
+    pub name: String,
+    /// Very old tags were written without a tagger
+    pub tagger: Option<Signature>,
+    pub extra_headers: Headers,
+    /// The message, followed by the signature for signed tags
+    pub message: Vec<u8>,
+    /// Whether an empty line ends the headers
+    pub has_message: bool,
+}
+
+impl Tag {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let (headers, message) = parse_headers(data)?;
+        let mut headers = headers.into_iter().peekable();
+        let object = match headers.next() {
+            Some((key, value)) if key == "object" => parse_hash(&value)?,
+            _ => return Err(Error::corrupt("tag doesn't start with an object")),
+        };
+        let object_type = match headers.next() {
+            Some((key, value)) if key == "type" => {
+                object_type_name(std::str::from_utf8(&value).context("reading tag type")?)?
+            }
+            _ => return Err(Error::corrupt("tag is missing the type of its object")),
+        };
+        let name = match headers.next() {
+            Some((key, value)) if key == "tag" => {
+                String::from_utf8(value).context("reading tag name")?
+            }
+            _ => return Err(Error::corrupt("tag is missing its name")),
+        };
+        let tagger = headers
+            .next_if(|(key, _)| key == "tagger")
+            .map(|(_, value)| Signature::parse(&value))
+            .transpose()?;
+
+        Ok(Self {
+            object,
+            object_type,
+            name,
+            tagger,
+            extra_headers: headers.collect(),
+            has_message: message.is_some(),
+            message: message.unwrap_or_default(),
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = vec![];
+
+        write_header(&mut data, "object", self.object.to_string().as_bytes());
+        write_header(&mut data, "type", self.object_type.as_bytes());
+        write_header(&mut data, "tag", self.name.as_bytes());
+
+        if let Some(tagger) = &self.tagger {
+            write_header(&mut data, "tagger", &tagger.serialize());
+        }
+
+        for (key, value) in &self.extra_headers {
+            write_header(&mut data, key, value);
+        }
+
+        if self.has_message {
+            data.push(b'\n');
+            data.extend_from_slice(&self.message);
+        }
+
+        data
+    }
+}
+
+/// Who made a commit or tag and when, as in `Name <email> 1700000000 +0100`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// Seconds since the unix epoch
+    pub time: i64,
+    /// Offset from UTC of the timezone the signature was made in
+    pub offset_minutes: i32,
+    /// The bytes it was parsed from, when they aren't what the fields above would be written
+    /// as, like a `-0000` timezone, extra spaces or a name that isn't UTF-8
+    pub raw: Option<Vec<u8>>,
+}
+
+impl Signature {
+    pub fn new(name: &str, email: &str, time: i64, offset_minutes: i32) -> Self {
+        Self {
+            name: name.to_owned(),
+            email: email.to_owned(),
+            time,
+            offset_minutes,
+            raw: None,
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let line = String::from_utf8_lossy(bytes);
+        let (name, rest) = line
+            .split_once('<')
+            .context("signature is missing its email")?;
+        let (email, rest) = rest
+            .split_once('>')
+            .context("signature email isn't closed")?;
+        let (time, offset) = rest
+            .trim()
+            .split_once(' ')
+            .context("signature is missing its timezone")?;
+        let time = time.parse().context("parsing signature time")?;
+        let mut signature = Self::new(name.trim_end(), email, time, parse_offset(offset)?);
+
+        if signature.to_string().as_bytes() != bytes {
+            signature.raw = Some(bytes.to_vec());
+        }
+
+        Ok(signature)
+    }
+
+    /// The bytes to write into a commit or tag, the exact ones it was parsed from if any
+    pub fn serialize(&self) -> Vec<u8> {
+        match &self.raw {
+            Some(raw) => raw.clone(),
+            None => self.to_string().into_bytes(),
+        }
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.offset_minutes < 0 { '-' } else { '+' };
+        let offset = self.offset_minutes.unsigned_abs();
+
+        write!(
+            f,
+            "{} <{}> {} {sign}{:02}{:02}",
+            self.name,
+            self.email,
+            self.time,
+            offset / 60,
+            offset % 60
+        )
+    }
+}
+
+/// Parses a timezone like `-0700` into minutes
+fn parse_offset(offset: &str) -> Result<i32> {
+    let (sign, digits) = match offset.split_at_checked(1) {
+        Some(("+", digits)) => (1, digits),
+        Some(("-", digits)) => (-1, digits),
+        _ => {
+            return Err(Error::corrupt(format!(
+                "timezone {offset} doesn't start with + or -"
+            )))
+        }
+    };
+
+    if digits.len() != 4 || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(Error::corrupt(format!(
+            "timezone {offset} should have four digits"
+        )));
+    }
+
+    let hours: i32 = digits[..2].parse()?;
+    let minutes: i32 = digits[2..].parse()?;
+
+    Ok(sign * (hours * 60 + minutes))
+}
+
+fn parse_hash(value: &[u8]) -> Result<Hash> {
+    value.to_vec().try_into()
+}
+
+/// Splits commit or tag headers from the message that follows the first empty line, which is
+/// `None` when there's no empty line. Lines starting with a space continue the value of the
+/// header before them.
+fn parse_headers(data: &[u8]) -> Result<(Headers, Option<Vec<u8>>)> {
+    let mut headers: Headers = vec![];
+    let mut position = 0;
+
+    while position < data.len() {
+        if data[position] == b'\n' {
+            return Ok((headers, Some(data[position + 1..].to_vec())));
+        }
+
+        let line_end = data[position..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(data.len(), |end| position + end);
+        let line = &data[position..line_end];
+
+        if let Some(continuation) = line.strip_prefix(b" ") {
+            let (_, value) = headers
+                .last_mut()
+                .context("continuation line before the first header")?;
+
+            value.push(b'\n');
+            value.extend_from_slice(continuation);
+        } else {
+            let space = line
+                .iter()
+                .position(|&byte| byte == b' ')
+                .context("header is missing its value")?;
+            let key = String::from_utf8(line[..space].to_vec()).context("reading header name")?;
+
+            headers.push((key, line[space + 1..].to_vec()));
+        }
+
+        position = line_end + 1;
+    }
+
+    Ok((headers, None))
+}
+
+fn write_header(data: &mut Vec<u8>, key: &str, value: &[u8]) {
+    data.extend_from_slice(key.as_bytes());
+    data.push(b' ');
+
+    for &byte in value {
+        data.push(byte);
+
+        // continuation lines start with a space
+        if byte == b'\n' {
+            data.push(b' ');
+        }
+    }
+
+    data.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIGNED_MERGE: &[u8] = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+parent 3a1f7ed5a60c0720ef825f2654337789ca67361d
+parent bd9dbf5aae1a3862dd1526723246b20206e5fc37
+author Ada Lovelace <ada@example.com> 1700000000 +0530
+committer Charles Babbage <charles@example.com> 1700000100 -0700
+encoding ISO-8859-1
+gpgsig -----BEGIN PGP SIGNATURE-----
+ 
+ iQEzBAABCAAdFiEE
+ -----END PGP SIGNATURE-----
+
+Merge branch 'engine'
+
+Adds the difference engine.
+";
+
+    #[test]
+    fn should_parse_signed_merge_commit() -> Result<()> {
+        let commit = Commit::parse(SIGNED_MERGE)?;
+
+        assert_eq!(
+            commit.tree.to_string(),
+            "4b825dc642cb6eb9a060e54bf8d69288fbee4904"
+        );
+        assert_eq!(commit.parents.len(), 2);
+        assert_eq!(
+            commit.author,
+            Signature::new("Ada Lovelace", "ada@example.com", 1700000000, 330)
+        );
+        assert_eq!(commit.committer.offset_minutes, -420);
+        assert_eq!(
+            commit.extra_headers[0],
+            ("encoding".into(), b"ISO-8859-1".to_vec())
+        );
+        assert_eq!(
+            commit.gpgsig(),
+            Some(
+                &b"-----BEGIN PGP SIGNATURE-----\n\niQEzBAABCAAdFiEE\n-----END PGP SIGNATURE-----"
+                    [..]
+            )
+        );
+        assert_eq!(
+            commit.message,
+            b"Merge branch 'engine'\n\nAdds the difference engine.\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_serialize_commit_byte_for_byte() -> Result<()> {
+        let object = Object::parse("commit", SIGNED_MERGE)?;
+
+        assert_eq!(object.serialize(), SIGNED_MERGE);
+        Ok(())
+    }
+
+    #[test]
+    fn should_round_trip_tags() -> Result<()> {
+        let data = b"object 3a1f7ed5a60c0720ef825f2654337789ca67361d
+type commit
+tag v1.0
+tagger Ada Lovelace <ada@example.com> 1700000000 +0000
+
+First release
+-----BEGIN PGP SIGNATURE-----
+-----END PGP SIGNATURE-----
+";
+        let tag = Tag::parse(data)?;
+
+        assert_eq!(tag.object_type, "commit");
+        assert_eq!(tag.name, "v1.0");
+        assert_eq!(
+            tag.tagger.as_ref().map(|tagger| tagger.time),
+            Some(1700000000)
+        );
+        assert_eq!(tag.serialize(), data);
+        Ok(())
+    }
+
+    #[test]
+    fn should_keep_unusual_signatures_and_missing_messages() -> Result<()> {
+        let data = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+author A <a@x>  1700000000 -0000
+committer \xe9 <a@x> 1700000000 +0000
+";
+        let commit = Commit::parse(data)?;
+
+        assert_eq!(commit.author.offset_minutes, 0);
+        assert!(!commit.has_message);
+        assert_eq!(commit.serialize(), data);
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_malformed_commits() {
+        assert!(Commit::parse(b"parent 3a1f7ed5a60c0720ef825f2654337789ca67361d\n\n").is_err());
+        assert!(Commit::parse(b"tree nothex\n\n").is_err());
+        assert!(Signature::parse(b"Ada <ada@example.com> 1700000000 0100").is_err());
+        assert!(Signature::parse(b"Ada ada@example.com 1700000000 +0100").is_err());
+    }
+}
@@ -0,0 +1,573 @@
+use crate::error::{Context, Error, Result};
+use flate2::Compression;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    hash::Hash,
+    object::{Commit, Object},
+    pack::Pack,
+    pack_index::PackIndex,
+    tree::{Tree, TreeObject, TreeObjectType},
+    utils::{
+        add_header, compress, decompress, get_hash, get_object_directory_name, get_object_file_name,
+    },
+};
+
+/// Numbers the temporary files loose objects are written to, so threads don't share one
+static TEMPORARY_OBJECTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Somewhere git objects can be read from and written to. Objects are identified by the hash
+/// of their `<type> <size>\0` header plus content, but are handed around without the header.
+pub trait ObjectStore {
+    /// Returns the object type and content
+    fn read(&self, hash: &Hash) -> Result<Option<(&'static str, Vec<u8>)>>;
+
+    /// Stores the object if it isn't there already, returning its hash
+    fn write(&mut self, object_type: &'static str, content: &[u8]) -> Result<Hash>;
+
+    fn contains(&self, hash: &Hash) -> Result<bool>;
+
+    /// Hashes of every object in the store
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Hash> + '_>>;
+
+    /// Hashes of the objects whose hex digits start with `prefix`, to expand abbreviated hashes
+    fn find_by_prefix(&self, prefix: &str) -> Result<Vec<Hash>> {
+        let prefix = prefix.to_ascii_lowercase();
+
+        Ok(self
+            .iter()?
+            .filter(|hash| hash.to_string().starts_with(&prefix))
+            .collect())
+    }
+
+    fn read_existing(&self, hash: &Hash) -> Result<(&'static str, Vec<u8>)> {
+        self.read(hash)?
+            .ok_or_else(|| Error::ObjectNotFound(hash.clone()))
+    }
+
+    fn read_object(&self, hash: &Hash) -> Result<Object> {
+        let (object_type, data) = self.read_existing(hash)?;
+
+        Object::parse(object_type, &data).with_context(|| format!("parsing object {hash}"))
+    }
+
+    fn read_commit(&self, hash: &Hash) -> Result<Commit> {
+        match self.read_object(hash)? {
+            Object::Commit(commit) => Ok(commit),
+            object => Err(Error::WrongObjectType {
+                hash: hash.clone(),
+                expected: "commit",
+                found: object.object_type(),
+            }),
+        }
+    }
+
+    fn read_tree(&self, hash: &Hash) -> Result<Tree> {
+        match self.read_object(hash)? {
+            Object::Tree(tree) => Ok(tree),
+            object => Err(Error::WrongObjectType {
+                hash: hash.clone(),
+                expected: "tree",
+                found: object.object_type(),
+            }),
+        }
+    }
+
+    /// The entry at `path`, like `src/main.rs`, looking through the subtrees of `tree`
+    fn read_tree_entry(&self, tree: &Hash, path: &str) -> Result<Option<TreeObject>> {
+        let mut tree = self.read_tree(tree)?;
+        let mut names = path.split('/').filter(|name| !name.is_empty()).peekable();
+
+        while let Some(name) = names.next() {
+            let Some(entry) = tree
+                .tree_objects
+                .into_iter()
+                .find(|entry| entry.filename == name.as_bytes())
+            else {
+                return Ok(None);
+            };
+
+            if names.peek().is_none() {
+                return Ok(Some(entry));
+            }
+
+            if entry.object_type != TreeObjectType::Tree {
+                return Ok(None);
+            }
+
+            tree = self.read_tree(&entry.checksum)?;
+        }
+
+        Ok(None)
+    }
+
+    fn write_object(&mut self, object: &Object) -> Result<Hash> {
+        self.write(object.object_type(), &object.serialize())
+    }
+}
+
+/// Objects stored one per file as `objects/xx/yyyy`, zlib compressed.
+pub struct LooseObjectStore {
+    objects_directory: PathBuf,
+    compression: Compression,
+}
+
+impl LooseObjectStore {
+    pub fn new(objects_directory: PathBuf) -> Self {
+        Self {
+            objects_directory,
+            compression: Compression::default(),
+        }
+    }
+
+    /// How hard to compress new objects, from `core.looseCompression`
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    fn object_path(&self, hash: &Hash) -> PathBuf {
+        let hash_hex = hash.to_string();
+
+        self.objects_directory
+            .join(get_object_directory_name(&hash_hex))
+            .join(get_object_file_name(&hash_hex))
+    }
+
+    pub fn remove(&self, hash: &Hash) -> Result<()> {
+        let path = self.object_path(hash);
+
+        std::fs::remove_file(&path).context("removing loose object")?;
+
+        let directory = path.parent().context("getting object directory")?;
+
+        if std::fs::read_dir(directory)?.next().is_none() {
+            std::fs::remove_dir(directory).context("removing empty object directory")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ObjectStore for LooseObjectStore {
+    fn read(&self, hash: &Hash) -> Result<Option<(&'static str, Vec<u8>)>> {
+        let path = self.object_path(hash);
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let compressed = std::fs::read(&path).context("reading loose object")?;
+        let object =
+            parse_loose_object(&decompress(&compressed)?).context("parsing loose object")?;
+
+        Ok(Some(object))
+    }
+
+    fn write(&mut self, object_type: &'static str, content: &[u8]) -> Result<Hash> {
+        let object = add_header(object_type, content);
+        let hash = get_hash(&object)?;
+        let path = self.object_path(&hash);
+
+        if path.exists() {
+            return Ok(hash);
+        }
+
+        let directory = path.parent().context("getting object directory")?;
+
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .create(directory)
+            .context("creating object directory")?;
+        // a crash part way through would otherwise leave a truncated object that every later
+        // write skips because it exists
+        let temporary = directory.join(format!(
+            "tmp_obj_{}_{}",
+            std::process::id(),
+            TEMPORARY_OBJECTS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = std::fs::write(&temporary, compress(&object, self.compression)?)
+            .and_then(|()| std::fs::rename(&temporary, &path));
+
+        if let Err(error) = written {
+            let _ = std::fs::remove_file(&temporary);
+
+            return Err(error).context("writing loose object");
+        }
+
+        Ok(hash)
+    }
+
+    fn contains(&self, hash: &Hash) -> Result<bool> {
+        Ok(self.object_path(hash).exists())
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Hash> + '_>> {
+        let mut hashes = vec![];
+
+        if !self.objects_directory.is_dir() {
+            return Ok(Box::new(hashes.into_iter()));
+        }
+
+        for entry in std::fs::read_dir(&self.objects_directory)? {
+            let directory = entry?.path();
+            let Some(directory_name) = directory.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+
+            if directory_name.len() != 2 || !directory.is_dir() {
+                continue;
+            }
+
+            for object in std::fs::read_dir(&directory)? {
+                let object_path = object?.path();
+                let Some(file_name) = object_path.file_name().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+
+                // only 40 hex digits, so leftover temporary files aren't taken for objects
+                if let Ok(hash) = format!("{directory_name}{file_name}").parse() {
+                    hashes.push(hash);
+                }
+            }
+        }
+
+        Ok(Box::new(hashes.into_iter()))
+    }
+
+    /// Only lists the `objects/xx` directory the prefix falls in
+    fn find_by_prefix(&self, prefix: &str) -> Result<Vec<Hash>> {
+        let prefix = prefix.to_ascii_lowercase();
+
+        if prefix.len() < 2 || !prefix.is_char_boundary(2) {
+            return Ok(vec![]);
+        }
+
+        let (directory_name, file_prefix) = prefix.split_at(2);
+        let directory = self.objects_directory.join(directory_name);
+
+        if !directory.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut hashes = vec![];
+
+        for object in std::fs::read_dir(&directory)? {
+            let object_path = object?.path();
+            let Some(file_name) = object_path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+
+            if !file_name.starts_with(file_prefix) {
+                continue;
+            }
+
+            if let Ok(hash) = format!("{directory_name}{file_name}").parse() {
+                hashes.push(hash);
+            }
+        }
+
+        Ok(hashes)
+    }
+}
+
+/// Objects inside the packfiles of `objects/pack`. Packs are only written by repacking or
+/// cloning, so this store is read only.
+pub struct PackObjectStore {
+    packs: Vec<Pack>,
+}
+
+impl PackObjectStore {
+    pub fn open(pack_directory: &Path) -> Result<Self> {
+        let mut packs = vec![];
+
+        if pack_directory.is_dir() {
+            for entry in std::fs::read_dir(pack_directory).context("reading pack directory")? {
+                let index_path = entry?.path();
+
+                if index_path
+                    .extension()
+                    .is_none_or(|extension| extension != "idx")
+                {
+                    continue;
+                }
+
+                let index = PackIndex::open(&index_path)?;
+                let pack = Pack::open(&index_path.with_extension("pack"), index)
+                    .with_context(|| format!("opening pack for {}", index_path.display()))?;
+
+                packs.push(pack);
+            }
+        }
+
+        Ok(Self { packs })
+    }
+}
+
+impl ObjectStore for PackObjectStore {
+    fn read(&self, hash: &Hash) -> Result<Option<(&'static str, Vec<u8>)>> {
+        for pack in &self.packs {
+            if let Some(object) = pack.read_object(hash)? {
+                return Ok(Some(object));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn write(&mut self, _object_type: &'static str, _content: &[u8]) -> Result<Hash> {
+        Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "objects can't be added to existing packs, write them loose and repack instead",
+        )))
+    }
+
+    fn contains(&self, hash: &Hash) -> Result<bool> {
+        Ok(self
+            .packs
+            .iter()
+            .any(|pack| pack.index().find_offset(hash).is_some()))
+    }
+
+    /// Searches each pack index rather than listing every object
+    fn find_by_prefix(&self, prefix: &str) -> Result<Vec<Hash>> {
+        Ok(self
+            .packs
+            .iter()
+            .flat_map(|pack| pack.index().find_by_prefix(prefix))
+            .collect())
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Hash> + '_>> {
+        Ok(Box::new(self.packs.iter().flat_map(|pack| {
+            pack.index()
+                .entries()
+                .iter()
+                .map(|entry| entry.hash.clone())
+        })))
+    }
+}
+
+/// Keeps objects in memory, for tests and for work that shouldn't touch the repository.
+#[derive(Default)]
+pub struct MemoryObjectStore {
+    objects: HashMap<Hash, (&'static str, Vec<u8>)>,
+}
+
+impl MemoryObjectStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ObjectStore for MemoryObjectStore {
+    fn read(&self, hash: &Hash) -> Result<Option<(&'static str, Vec<u8>)>> {
+        Ok(self.objects.get(hash).cloned())
+    }
+
+    fn write(&mut self, object_type: &'static str, content: &[u8]) -> Result<Hash> {
+        let hash = get_hash(&add_header(object_type, content))?;
+
+        self.objects
+            .entry(hash.clone())
+            .or_insert_with(|| (object_type, content.to_vec()));
+
+        Ok(hash)
+    }
+
+    fn contains(&self, hash: &Hash) -> Result<bool> {
+        Ok(self.objects.contains_key(hash))
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Hash> + '_>> {
+        Ok(Box::new(self.objects.keys().cloned()))
+    }
+}
+
+/// Every object in a repository: loose objects first, then packs. New objects are written
+/// loose, the way git does until the next repack.
+pub struct Objects {
+    loose: LooseObjectStore,
+    packs: PackObjectStore,
+}
+
+impl Objects {
+    /// Opens the object directory of the repository at `git_directory`, e.g. `.vc`
+    pub fn open(git_directory: &Path) -> Result<Self> {
+        let objects_directory = git_directory.join("objects");
+        let packs = PackObjectStore::open(&objects_directory.join("pack"))?;
+
+        Ok(Self {
+            loose: LooseObjectStore::new(objects_directory),
+            packs,
+        })
+    }
+
+    pub fn loose_mut(&mut self) -> &mut LooseObjectStore {
+        &mut self.loose
+    }
+
+    pub fn loose(&self) -> &LooseObjectStore {
+        &self.loose
+    }
+}
+
+impl ObjectStore for Objects {
+    fn read(&self, hash: &Hash) -> Result<Option<(&'static str, Vec<u8>)>> {
+        match self.loose.read(hash)? {
+            Some(object) => Ok(Some(object)),
+            None => self.packs.read(hash),
+        }
+    }
+
+    fn write(&mut self, object_type: &'static str, content: &[u8]) -> Result<Hash> {
+        self.loose.write(object_type, content)
+    }
+
+    fn contains(&self, hash: &Hash) -> Result<bool> {
+        Ok(self.loose.contains(hash)? || self.packs.contains(hash)?)
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Hash> + '_>> {
+        Ok(Box::new(self.loose.iter()?.chain(self.packs.iter()?)))
+    }
+
+    fn find_by_prefix(&self, prefix: &str) -> Result<Vec<Hash>> {
+        let mut hashes = self.loose.find_by_prefix(prefix)?;
+
+        // an object can be both loose and packed
+        hashes.extend(self.packs.find_by_prefix(prefix)?);
+        hashes.sort();
+        hashes.dedup();
+
+        Ok(hashes)
+    }
+}
+
+/// Splits a decompressed loose object into its type and content, checking the size.
+pub fn parse_loose_object(bytes: &[u8]) -> Result<(&'static str, Vec<u8>)> {
+    let header_end = bytes
+        .iter()
+        .position(|&byte| byte == b'\0')
+        .context("loose object is missing its header")?;
+    let header = std::str::from_utf8(&bytes[..header_end]).context("reading object header")?;
+    let (object_type, size) = header
+        .split_once(' ')
+        .context("object header is missing its size")?;
+    let size: usize = size.parse().context("parsing object size")?;
+    let content = &bytes[header_end + 1..];
+
+    if content.len() != size {
+        return Err(Error::corrupt(format!(
+            "object header says {size} bytes, found {}",
+            content.len()
+        )));
+    }
+
+    Ok((object_type_name(object_type)?, content.to_vec()))
+}
+
+pub fn object_type_name(object_type: &str) -> Result<&'static str> {
+    Ok(match object_type {
+        "commit" => "commit",
+        "tree" => "tree",
+        "blob" => "blob",
+        "tag" => "tag",
+        _ => return Err(Error::corrupt(format!("unknown object type {object_type}"))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_directory;
+
+    #[test]
+    fn should_parse_loose_object() -> Result<()> {
+        let (object_type, content) = parse_loose_object(b"blob 5\0hello")?;
+
+        assert_eq!(object_type, "blob");
+        assert_eq!(content, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_loose_object_with_wrong_size() {
+        assert!(parse_loose_object(b"blob 6\0hello").is_err());
+        assert!(parse_loose_object(b"blurb 5\0hello").is_err());
+    }
+
+    #[test]
+    fn should_write_and_read_from_memory() -> Result<()> {
+        let mut store = MemoryObjectStore::new();
+        let hash = store.write("blob", b"what is up, doc?")?;
+
+        assert_eq!(hash.to_string(), "bd9dbf5aae1a3862dd1526723246b20206e5fc37");
+        assert!(store.contains(&hash)?);
+        assert_eq!(
+            store.read(&hash)?,
+            Some(("blob", b"what is up, doc?".to_vec()))
+        );
+        assert_eq!(store.iter()?.collect::<Vec<_>>(), vec![hash]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_write_loose_objects_without_leaving_temporary_files() -> Result<()> {
+        let directory = temp_directory("object-store-loose");
+        let mut store = LooseObjectStore::new(directory.clone());
+        let hash = store.write("blob", b"what is up, doc?")?;
+
+        assert_eq!(store.iter()?.collect::<Vec<_>>(), vec![hash.clone()]);
+        assert_eq!(std::fs::read_dir(directory.join("bd"))?.count(), 1);
+        assert_eq!(store.find_by_prefix("bd9d")?, vec![hash.clone()]);
+
+        // a leftover temporary file whose path, like a raw hash, is 20 bytes long
+        std::fs::write(directory.join("bd/tmp_obj_123456_789"), "")?;
+
+        assert_eq!(store.iter()?.collect::<Vec<_>>(), vec![hash]);
+        assert_eq!(store.find_by_prefix("bdt")?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_find_entries_in_subtrees() -> Result<()> {
+        let mut store = MemoryObjectStore::new();
+        let blob = store.write("blob", b"fn main() {}\n")?;
+        let src = Tree::new(vec![TreeObject::new(
+            100644,
+            "main.rs".to_owned(),
+            blob.clone(),
+        )?]);
+        let src = store.write("tree", &src.serialize())?;
+        let root = Tree::new(vec![TreeObject::new(40000, "src".to_owned(), src.clone())?]);
+        let root = store.write("tree", &root.serialize())?;
+        let entry = |path| -> Result<Option<Hash>> {
+            Ok(store
+                .read_tree_entry(&root, path)?
+                .map(|entry| entry.checksum))
+        };
+
+        assert_eq!(entry("src/main.rs")?, Some(blob));
+        assert_eq!(entry("src")?, Some(src));
+        assert_eq!(entry("src/main.rs/nested")?, None);
+        assert_eq!(entry("README")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn should_report_missing_objects() -> Result<()> {
+        let store = MemoryObjectStore::new();
+
+        assert!(!store.contains(&Hash::default())?);
+        assert!(store.read(&Hash::default())?.is_none());
+        assert!(matches!(
+            store.read_existing(&Hash::default()),
+            Err(Error::ObjectNotFound(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,422 @@
+// Layout is described at https://git-scm.com/docs/pack-format
+
+use crate::error::{Context, Error, Result};
+use flate2::{bufread::ZlibDecoder, Crc};
+use flate2::{write::ZlibEncoder, Compression, CrcReader};
+use sha1::{Digest, Sha1};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{
+    hash::Hash,
+    pack_index::{PackIndex, PackIndexEntry},
+    process_packfile::{
+        apply_delta, read_bytes, read_offset_encoding, read_type_and_size, write_offset_encoding,
+        write_type_and_size, ObjectType, MAX_PREALLOCATION,
+    },
+};
+
+pub const PACK_SIGNATURE: &[u8; 4] = b"PACK";
+pub const PACK_HEADER_SIZE: u64 = 12;
+pub const PACK_CHECKSUM_SIZE: u64 = 20;
+pub const PACK_VERSION: u32 = 2;
+// git refuses delta chains far shorter than this, so anything longer is a cycle
+const MAX_DELTA_CHAIN: usize = 10_000;
+// resolved objects are kept around so that the next delta on the same base doesn't have to
+// replay the whole chain again
+const CACHE_LIMIT_BYTES: usize = 32 * 1024 * 1024;
+
+#[derive(Debug)]
+pub struct PackHeader {
+    pub version: u32,
+    pub object_count: u32,
+}
+
+impl PackHeader {
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let signature: [u8; 4] = read_bytes(reader).context("reading pack signature")?;
+
+        if &signature != PACK_SIGNATURE {
+            return Err(Error::corrupt(
+                "packfile doesn't start with the PACK signature",
+            ));
+        }
+
+        let version = u32::from_be_bytes(read_bytes(reader).context("reading pack version")?);
+
+        if version != 2 && version != 3 {
+            return Err(Error::corrupt(format!(
+                "unsupported packfile version {version}"
+            )));
+        }
+
+        let object_count =
+            u32::from_be_bytes(read_bytes(reader).context("reading pack object count")?);
+
+        Ok(Self {
+            version,
+            object_count,
+        })
+    }
+}
+
+/// Where a delta finds its base: ref deltas name it by hash, ofs deltas by where it starts in
+/// the pack.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum DeltaBase {
+    Hash(Hash),
+    Offset(u64),
+}
+
+#[derive(Debug)]
+pub struct PackEntry {
+    pub object_type: ObjectType,
+    pub base: Option<DeltaBase>,
+    /// The inflated object, or the delta instructions when `base` is set
+    pub data: Vec<u8>,
+    /// CRC32 of the raw entry as it is stored in the pack, for the pack index
+    pub crc32: u32,
+    /// Offset of the entry that follows this one
+    pub end: u64,
+}
+
+pub struct PackReader<R> {
+    reader: R,
+    cache: HashMap<u64, (&'static str, Vec<u8>)>,
+    cache_size: usize,
+}
+
+impl<R: BufRead + Seek> PackReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            cache: HashMap::new(),
+            cache_size: 0,
+        }
+    }
+
+    pub fn read_header(&mut self) -> Result<PackHeader> {
+        self.reader.seek(SeekFrom::Start(0))?;
+
+        PackHeader::read(&mut self.reader)
+    }
+
+    /// Reads a single entry without resolving deltas.
+    pub fn read_entry(&mut self, offset: u64) -> Result<PackEntry> {
+        self.reader
+            .seek(SeekFrom::Start(offset))
+            .context("seeking to pack entry")?;
+
+        let mut reader = CrcReader::new(&mut self.reader);
+        let object_type = read_type_and_size(&mut reader).context("reading type and size")?;
+        let base = match object_type {
+            ObjectType::OfsDelta(_) => {
+                let distance = read_offset_encoding(&mut reader).context("reading base offset")?;
+                let base_offset = offset
+                    .checked_sub(distance)
+                    .context("base offset points before the start of the packfile")?;
+
+                Some(DeltaBase::Offset(base_offset))
+            }
+            ObjectType::RefDelta(_) => {
+                let hash = read_bytes(&mut reader).context("reading base hash")?;
+
+                Some(DeltaBase::Hash(Hash::new(hash)))
+            }
+            ObjectType::Unknown => {
+                return Err(Error::corrupt(format!(
+                    "unknown object type at pack offset {offset}"
+                )))
+            }
+            _ => None,
+        };
+        let size = object_type
+            .get_size()
+            .context("getting size of pack entry")?;
+        let mut data = Vec::with_capacity(size.min(MAX_PREALLOCATION));
+
+        // one byte past the size is enough to tell the entry is too long
+        ZlibDecoder::new(&mut reader)
+            .take(size as u64 + 1)
+            .read_to_end(&mut data)
+            .context("inflating pack entry")?;
+
+        if data.len() != size {
+            return Err(Error::corrupt(format!(
+                "pack entry at offset {offset} should inflate to {size} bytes, got {}",
+                data.len()
+            )));
+        }
+
+        let crc32 = reader.crc().sum();
+        let end = self.reader.stream_position()?;
+
+        Ok(PackEntry {
+            object_type,
+            base,
+            data,
+            crc32,
+            end,
+        })
+    }
+
+    /// Reads the object at `offset`, following its delta chain back to a whole object.
+    /// `find_offset` locates ref delta bases by hash.
+    pub fn read_object(
+        &mut self,
+        offset: u64,
+        find_offset: impl Fn(&Hash) -> Option<u64>,
+    ) -> Result<(&'static str, Vec<u8>)> {
+        let mut deltas = vec![];
+        let mut current_offset = offset;
+
+        let (object_type, mut data) = loop {
+            if let Some((object_type, data)) = self.cache.get(&current_offset) {
+                break (*object_type, data.clone());
+            }
+
+            if deltas.len() > MAX_DELTA_CHAIN {
+                return Err(Error::corrupt(format!(
+                    "delta chain starting at offset {offset} never reaches a base object"
+                )));
+            }
+
+            let entry = self.read_entry(current_offset)?;
+
+            match entry.base {
+                None => break (entry.object_type.get_type(), entry.data),
+                Some(DeltaBase::Offset(base_offset)) => {
+                    deltas.push(entry.data);
+                    current_offset = base_offset;
+                }
+                Some(DeltaBase::Hash(base_hash)) => {
+                    deltas.push(entry.data);
+                    current_offset = find_offset(&base_hash)
+                        .with_context(|| format!("missing delta base object {base_hash}"))?;
+                }
+            }
+        };
+
+        for delta in deltas.iter().rev() {
+            data = apply_delta(&data, delta).context("applying delta")?;
+        }
+
+        self.cache_object(offset, object_type, &data);
+
+        Ok((object_type, data))
+    }
+
+    fn cache_object(&mut self, offset: u64, object_type: &'static str, data: &[u8]) {
+        if self.cache_size + data.len() > CACHE_LIMIT_BYTES {
+            self.cache.clear();
+            self.cache_size = 0;
+        }
+
+        if data.len() <= CACHE_LIMIT_BYTES {
+            self.cache_size += data.len();
+            self.cache.insert(offset, (object_type, data.to_vec()));
+        }
+    }
+}
+
+/// A packfile together with its index, so objects can be looked up by hash.
+pub struct Pack {
+    // reading moves the file position, but looking an object up shouldn't need `&mut`
+    reader: RefCell<PackReader<BufReader<File>>>,
+    index: PackIndex,
+}
+
+impl Pack {
+    pub fn new(reader: PackReader<BufReader<File>>, index: PackIndex) -> Self {
+        Self {
+            reader: RefCell::new(reader),
+            index,
+        }
+    }
+
+    pub fn open(pack_path: &Path, index: PackIndex) -> Result<Self> {
+        let file = File::open(pack_path).context("opening packfile")?;
+
+        Ok(Self::new(PackReader::new(BufReader::new(file)), index))
+    }
+
+    pub fn index(&self) -> &PackIndex {
+        &self.index
+    }
+
+    pub fn read_object(&self, hash: &Hash) -> Result<Option<(&'static str, Vec<u8>)>> {
+        let Some(offset) = self.index.find_offset(hash) else {
+            return Ok(None);
+        };
+
+        self.reader
+            .borrow_mut()
+            .read_object(offset, |hash| self.index.find_offset(hash))
+            .map(Some)
+    }
+}
+
+/// Writes a version 2 packfile, keeping track of what its index needs.
+pub struct PackWriter<W> {
+    writer: W,
+    hasher: Sha1,
+    offset: u64,
+    entries: Vec<PackIndexEntry>,
+    compression: Compression,
+}
+
+impl<W: Write> PackWriter<W> {
+    pub fn new(mut writer: W, object_count: u32) -> Result<Self> {
+        let mut header = PACK_SIGNATURE.to_vec();
+
+        header.extend_from_slice(&PACK_VERSION.to_be_bytes());
+        header.extend_from_slice(&object_count.to_be_bytes());
+        writer.write_all(&header).context("writing pack header")?;
+
+        Ok(Self {
+            writer,
+            hasher: Sha1::new_with_prefix(&header),
+            offset: PACK_HEADER_SIZE,
+            entries: vec![],
+            compression: Compression::default(),
+        })
+    }
+
+    /// How hard to compress entries, from `pack.compression`
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Writes a whole object, returning the offset it starts at.
+    pub fn write_object(&mut self, hash: Hash, object_type: &str, data: &[u8]) -> Result<u64> {
+        let object_type = ObjectType::from_type(object_type, data.len());
+
+        if let ObjectType::Unknown = object_type {
+            return Err(Error::corrupt(format!(
+                "cannot pack object {hash} of unknown type"
+            )));
+        }
+
+        let mut entry = vec![];
+
+        write_type_and_size(object_type.type_number(), data.len(), &mut entry);
+
+        self.write_entry(hash, entry, data)
+    }
+
+    /// Writes `delta` as an ofs delta against the object written at `base_offset`.
+    pub fn write_ofs_delta(&mut self, hash: Hash, base_offset: u64, delta: &[u8]) -> Result<u64> {
+        let mut entry = vec![];
+
+        write_type_and_size(
+            ObjectType::OfsDelta(0).type_number(),
+            delta.len(),
+            &mut entry,
+        );
+        write_offset_encoding(self.offset - base_offset, &mut entry);
+
+        self.write_entry(hash, entry, delta)
+    }
+
+    fn write_entry(&mut self, hash: Hash, mut entry: Vec<u8>, data: &[u8]) -> Result<u64> {
+        let mut encoder = ZlibEncoder::new(entry, self.compression);
+
+        encoder.write_all(data).context("compressing pack entry")?;
+        entry = encoder.finish().context("compressing pack entry")?;
+
+        let mut crc = Crc::new();
+
+        crc.update(&entry);
+        self.writer
+            .write_all(&entry)
+            .context("writing pack entry")?;
+        self.hasher.update(&entry);
+
+        let offset = self.offset;
+
+        self.entries.push(PackIndexEntry {
+            hash,
+            crc32: crc.sum(),
+            offset,
+        });
+        self.offset += entry.len() as u64;
+
+        Ok(offset)
+    }
+
+    /// Writes the trailing checksum and returns the index for everything written.
+    pub fn finish(mut self) -> Result<PackIndex> {
+        let checksum: [u8; 20] = self.hasher.finalize().into();
+
+        self.writer
+            .write_all(&checksum)
+            .context("writing pack checksum")?;
+        self.writer.flush().context("flushing packfile")?;
+
+        Ok(PackIndex::new(self.entries, Hash::new(checksum)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        index_pack::build_index,
+        process_packfile::create_delta,
+        utils::{add_header, get_hash},
+    };
+
+    #[test]
+    fn should_read_back_written_objects() -> Result<()> {
+        let base = b"the quick brown fox jumps over the lazy dog".repeat(4);
+        let target = [base.as_slice(), b" and the cat"].concat();
+        let base_hash = get_hash(&add_header("blob", &base))?;
+        let target_hash = get_hash(&add_header("blob", &target))?;
+        let mut pack = vec![];
+        let mut writer = PackWriter::new(&mut pack, 2)?;
+        let base_offset = writer.write_object(base_hash.clone(), "blob", &base)?;
+        let target_offset = writer.write_ofs_delta(
+            target_hash.clone(),
+            base_offset,
+            &create_delta(&base, &target),
+        )?;
+        let index = writer.finish()?;
+        let pack_length = pack.len() as u64;
+        let mut reader = PackReader::new(Cursor::new(pack));
+        let rebuilt_index = build_index(&mut reader, pack_length, index.pack_checksum().clone())?;
+
+        assert_eq!(rebuilt_index.entries(), index.entries());
+        assert_eq!(
+            reader.read_object(target_offset, |_| None)?,
+            ("blob", target)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_entries_smaller_than_their_header_says() -> Result<()> {
+        let mut pack = PACK_SIGNATURE.to_vec();
+
+        pack.extend_from_slice(&PACK_VERSION.to_be_bytes());
+        pack.extend_from_slice(&1_u32.to_be_bytes());
+        // a blob claiming to be a terabyte
+        write_type_and_size(3, 1 << 40, &mut pack);
+
+        let mut encoder = ZlibEncoder::new(&mut pack, Compression::default());
+
+        encoder.write_all(b"small")?;
+        encoder.finish()?;
+
+        let mut reader = PackReader::new(Cursor::new(pack));
+
+        assert!(reader.read_object(PACK_HEADER_SIZE, |_| None).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,410 @@
+// Version 2 pack index, described at https://git-scm.com/docs/pack-format
+
+use crate::error::{Context, Error, Result};
+use std::path::Path;
+
+use crate::{hash::Hash, utils::get_hash};
+
+pub const INDEX_SIGNATURE: &[u8; 4] = b"\xfftOc";
+pub const INDEX_VERSION: u32 = 2;
+const FANOUT_ENTRIES: usize = 256;
+// offsets that don't fit in 31 bits live in a separate table of 8 byte offsets, and the
+// 4 byte offset holds this flag plus the position in that table
+const LARGE_OFFSET_FLAG: u32 = 0x8000_0000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackIndexEntry {
+    pub hash: Hash,
+    pub crc32: u32,
+    pub offset: u64,
+}
+
+#[derive(Debug)]
+pub struct PackIndex {
+    entries: Vec<PackIndexEntry>,
+    // fanout[n] is how many hashes have a first byte of n or less
+    fanout: [u32; FANOUT_ENTRIES],
+    pack_checksum: Hash,
+}
+
+impl PackIndex {
+    pub fn new(mut entries: Vec<PackIndexEntry>, pack_checksum: Hash) -> Self {
+        entries.sort_unstable_by(|a, b| a.hash.cmp(&b.hash));
+
+        let mut fanout = [0_u32; FANOUT_ENTRIES];
+
+        for entry in &entries {
+            fanout[entry.hash[0] as usize] += 1;
+        }
+
+        let mut total = 0;
+
+        for count in &mut fanout {
+            total += *count;
+            *count = total;
+        }
+
+        Self {
+            entries,
+            fanout,
+            pack_checksum,
+        }
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path).context("reading pack index")?;
+
+        Self::parse(&bytes).with_context(|| format!("parsing pack index {}", path.display()))
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut reader = IndexReader { bytes, position: 0 };
+
+        if reader.take(4)? != INDEX_SIGNATURE {
+            return Err(Error::corrupt(
+                "pack index doesn't start with the index signature",
+            ));
+        }
+
+        let version = reader.read_u32()?;
+
+        if version != INDEX_VERSION {
+            return Err(Error::corrupt(format!(
+                "unsupported pack index version {version}"
+            )));
+        }
+
+        let mut fanout = [0_u32; FANOUT_ENTRIES];
+
+        for count in &mut fanout {
+            *count = reader.read_u32()?;
+        }
+
+        if fanout.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err(Error::corrupt("pack index fanout isn't cumulative"));
+        }
+
+        let object_count = fanout[FANOUT_ENTRIES - 1] as usize;
+        // a hash, a crc and a small offset per object, then the two checksums. A count the
+        // file is too short for is corrupt rather than something to allocate room for.
+        let minimum_length = object_count
+            .checked_mul(20 + 4 + 4)
+            .and_then(|length| length.checked_add(reader.position + 40));
+
+        if minimum_length.is_none_or(|length| length > bytes.len()) {
+            return Err(Error::corrupt(format!(
+                "pack index is too short for {object_count} objects"
+            )));
+        }
+
+        let mut entries = Vec::with_capacity(object_count);
+
+        for _ in 0..object_count {
+            let hash = Hash::new(reader.take(20)?.try_into()?);
+
+            entries.push(PackIndexEntry {
+                hash,
+                crc32: 0,
+                offset: 0,
+            });
+        }
+
+        for entry in &mut entries {
+            entry.crc32 = reader.read_u32()?;
+        }
+
+        let small_offsets = (0..object_count)
+            .map(|_| reader.read_u32())
+            .collect::<Result<Vec<_>>>()?;
+        let large_offsets_start = reader.position;
+
+        for (entry, offset) in entries.iter_mut().zip(small_offsets) {
+            entry.offset = if offset & LARGE_OFFSET_FLAG == 0 {
+                offset as u64
+            } else {
+                let position = large_offsets_start + (offset & !LARGE_OFFSET_FLAG) as usize * 8;
+                let large_offset = bytes
+                    .get(position..position + 8)
+                    .context("large offset is outside of the pack index")?;
+
+                u64::from_be_bytes(large_offset.try_into()?)
+            };
+        }
+
+        let trailer_start = bytes
+            .len()
+            .checked_sub(40)
+            .filter(|&start| start >= large_offsets_start)
+            .context("pack index is missing its checksums")?;
+        let pack_checksum = Hash::new(bytes[trailer_start..trailer_start + 20].try_into()?);
+        let index_checksum = get_hash(&bytes[..trailer_start + 20])?;
+
+        if index_checksum.as_ref() != &bytes[trailer_start + 20..] {
+            return Err(Error::corrupt("pack index checksum mismatch"));
+        }
+
+        if entries.windows(2).any(|pair| pair[0].hash >= pair[1].hash) {
+            return Err(Error::corrupt("pack index hashes are not sorted"));
+        }
+
+        Ok(Self {
+            entries,
+            fanout,
+            pack_checksum,
+        })
+    }
+
+    /// Entries sorted by hash
+    pub fn entries(&self) -> &[PackIndexEntry] {
+        &self.entries
+    }
+
+    pub fn pack_checksum(&self) -> &Hash {
+        &self.pack_checksum
+    }
+
+    pub fn find_offset(&self, hash: &Hash) -> Option<u64> {
+        let candidates = self.with_first_byte(hash[0])?;
+
+        candidates
+            .binary_search_by(|entry| entry.hash.cmp(hash))
+            .ok()
+            .map(|index| candidates[index].offset)
+    }
+
+    /// Hashes starting with the hex digits of `prefix`, found by binary search
+    pub fn find_by_prefix(&self, prefix: &str) -> Vec<Hash> {
+        let prefix = prefix.to_ascii_lowercase();
+        // the smallest hash the prefix allows is where matches start
+        let Ok(lowest) = format!("{prefix:0<40}").parse::<Hash>() else {
+            return vec![];
+        };
+        let candidates = match prefix.len() {
+            0 | 1 => Some(&self.entries[..]),
+            _ => self.with_first_byte(lowest[0]),
+        };
+        let Some(candidates) = candidates else {
+            return vec![];
+        };
+        let start = candidates.partition_point(|entry| entry.hash < lowest);
+
+        candidates[start..]
+            .iter()
+            .take_while(|entry| entry.hash.to_string().starts_with(&prefix))
+            .map(|entry| entry.hash.clone())
+            .collect()
+    }
+
+    /// The entries whose hashes start with `first_byte`, which the fanout says where to find
+    fn with_first_byte(&self, first_byte: u8) -> Option<&[PackIndexEntry]> {
+        let first_byte = first_byte as usize;
+        let start = match first_byte {
+            0 => 0,
+            _ => self.fanout[first_byte - 1] as usize,
+        };
+        let end = self.fanout[first_byte] as usize;
+
+        self.entries.get(start..end)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+
+        bytes.extend_from_slice(INDEX_SIGNATURE);
+        bytes.extend_from_slice(&INDEX_VERSION.to_be_bytes());
+
+        for count in self.fanout {
+            bytes.extend_from_slice(&count.to_be_bytes());
+        }
+
+        for entry in &self.entries {
+            bytes.extend_from_slice(entry.hash.as_ref());
+        }
+
+        for entry in &self.entries {
+            bytes.extend_from_slice(&entry.crc32.to_be_bytes());
+        }
+
+        let mut large_offsets = vec![];
+
+        for entry in &self.entries {
+            let offset = match u32::try_from(entry.offset) {
+                Ok(offset) if offset & LARGE_OFFSET_FLAG == 0 => offset,
+                _ => {
+                    let position = u32::try_from(large_offsets.len())
+                        .context("too many large offsets for the pack index")?;
+
+                    large_offsets.push(entry.offset);
+                    LARGE_OFFSET_FLAG | position
+                }
+            };
+
+            bytes.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        for offset in large_offsets {
+            bytes.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        bytes.extend_from_slice(self.pack_checksum.as_ref());
+
+        let checksum = get_hash(&bytes)?;
+
+        bytes.extend_from_slice(checksum.as_ref());
+
+        Ok(bytes)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_bytes()?).context("writing pack index")?;
+
+        Ok(())
+    }
+}
+
+struct IndexReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> IndexReader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + length)
+            .context("pack index ended early")?;
+
+        self.position += length;
+
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(first_byte: u8, offset: u64) -> PackIndexEntry {
+        let mut hash = [0; 20];
+
+        hash[0] = first_byte;
+
+        PackIndexEntry {
+            hash: Hash::new(hash),
+            crc32: 0xdead_beef,
+            offset,
+        }
+    }
+
+    #[test]
+    fn should_sort_entries_and_find_offsets() {
+        let index = PackIndex::new(vec![entry(9, 100), entry(1, 12)], Hash::default());
+
+        assert_eq!(index.entries()[0].offset, 12);
+        assert_eq!(index.find_offset(&entry(9, 0).hash), Some(100));
+        assert_eq!(index.find_offset(&entry(5, 0).hash), None);
+    }
+
+    #[test]
+    fn should_find_hashes_by_prefix() -> Result<()> {
+        let mut entries = vec![entry(0x12, 1), entry(0x13, 2), entry(0x21, 3)];
+
+        entries[1].hash = "1340000000000000000000000000000000000000".parse()?;
+
+        let index = PackIndex::new(entries, Hash::default());
+        let found = |prefix| {
+            index
+                .find_by_prefix(prefix)
+                .iter()
+                .map(|hash| hash.to_string()[..4].to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(found("1"), ["1200", "1340"]);
+        assert_eq!(found("134"), ["1340"]);
+        assert_eq!(found("13"), ["1340"]);
+        assert_eq!(found("130"), Vec::<String>::new());
+        assert_eq!(found("xyz"), Vec::<String>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn should_write_cumulative_fanout() -> Result<()> {
+        let index = PackIndex::new(
+            vec![entry(0, 12), entry(2, 40), entry(2, 80)],
+            Hash::default(),
+        );
+        let bytes = index.to_bytes()?;
+        let fanout =
+            |n: usize| u32::from_be_bytes(bytes[8 + n * 4..12 + n * 4].try_into().unwrap());
+
+        assert_eq!(&bytes[0..4], INDEX_SIGNATURE);
+        assert_eq!(fanout(0), 1);
+        assert_eq!(fanout(1), 1);
+        assert_eq!(fanout(2), 3);
+        assert_eq!(fanout(255), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn should_move_large_offsets_to_their_own_table() -> Result<()> {
+        let index = PackIndex::new(vec![entry(0, 1 << 33)], Hash::default());
+        let bytes = index.to_bytes()?;
+        let offsets_start = 8 + 256 * 4 + 20 + 4;
+        let offset = u32::from_be_bytes(bytes[offsets_start..offsets_start + 4].try_into()?);
+        let large_offset =
+            u64::from_be_bytes(bytes[offsets_start + 4..offsets_start + 12].try_into()?);
+
+        assert_eq!(offset, LARGE_OFFSET_FLAG);
+        assert_eq!(large_offset, 1 << 33);
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_what_it_writes() -> Result<()> {
+        let entries = vec![entry(0xff, 12), entry(0, 1 << 33), entry(7, 400)];
+        let index = PackIndex::new(entries, Hash::new([3; 20]));
+        let parsed = PackIndex::parse(&index.to_bytes()?)?;
+
+        assert_eq!(parsed.entries(), index.entries());
+        assert_eq!(parsed.pack_checksum(), index.pack_checksum());
+        assert_eq!(parsed.find_offset(&entry(0xff, 0).hash), Some(12));
+        assert_eq!(parsed.find_offset(&entry(0, 0).hash), Some(1 << 33));
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_corrupt_index() -> Result<()> {
+        let index = PackIndex::new(vec![entry(1, 12)], Hash::default());
+        let mut bytes = index.to_bytes()?;
+
+        bytes[8 + 256 * 4] ^= 1;
+
+        assert!(PackIndex::parse(&bytes).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_fanouts_the_index_cant_hold() -> Result<()> {
+        let index = PackIndex::new(vec![entry(1, 12)], Hash::default());
+        let bytes = index.to_bytes()?;
+        let mut huge = bytes.clone();
+        let mut shrinking = bytes;
+
+        huge[8 + 255 * 4..8 + 256 * 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        shrinking[8 + 4..8 + 8].copy_from_slice(&7_u32.to_be_bytes());
+
+        for bytes in [huge, shrinking] {
+            assert!(matches!(
+                PackIndex::parse(&bytes),
+                Err(Error::CorruptObject(_))
+            ));
+        }
+
+        Ok(())
+    }
+}
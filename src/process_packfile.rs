@@ -1,7 +1,8 @@
 // Implemented using https://dev.to/calebsander/git-internals-part-2-packfiles-1jg8 as a reference
 
-use anyhow::{bail, Context, Result};
-use std::io::{ErrorKind, Read};
+use crate::error::{Context, Error, Result};
+use std::collections::HashMap;
+use std::io::{Cursor, ErrorKind, Read};
 
 const VARINT_ENCODING_BITS: u8 = 7;
 const VARINT_CONTINUE_FLAG: u8 = 1 << VARINT_ENCODING_BITS;
@@ -11,6 +12,12 @@ const COPY_INSTRUCTION_FLAG: u8 = 1 << 7;
 const COPY_SIZE_BYTES: u8 = 3;
 const COPY_ZERO_SIZE: usize = 0x10000;
 const COPY_OFFSET_BYTES: u8 = 4;
+const INSERT_MAX_SIZE: usize = 0x7f;
+// 16 bytes is short enough to find matches in small edits but long enough to not match by chance
+const DELTA_BLOCK_SIZE: usize = 16;
+// sizes in pack and delta headers are only checked once the data is read, so buffers reserve at
+// most this much up front and grow as the data really arrives
+pub const MAX_PREALLOCATION: usize = 1 << 20;
 
 pub fn read_varint_byte<R: Read>(packfile_reader: &mut R) -> Result<(u8, bool)> {
     let mut bytes: [u8; 1] = [0];
@@ -30,17 +37,76 @@ pub fn read_size_encoding<R: Read>(packfile_reader: &mut R) -> Result<usize> {
 
     loop {
         let (byte_value, more_bytes) = read_varint_byte(packfile_reader)?;
+        // bits shifted past the top would be lost, so the size is corrupt rather than wrapped
+        let bits = (byte_value as usize)
+            .checked_shl(length)
+            .filter(|bits| bits >> length == byte_value as usize)
+            .ok_or_else(|| Error::corrupt("size encoding doesn't fit in 64 bits"))?;
 
-        value |= (byte_value as usize) << length;
+        value |= bits;
 
         if !more_bytes {
             return Ok(value);
         }
 
-        length += VARINT_ENCODING_BITS;
+        length += VARINT_ENCODING_BITS as u32;
     }
 }
 
+/// Reads the base offset of an ofs delta. Unlike the size encoding this is big endian, and
+/// every continuation adds one so that no two encodings describe the same offset.
+pub fn read_offset_encoding<R: Read>(packfile_reader: &mut R) -> Result<u64> {
+    let mut value = 0_u64;
+
+    loop {
+        let (byte_value, more_bytes) = read_varint_byte(packfile_reader)?;
+        let overflow = || Error::corrupt("offset encoding doesn't fit in 64 bits");
+
+        if value.leading_zeros() < VARINT_ENCODING_BITS as u32 {
+            return Err(overflow());
+        }
+
+        value = (value << VARINT_ENCODING_BITS) | byte_value as u64;
+
+        if !more_bytes {
+            return Ok(value);
+        }
+
+        value = value.checked_add(1).ok_or_else(overflow)?;
+    }
+}
+
+pub fn write_size_encoding(mut value: usize, bytes: &mut Vec<u8>) {
+    loop {
+        let byte = keep_bits(value, VARINT_ENCODING_BITS) as u8;
+
+        value >>= VARINT_ENCODING_BITS;
+
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+
+        bytes.push(byte | VARINT_CONTINUE_FLAG);
+    }
+}
+
+/// The inverse of `read_offset_encoding`.
+pub fn write_offset_encoding(mut value: u64, bytes: &mut Vec<u8>) {
+    let mut encoded = vec![keep_bits(value as usize, VARINT_ENCODING_BITS) as u8];
+
+    value >>= VARINT_ENCODING_BITS;
+
+    while value > 0 {
+        value -= 1;
+        encoded.push(keep_bits(value as usize, VARINT_ENCODING_BITS) as u8 | VARINT_CONTINUE_FLAG);
+        value >>= VARINT_ENCODING_BITS;
+    }
+
+    encoded.reverse();
+    bytes.extend(encoded);
+}
+
 pub fn keep_bits(value: usize, bits: u8) -> usize {
     value & ((1 << bits) - 1)
 }
@@ -54,6 +120,20 @@ pub fn read_type_and_size<R: Read>(packfile_reader: &mut R) -> Result<ObjectType
     Ok(ObjectType::new(object_type, size))
 }
 
+/// Writes the type and size header that starts every packfile entry.
+pub fn write_type_and_size(object_type: u8, size: usize, bytes: &mut Vec<u8>) {
+    let first_bits =
+        keep_bits(size, TYPE_BYTE_SIZE_BITS) | (object_type as usize) << TYPE_BYTE_SIZE_BITS;
+    let rest = size >> TYPE_BYTE_SIZE_BITS;
+
+    if rest == 0 {
+        bytes.push(first_bits as u8);
+    } else {
+        bytes.push(first_bits as u8 | VARINT_CONTINUE_FLAG);
+        write_size_encoding(rest, bytes);
+    }
+}
+
 pub fn read_size<R: Read>(packfile_reader: &mut R) -> Result<usize> {
     let value = read_size_encoding(packfile_reader).context("reading size encoding")?;
     let size = keep_bits(value, TYPE_BYTE_SIZE_BITS);
@@ -81,10 +161,7 @@ impl ObjectType {
             4 => Self::Tag(size),
             6 => Self::OfsDelta(size),
             7 => Self::RefDelta(size),
-            _ => {
-                eprintln!("Error, object type must be 1-7, got {object_type}");
-                Self::Unknown
-            }
+            _ => Self::Unknown,
         }
     }
 
@@ -96,15 +173,37 @@ impl ObjectType {
             ObjectType::Tag(size) => size,
             ObjectType::OfsDelta(size) => size,
             ObjectType::RefDelta(size) => size,
-            ObjectType::Unknown => unreachable!(),
+            ObjectType::Unknown => return None,
         })
     }
 
+    pub fn from_type(object_type: &str, size: usize) -> Self {
+        match object_type {
+            "commit" => Self::Commit(size),
+            "tree" => Self::Tree(size),
+            "blob" => Self::Blob(size),
+            "tag" => Self::Tag(size),
+            _ => Self::Unknown,
+        }
+    }
+
+    pub fn type_number(&self) -> u8 {
+        match self {
+            Self::Commit(_) => 1,
+            Self::Tree(_) => 2,
+            Self::Blob(_) => 3,
+            Self::Tag(_) => 4,
+            Self::OfsDelta(_) => 6,
+            Self::RefDelta(_) => 7,
+            Self::Unknown => 0,
+        }
+    }
+
     pub fn get_type(&self) -> &'static str {
         match self {
             Self::Commit(_) => "commit",
             Self::Tree(_) => "tree",
-            Self::Unknown => unreachable!(),
+            Self::Unknown => "unknown",
             ObjectType::Blob(_) => "blob",
             ObjectType::Tag(_) => "tag",
             ObjectType::OfsDelta(_) => "ofsdelta",
@@ -158,8 +257,7 @@ pub fn apply_delta_instruction<R: Read>(
 
     if instruction & COPY_INSTRUCTION_FLAG == 0 {
         if instruction == 0 {
-            eprintln!("got bad instruction :(");
-            bail!("invalid data instruction");
+            return Err(Error::corrupt("invalid data instruction"));
         }
 
         let mut data = vec![0; instruction as usize];
@@ -187,3 +285,238 @@ pub fn apply_delta_instruction<R: Read>(
 
     Ok(true)
 }
+
+/// Rebuilds an object from its base and the delta instructions that describe it.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut delta = Cursor::new(delta);
+    let base_object_size = read_size_encoding(&mut delta).context("reading base object size")?;
+    let new_object_size = read_size_encoding(&mut delta).context("reading new object size")?;
+
+    if base_object_size != base.len() {
+        return Err(Error::corrupt(format!(
+            "delta expects a base of {base_object_size} bytes, got {}",
+            base.len()
+        )));
+    }
+
+    let mut object = Vec::with_capacity(new_object_size.min(MAX_PREALLOCATION));
+
+    while apply_delta_instruction(&mut delta, base, &mut object)
+        .context("applying delta instruction")?
+    {}
+
+    if object.len() != new_object_size {
+        return Err(Error::corrupt(format!(
+            "delta should produce {new_object_size} bytes, got {}",
+            object.len()
+        )));
+    }
+
+    Ok(object)
+}
+
+/// Describes `target` as copy and insert instructions against `base`, the format
+/// `apply_delta` reads.
+pub fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = vec![];
+
+    write_size_encoding(base.len(), &mut delta);
+    write_size_encoding(target.len(), &mut delta);
+
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+
+    for (index, block) in base.chunks_exact(DELTA_BLOCK_SIZE).enumerate() {
+        blocks.entry(block).or_insert(index * DELTA_BLOCK_SIZE);
+    }
+
+    let mut insert = vec![];
+    let mut position = 0;
+
+    while position < target.len() {
+        let base_offset = target
+            .get(position..position + DELTA_BLOCK_SIZE)
+            .and_then(|block| blocks.get(block));
+
+        let Some(&base_offset) = base_offset else {
+            insert.push(target[position]);
+            position += 1;
+
+            if insert.len() == INSERT_MAX_SIZE {
+                write_insert_instruction(&insert, &mut delta);
+                insert.clear();
+            }
+
+            continue;
+        };
+
+        let length = base[base_offset..]
+            .iter()
+            .zip(&target[position..])
+            .take(COPY_ZERO_SIZE)
+            .take_while(|(base_byte, target_byte)| base_byte == target_byte)
+            .count();
+
+        if !insert.is_empty() {
+            write_insert_instruction(&insert, &mut delta);
+            insert.clear();
+        }
+
+        write_copy_instruction(base_offset, length, &mut delta);
+        position += length;
+    }
+
+    if !insert.is_empty() {
+        write_insert_instruction(&insert, &mut delta);
+    }
+
+    delta
+}
+
+fn write_insert_instruction(data: &[u8], delta: &mut Vec<u8>) {
+    delta.push(data.len() as u8);
+    delta.extend_from_slice(data);
+}
+
+fn write_copy_instruction(offset: usize, size: usize, delta: &mut Vec<u8>) {
+    let mut instruction = COPY_INSTRUCTION_FLAG;
+    let mut arguments = vec![];
+    // a size of 0x10000 is written as no size bytes at all
+    let size = if size == COPY_ZERO_SIZE { 0 } else { size };
+
+    for (flag_index, value, byte_count) in [
+        (0, offset, COPY_OFFSET_BYTES),
+        (COPY_OFFSET_BYTES, size, COPY_SIZE_BYTES),
+    ] {
+        for byte_index in 0..byte_count {
+            let byte = (value >> (byte_index * 8)) as u8;
+
+            if byte != 0 {
+                instruction |= 1 << (flag_index + byte_index);
+                arguments.push(byte);
+            }
+        }
+    }
+
+    delta.push(instruction);
+    delta.extend(arguments);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_single_byte_offset() -> Result<()> {
+        let mut bytes = Cursor::new([0x2a]);
+        let result = read_offset_encoding(&mut bytes)?;
+
+        assert_eq!(result, 42);
+        Ok(())
+    }
+
+    #[test]
+    fn should_read_multi_byte_offset() -> Result<()> {
+        // 0x81 0x00 => ((1 + 1) << 7) | 0
+        let mut bytes = Cursor::new([0x81, 0x00]);
+        let result = read_offset_encoding(&mut bytes)?;
+
+        assert_eq!(result, 256);
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_encodings_longer_than_64_bits() {
+        let mut too_long = vec![0xff; 10];
+        let mut just_fits = vec![0xff; 9];
+
+        too_long.push(0x7f);
+        just_fits.push(0x01);
+
+        assert!(read_size_encoding(&mut Cursor::new(&too_long)).is_err());
+        assert!(read_offset_encoding(&mut Cursor::new(&too_long)).is_err());
+        assert!(
+            read_size_encoding(&mut Cursor::new(&just_fits)).is_ok_and(|size| size == usize::MAX)
+        );
+    }
+
+    #[test]
+    fn should_apply_copy_and_insert_instructions() -> Result<()> {
+        let base = b"hello world";
+        // copy 5 bytes from offset 0, then insert "!"
+        let mut instructions = Cursor::new([0b1001_0000, 5, 1, b'!']);
+        let mut result = vec![];
+
+        while apply_delta_instruction(&mut instructions, base, &mut result)? {}
+
+        assert_eq!(result, b"hello!");
+        Ok(())
+    }
+
+    #[test]
+    fn should_apply_delta_to_base() -> Result<()> {
+        let base = b"hello world";
+        // base size, result size, copy 5 bytes from offset 0, insert "!"
+        let delta = [11, 6, 0b1001_0000, 5, 1, b'!'];
+        let result = apply_delta(base, &delta)?;
+
+        assert_eq!(result, b"hello!");
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_delta_for_wrong_base() {
+        let base = b"hello";
+        let delta = [11, 6, 0b1001_0000, 5, 1, b'!'];
+
+        assert!(apply_delta(base, &delta).is_err());
+    }
+
+    #[test]
+    fn should_round_trip_offset_encoding() -> Result<()> {
+        for offset in [0, 127, 128, 255, 16_511, 16_512, 1 << 40] {
+            let mut bytes = vec![];
+
+            write_offset_encoding(offset, &mut bytes);
+
+            assert_eq!(read_offset_encoding(&mut Cursor::new(bytes))?, offset);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_round_trip_type_and_size() -> Result<()> {
+        for size in [0, 15, 16, 2047, 1 << 30] {
+            let mut bytes = vec![];
+
+            write_type_and_size(3, size, &mut bytes);
+
+            let object_type = read_type_and_size(&mut Cursor::new(bytes))?;
+
+            assert_eq!(object_type.get_type(), "blob");
+            assert_eq!(object_type.get_size(), Some(size));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_create_delta_that_rebuilds_target() -> Result<()> {
+        let base = (0..2000).map(|n| format!("line {n}\n")).collect::<String>();
+        let target = base.replace("line 1000\n", "changed line\n") + "appended\n";
+        let delta = create_delta(base.as_bytes(), target.as_bytes());
+
+        assert!(delta.len() < target.len() / 10);
+        assert_eq!(apply_delta(base.as_bytes(), &delta)?, target.as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn should_create_delta_from_empty_base() -> Result<()> {
+        let target = vec![7; 300];
+        let delta = create_delta(&[], &target);
+
+        assert_eq!(apply_delta(&[], &delta)?, target);
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+use std::{collections::HashSet, fmt::Display, io::Write};
+
+use crate::{
+    config::Config,
+    error::{Context, Error, Result},
+    hash::Hash,
+    identity,
+    lockfile::LockFile,
+    object::Signature,
+    reflog::{self, ReflogEntry},
+    refs::{check_ref_name, RefTarget, Refs, HEAD},
+};
+
+/// What a ref has to hold for a change to go ahead
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    Anything,
+    Missing,
+    Hash(Hash),
+}
+
+impl Expected {
+    fn matches(&self, current: Option<&Hash>) -> bool {
+        match self {
+            Self::Anything => true,
+            Self::Missing => current.is_none(),
+            Self::Hash(hash) => current == Some(hash),
+        }
+    }
+}
+
+impl Display for Expected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Anything => write!(f, "anything"),
+            Self::Missing => write!(f, "no ref"),
+            Self::Hash(hash) => write!(f, "{hash}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    Update(Hash),
+    Delete,
+    Verify,
+}
+
+#[derive(Debug)]
+struct Change {
+    name: String,
+    action: Action,
+    expected: Expected,
+}
+
+/// A batch of ref changes that are applied all together or not at all. Every ref is locked and
+/// checked against its expected value before anything is written, so concurrent writers either
+/// wait their turn or fail with a conflict. Every update is recorded in the reflog.
+pub struct RefTransaction<'a> {
+    refs: &'a Refs,
+    changes: Vec<Change>,
+    message: String,
+    committer: Option<Signature>,
+}
+
+impl<'a> RefTransaction<'a> {
+    pub fn new(refs: &'a Refs) -> Self {
+        Self {
+            refs,
+            changes: vec![],
+            message: String::new(),
+            committer: None,
+        }
+    }
+
+    /// Why the refs are moving, like `commit: Fix the build`, for the reflog
+    pub fn message(&mut self, message: &str) -> &mut Self {
+        self.message = message.to_owned();
+        self
+    }
+
+    /// Who is moving the refs, the configured committer by default
+    pub fn committer(&mut self, committer: Signature) -> &mut Self {
+        self.committer = Some(committer);
+        self
+    }
+
+    pub fn update(&mut self, name: &str, new: Hash, expected: Expected) -> &mut Self {
+        self.push(name, Action::Update(new), expected)
+    }
+
+    /// Like `update`, but the ref must not exist yet
+    pub fn create(&mut self, name: &str, new: Hash) -> &mut Self {
+        self.push(name, Action::Update(new), Expected::Missing)
+    }
+
+    pub fn delete(&mut self, name: &str, expected: Expected) -> &mut Self {
+        self.push(name, Action::Delete, expected)
+    }
+
+    /// Fails the whole transaction unless `name` holds `expected`, without changing it
+    pub fn verify(&mut self, name: &str, expected: Expected) -> &mut Self {
+        self.push(name, Action::Verify, expected)
+    }
+
+    fn push(&mut self, name: &str, action: Action, expected: Expected) -> &mut Self {
+        self.changes.push(Change {
+            name: name.to_owned(),
+            action,
+            expected,
+        });
+
+        self
+    }
+
+    /// Applies every change, following symbolic refs so updating HEAD moves its branch.
+    /// Returns the names of the refs that were changed.
+    pub fn commit(self) -> Result<Vec<String>> {
+        let mut changes = vec![];
+        let mut seen = HashSet::new();
+        // moving the branch HEAD is on moves HEAD too, so its reflog gets the entry as well
+        let head_branch = match self.refs.read(HEAD)? {
+            Some(RefTarget::Symbolic(_)) => Some(self.refs.resolve_name(HEAD)?),
+            Some(RefTarget::Hash(_)) | None => None,
+        };
+
+        for change in self.changes {
+            let name = self.refs.resolve_name(&change.name)?;
+
+            check_ref_name(&name)?;
+
+            if !seen.insert(name.clone()) {
+                return Err(Error::InvalidRef(format!(
+                    "{name} is changed more than once in the same transaction"
+                )));
+            }
+
+            changes.push(Change { name, ..change });
+        }
+
+        // locking in a fixed order keeps two transactions from each holding what the other needs
+        changes.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut locks = vec![];
+
+        for change in &changes {
+            locks.push(LockFile::acquire(&self.refs.loose_path(&change.name))?);
+        }
+
+        let committer = match self.committer {
+            Some(committer) => committer,
+            None => identity::reflog_committer(&Config::load(Some(self.refs.git_directory()))?)?,
+        };
+        let mut entries = vec![];
+
+        for (change, lock) in changes.iter().zip(locks.iter_mut()) {
+            let current = match self.refs.read(&change.name)? {
+                Some(RefTarget::Hash(hash)) => Some(hash),
+                Some(RefTarget::Symbolic(_)) | None => None,
+            };
+
+            if !change.expected.matches(current.as_ref()) {
+                return Err(Error::RefConflict {
+                    name: change.name.clone(),
+                    expected: change.expected.to_string(),
+                    actual: current.map_or_else(|| "no ref".to_owned(), |hash| hash.to_string()),
+                });
+            }
+
+            if let Action::Update(hash) = &change.action {
+                lock.write_all(format!("{hash}\n").as_bytes())
+                    .with_context(|| format!("writing ref {}", change.name))?;
+                entries.push((
+                    change.name.as_str(),
+                    ReflogEntry::new(current, hash.clone(), committer.clone(), &self.message),
+                ));
+            }
+        }
+
+        let git_directory = self.refs.git_directory();
+
+        for (name, entry) in &entries {
+            reflog::append(git_directory, name, entry)?;
+
+            if *name != HEAD && head_branch.as_deref() == Some(*name) {
+                reflog::append(git_directory, HEAD, entry)?;
+            }
+        }
+
+        let deleted = changes
+            .iter()
+            .filter(|change| change.action == Action::Delete)
+            .map(|change| change.name.as_str())
+            .collect::<HashSet<_>>();
+        let packed_refs = self.refs.packed_refs()?;
+
+        if packed_refs
+            .iter()
+            .any(|packed_ref| deleted.contains(packed_ref.name.as_str()))
+        {
+            let remaining = packed_refs
+                .into_iter()
+                .filter(|packed_ref| !deleted.contains(packed_ref.name.as_str()))
+                .collect::<Vec<_>>();
+
+            self.refs.write_packed_refs(&remaining)?;
+        }
+
+        let mut changed = vec![];
+
+        for (change, lock) in changes.into_iter().zip(locks) {
+            match change.action {
+                Action::Update(_) => lock.commit()?,
+                Action::Delete => {
+                    self.refs.remove_loose(&change.name, lock)?;
+                    reflog::delete(git_directory, &change.name)?;
+                }
+                Action::Verify => continue,
+            }
+
+            changed.push(change.name);
+        }
+
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_directory;
+
+    fn create_refs(name: &str) -> Result<Refs> {
+        let git_directory = temp_directory(&format!("ref-transaction-{name}"));
+
+        std::fs::create_dir_all(git_directory.join("refs"))?;
+
+        let refs = Refs::new(git_directory);
+
+        refs.write("HEAD", &RefTarget::Symbolic("refs/heads/master".to_owned()))?;
+
+        Ok(refs)
+    }
+
+    #[test]
+    fn should_apply_all_changes_together() -> Result<()> {
+        let refs = create_refs("apply")?;
+        let mut transaction = RefTransaction::new(&refs);
+
+        transaction
+            .create("HEAD", Hash::new([1; 20]))
+            .create("refs/heads/feature", Hash::new([2; 20]));
+
+        assert_eq!(
+            transaction.commit()?,
+            vec!["refs/heads/feature", "refs/heads/master"]
+        );
+
+        let mut transaction = RefTransaction::new(&refs);
+
+        transaction
+            .update(
+                "refs/heads/master",
+                Hash::new([3; 20]),
+                Expected::Hash(Hash::new([1; 20])),
+            )
+            .delete("refs/heads/feature", Expected::Anything);
+        transaction.commit()?;
+
+        assert_eq!(
+            refs.list("refs/")?,
+            vec![("refs/heads/master".to_owned(), Hash::new([3; 20]))]
+        );
+        assert_eq!(refs.reflog("HEAD")?.len(), 2);
+        assert_eq!(refs.reflog("refs/heads/master")?[1].old, Hash::new([1; 20]));
+        assert!(refs.reflog("refs/heads/feature")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn should_change_nothing_on_conflict() -> Result<()> {
+        let refs = create_refs("conflict")?;
+
+        refs.update("refs/heads/master", &Hash::new([1; 20]), "test")?;
+
+        let mut transaction = RefTransaction::new(&refs);
+
+        transaction
+            .create("refs/heads/feature", Hash::new([2; 20]))
+            .update(
+                "refs/heads/master",
+                Hash::new([3; 20]),
+                Expected::Hash(Hash::new([9; 20])),
+            );
+
+        assert!(matches!(
+            transaction.commit(),
+            Err(Error::RefConflict { .. })
+        ));
+        assert_eq!(
+            refs.list("refs/")?,
+            vec![("refs/heads/master".to_owned(), Hash::new([1; 20]))]
+        );
+        assert!(!refs.loose_path("refs/heads/master.lock").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn should_fail_when_a_ref_is_locked() -> Result<()> {
+        let refs = create_refs("locked")?;
+        let _lock = LockFile::acquire(&refs.loose_path("refs/heads/master"))?;
+        let mut transaction = RefTransaction::new(&refs);
+
+        transaction.create("refs/heads/master", Hash::new([1; 20]));
+
+        assert!(matches!(transaction.commit(), Err(Error::Locked(_))));
+        assert_eq!(refs.resolve("HEAD")?, None);
+        Ok(())
+    }
+}
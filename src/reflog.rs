@@ -0,0 +1,209 @@
+// The reflog format is described at https://git-scm.com/docs/git-reflog
+
+use std::{
+    fmt::Display,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Context, Error, Result},
+    hash::Hash,
+    object::Signature,
+    utils::remove_empty_directories,
+};
+
+/// One movement of a ref, a line in `.vc/logs/<ref>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflogEntry {
+    /// The all zero hash when the ref was created
+    pub old: Hash,
+    pub new: Hash,
+    pub committer: Signature,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    pub fn new(old: Option<Hash>, new: Hash, committer: Signature, message: &str) -> Self {
+        Self {
+            old: old.unwrap_or_default(),
+            new,
+            committer,
+            // each entry has to stay on its own line
+            message: message.split_whitespace().collect::<Vec<_>>().join(" "),
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Self> {
+        let invalid = || Error::corrupt(format!("reflog line {line:?}"));
+        let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+        let (old, rest) = header.split_once(' ').ok_or_else(invalid)?;
+        let (new, committer) = rest.split_once(' ').ok_or_else(invalid)?;
+
+        Ok(Self {
+            old: old.parse().map_err(|_error| invalid())?,
+            new: new.parse().map_err(|_error| invalid())?,
+            committer: Signature::parse(committer.as_bytes())?,
+            message: message.to_owned(),
+        })
+    }
+}
+
+impl Display for ReflogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}\t{}",
+            self.old, self.new, self.committer, self.message
+        )
+    }
+}
+
+fn path(git_directory: &Path, name: &str) -> PathBuf {
+    git_directory.join("logs").join(name)
+}
+
+/// Every entry for `name`, oldest first. Refs that never moved have no entries.
+pub fn read(git_directory: &Path, name: &str) -> Result<Vec<ReflogEntry>> {
+    let path = path(git_directory, name);
+
+    if !path.is_file() {
+        return Ok(vec![]);
+    }
+
+    std::fs::read_to_string(path)
+        .with_context(|| format!("reading reflog for {name}"))?
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(ReflogEntry::parse)
+        .collect()
+}
+
+pub fn append(git_directory: &Path, name: &str, entry: &ReflogEntry) -> Result<()> {
+    let path = path(git_directory, name);
+
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory).context("creating reflog directory")?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("opening reflog for {name}"))?;
+
+    writeln!(file, "{entry}").with_context(|| format!("appending to reflog for {name}"))
+}
+
+/// Forgets the history of a deleted ref
+pub fn delete(git_directory: &Path, name: &str) -> Result<()> {
+    let path = path(git_directory, name);
+
+    if path.is_file() {
+        std::fs::remove_file(&path).with_context(|| format!("removing reflog for {name}"))?;
+    }
+
+    for directory in path.ancestors().skip(1) {
+        if directory == git_directory.join("logs") || std::fs::remove_dir(directory).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves the history of `old` over to `new`, for renamed branches. Whatever `new` had is
+/// replaced.
+pub fn rename(git_directory: &Path, old: &str, new: &str) -> Result<()> {
+    let old_path = path(git_directory, old);
+    let new_path = path(git_directory, new);
+
+    if !old_path.is_file() {
+        return Ok(());
+    }
+
+    if let Some(directory) = new_path.parent() {
+        std::fs::create_dir_all(directory).context("creating reflog directory")?;
+    }
+
+    std::fs::rename(&old_path, &new_path)
+        .with_context(|| format!("moving reflog for {old} to {new}"))?;
+    remove_empty_directories(&old_path, &git_directory.join("logs"));
+
+    Ok(())
+}
+
+/// Prints the reflog newest first, the way `git reflog show` does.
+pub fn show(
+    git_directory: &Path,
+    name: &str,
+    display_name: &str,
+    output: &mut impl Write,
+) -> Result<()> {
+    for (index, entry) in read(git_directory, name)?.iter().rev().enumerate() {
+        writeln!(
+            output,
+            "{} {display_name}@{{{index}}}: {}",
+            &entry.new.to_string()[..7],
+            entry.message
+        )?;
+    }
+
+    output.flush().context("flushing reflog output")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_directory;
+
+    #[test]
+    fn should_round_trip_entries() -> Result<()> {
+        let git_directory = temp_directory("reflog");
+        let committer = Signature::new("A U Thor", "author@example.com", 1700000000, -420);
+        let first = ReflogEntry::new(None, Hash::new([1; 20]), committer.clone(), "commit: one");
+        let second = ReflogEntry::new(
+            Some(Hash::new([1; 20])),
+            Hash::new([2; 20]),
+            committer,
+            "commit: two\n\nwith a body",
+        );
+
+        append(&git_directory, "refs/heads/master", &first)?;
+        append(&git_directory, "refs/heads/master", &second)?;
+
+        assert_eq!(
+            std::fs::read_to_string(git_directory.join("logs/refs/heads/master"))?
+                .lines()
+                .next(),
+            Some(
+                "0000000000000000000000000000000000000000 \
+                 0101010101010101010101010101010101010101 \
+                 A U Thor <author@example.com> 1700000000 -0700\tcommit: one"
+            )
+        );
+        assert_eq!(
+            read(&git_directory, "refs/heads/master")?,
+            vec![first, second.clone()]
+        );
+        assert_eq!(second.message, "commit: two with a body");
+
+        let mut output = vec![];
+
+        show(&git_directory, "refs/heads/master", "master", &mut output)?;
+
+        assert_eq!(
+            String::from_utf8(output)?,
+            "0202020 master@{0}: commit: two with a body\n0101010 master@{1}: commit: one\n"
+        );
+
+        delete(&git_directory, "refs/heads/master")?;
+
+        assert!(read(&git_directory, "refs/heads/master")?.is_empty());
+        assert!(!git_directory.join("logs/refs").exists());
+        Ok(())
+    }
+}
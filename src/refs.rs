@@ -0,0 +1,449 @@
+// Ref storage is described at https://git-scm.com/docs/gitrepository-layout
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Context, Error, Result},
+    hash::Hash,
+    lockfile::LockFile,
+    ref_transaction::{Expected, RefTransaction},
+    reflog::{self, ReflogEntry},
+    utils::remove_empty_directories,
+};
+
+pub const HEAD: &str = "HEAD";
+const SYMBOLIC_PREFIX: &str = "ref: ";
+const PACKED_REFS_FILE: &str = "packed-refs";
+const PACKED_REFS_HEADER: &str = "# pack-refs with: peeled fully-peeled sorted \n";
+// git gives up after this many hops too, it's almost certainly a loop
+const MAX_SYMBOLIC_DEPTH: usize = 5;
+
+/// What a ref holds: a hash, or the name of another ref like HEAD's `ref: refs/heads/master`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefTarget {
+    Hash(Hash),
+    Symbolic(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedRef {
+    pub name: String,
+    pub hash: Hash,
+    /// What an annotated tag points at, from the `^` line after it
+    pub peeled: Option<Hash>,
+}
+
+/// Loose refs under `.vc/refs`, plus `.vc/packed-refs`. Loose refs win when a ref is in both.
+pub struct Refs {
+    git_directory: PathBuf,
+}
+
+impl Refs {
+    pub fn new(git_directory: PathBuf) -> Self {
+        Self { git_directory }
+    }
+
+    pub fn git_directory(&self) -> &Path {
+        &self.git_directory
+    }
+
+    /// Where `name` used to point, oldest first
+    pub fn reflog(&self, name: &str) -> Result<Vec<ReflogEntry>> {
+        check_ref_name(name)?;
+        reflog::read(&self.git_directory, name)
+    }
+
+    pub(crate) fn loose_path(&self, name: &str) -> PathBuf {
+        self.git_directory.join(name)
+    }
+
+    /// Reads a single ref without following it if it's symbolic.
+    pub fn read(&self, name: &str) -> Result<Option<RefTarget>> {
+        check_ref_name(name)?;
+
+        let path = self.loose_path(name);
+
+        if path.is_file() {
+            let content =
+                std::fs::read_to_string(&path).with_context(|| format!("reading ref {name}"))?;
+
+            return parse_ref(name, &content).map(Some);
+        }
+
+        Ok(self
+            .packed_refs()?
+            .into_iter()
+            .find(|packed_ref| packed_ref.name == name)
+            .map(|packed_ref| RefTarget::Hash(packed_ref.hash)))
+    }
+
+    /// The hash a ref ends up at after following symbolic refs, or `None` for refs that don't
+    /// exist yet, like the branch of a new repository.
+    pub fn resolve(&self, name: &str) -> Result<Option<Hash>> {
+        let name = self.resolve_name(name)?;
+
+        match self.read(&name)? {
+            Some(RefTarget::Hash(hash)) => Ok(Some(hash)),
+            Some(RefTarget::Symbolic(_)) | None => Ok(None),
+        }
+    }
+
+    /// The name of the ref `name` finally points at, e.g. `refs/heads/master` for `HEAD`.
+    /// The ref itself doesn't have to exist.
+    pub fn resolve_name(&self, name: &str) -> Result<String> {
+        let mut name = name.to_owned();
+
+        for _ in 0..=MAX_SYMBOLIC_DEPTH {
+            match self.read(&name)? {
+                Some(RefTarget::Symbolic(target)) => name = target,
+                Some(RefTarget::Hash(_)) | None => return Ok(name),
+            }
+        }
+
+        Err(Error::InvalidRef(format!(
+            "{name} is nested too deeply in symbolic refs"
+        )))
+    }
+
+    /// Writes `name` exactly as given, replacing a symbolic ref rather than following it.
+    pub fn write(&self, name: &str, target: &RefTarget) -> Result<()> {
+        check_ref_name(name)?;
+
+        let content = match target {
+            RefTarget::Hash(hash) => format!("{hash}\n"),
+            RefTarget::Symbolic(target) => {
+                check_ref_name(target)?;
+                format!("{SYMBOLIC_PREFIX}{target}\n")
+            }
+        };
+        let mut lock = LockFile::acquire(&self.loose_path(name))?;
+
+        lock.write_all(content.as_bytes())
+            .with_context(|| format!("writing ref {name}"))?;
+        lock.commit()
+    }
+
+    /// Points `name` at `hash`, following symbolic refs so updating HEAD moves its branch.
+    /// Returns the name of the ref that was written.
+    pub fn update(&self, name: &str, hash: &Hash, message: &str) -> Result<String> {
+        let mut transaction = self.transaction();
+
+        transaction
+            .update(name, hash.clone(), Expected::Anything)
+            .message(message);
+
+        let mut changed = transaction.commit()?;
+
+        Ok(changed.remove(0))
+    }
+
+    /// Removes a ref from both the loose refs and `packed-refs`.
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let mut transaction = self.transaction();
+
+        transaction.delete(name, Expected::Anything);
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    pub fn transaction(&self) -> RefTransaction<'_> {
+        RefTransaction::new(self)
+    }
+
+    /// Removes the loose file for `name` while its lock is held, then the lock and any
+    /// directories left empty.
+    pub(crate) fn remove_loose(&self, name: &str, lock: LockFile) -> Result<()> {
+        let path = self.loose_path(name);
+
+        if path.is_file() {
+            std::fs::remove_file(&path).with_context(|| format!("removing ref {name}"))?;
+        }
+
+        // the lock file lives next to the ref, so it has to go before the directories can
+        drop(lock);
+        remove_empty_directories(&path, &self.git_directory.join("refs"));
+
+        Ok(())
+    }
+
+    /// Every ref starting with `prefix`, like `refs/heads/`, sorted by name. Symbolic refs are
+    /// listed with the hash they resolve to, and left out when they dangle.
+    pub fn list(&self, prefix: &str) -> Result<Vec<(String, Hash)>> {
+        let mut names = BTreeMap::new();
+
+        for packed_ref in self.packed_refs()? {
+            names.insert(packed_ref.name, ());
+        }
+
+        let mut directories = vec![self.git_directory.join("refs")];
+
+        while let Some(directory) = directories.pop() {
+            if !directory.is_dir() {
+                continue;
+            }
+
+            for entry in std::fs::read_dir(&directory).context("listing refs")? {
+                let path = entry?.path();
+
+                if path.is_dir() {
+                    directories.push(path);
+                } else if path.extension().is_none_or(|extension| extension != "lock") {
+                    names.insert(self.ref_name(&path)?, ());
+                }
+            }
+        }
+
+        let mut refs = vec![];
+
+        for name in names.into_keys().filter(|name| name.starts_with(prefix)) {
+            if let Some(hash) = self.resolve(&name)? {
+                refs.push((name, hash));
+            }
+        }
+
+        Ok(refs)
+    }
+
+    fn ref_name(&self, path: &Path) -> Result<String> {
+        let relative = path
+            .strip_prefix(&self.git_directory)
+            .map_err(|_error| Error::InvalidRef(path.display().to_string()))?;
+        let components = relative
+            .components()
+            .map(|component| {
+                component
+                    .as_os_str()
+                    .to_str()
+                    .ok_or_else(|| Error::InvalidRef(relative.display().to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(components.join("/"))
+    }
+
+    pub fn packed_refs(&self) -> Result<Vec<PackedRef>> {
+        let path = self.git_directory.join(PACKED_REFS_FILE);
+
+        if !path.is_file() {
+            return Ok(vec![]);
+        }
+
+        let content = std::fs::read_to_string(path).context("reading packed refs")?;
+
+        parse_packed_refs(&content)
+    }
+
+    pub(crate) fn write_packed_refs(&self, packed_refs: &[PackedRef]) -> Result<()> {
+        let mut content = PACKED_REFS_HEADER.to_owned();
+
+        for packed_ref in packed_refs {
+            content.push_str(&format!("{} {}\n", packed_ref.hash, packed_ref.name));
+
+            if let Some(peeled) = &packed_ref.peeled {
+                content.push_str(&format!("^{peeled}\n"));
+            }
+        }
+
+        let mut lock = LockFile::acquire(&self.git_directory.join(PACKED_REFS_FILE))?;
+
+        lock.write_all(content.as_bytes())
+            .context("writing packed refs")?;
+        lock.commit()
+    }
+}
+
+fn parse_ref(name: &str, content: &str) -> Result<RefTarget> {
+    let content = content.trim_end();
+
+    match content.strip_prefix(SYMBOLIC_PREFIX) {
+        Some(target) => Ok(RefTarget::Symbolic(target.to_owned())),
+        None => content
+            .parse()
+            .map(RefTarget::Hash)
+            .map_err(|_error| Error::InvalidRef(format!("{name} holds {content:?}"))),
+    }
+}
+
+fn parse_packed_refs(content: &str) -> Result<Vec<PackedRef>> {
+    let mut packed_refs: Vec<PackedRef> = vec![];
+
+    for line in content.lines() {
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+
+        let invalid = || Error::InvalidRef(format!("packed-refs line {line:?}"));
+
+        if let Some(peeled) = line.strip_prefix('^') {
+            let last = packed_refs.last_mut().ok_or_else(invalid)?;
+
+            last.peeled = Some(peeled.parse().map_err(|_error| invalid())?);
+            continue;
+        }
+
+        let (hash, name) = line.split_once(' ').ok_or_else(invalid)?;
+
+        packed_refs.push(PackedRef {
+            name: name.to_owned(),
+            hash: hash.parse().map_err(|_error| invalid())?,
+            peeled: None,
+        });
+    }
+
+    Ok(packed_refs)
+}
+
+/// Checks `name` against git's rules for ref names, see `git check-ref-format`.
+pub fn check_ref_name(name: &str) -> Result<()> {
+    let invalid = |reason: &str| Err(Error::InvalidRef(format!("{name:?} {reason}")));
+
+    // top level refs like HEAD and ORIG_HEAD are all capitals
+    if !name.contains('/') {
+        if name.is_empty()
+            || !name
+                .bytes()
+                .all(|byte| byte.is_ascii_uppercase() || byte == b'_')
+        {
+            return invalid("is not a valid top level ref");
+        }
+
+        return Ok(());
+    }
+
+    if name
+        .bytes()
+        .any(|byte| byte.is_ascii_control() || b" ~^:?*[\\".contains(&byte) || byte == 0x7f)
+    {
+        return invalid("contains a forbidden character");
+    }
+
+    if name.contains("..") || name.contains("@{") || name == "@" {
+        return invalid("contains a forbidden sequence");
+    }
+
+    if name.ends_with('/') || name.ends_with('.') || name.ends_with(".lock") {
+        return invalid("has a forbidden ending");
+    }
+
+    if name
+        .split('/')
+        .any(|component| component.is_empty() || component.starts_with('.'))
+    {
+        return invalid("has an empty or hidden component");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_directory;
+
+    fn create_refs(name: &str) -> Result<Refs> {
+        let git_directory = temp_directory(&format!("refs-{name}"));
+
+        std::fs::create_dir_all(git_directory.join("refs"))?;
+
+        let refs = Refs::new(git_directory);
+
+        refs.write(HEAD, &RefTarget::Symbolic("refs/heads/master".to_owned()))?;
+
+        Ok(refs)
+    }
+
+    #[test]
+    fn should_follow_symbolic_refs() -> Result<()> {
+        let refs = create_refs("symbolic")?;
+
+        assert_eq!(refs.resolve(HEAD)?, None);
+        assert_eq!(refs.resolve_name(HEAD)?, "refs/heads/master");
+
+        assert_eq!(
+            refs.update(HEAD, &Hash::new([1; 20]), "test")?,
+            "refs/heads/master"
+        );
+        assert_eq!(refs.resolve(HEAD)?, Some(Hash::new([1; 20])));
+        assert_eq!(
+            refs.read(HEAD)?,
+            Some(RefTarget::Symbolic("refs/heads/master".to_owned()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_symbolic_loops() -> Result<()> {
+        let refs = create_refs("loop")?;
+
+        refs.write("refs/heads/master", &RefTarget::Symbolic("HEAD".to_owned()))?;
+
+        assert!(matches!(refs.resolve(HEAD), Err(Error::InvalidRef(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn should_list_loose_and_packed_refs() -> Result<()> {
+        let refs = create_refs("list")?;
+
+        std::fs::write(
+            refs.git_directory.join(PACKED_REFS_FILE),
+            format!(
+                "{PACKED_REFS_HEADER}{} refs/heads/master\n{} refs/tags/v1\n^{}\n",
+                Hash::new([1; 20]),
+                Hash::new([2; 20]),
+                Hash::new([3; 20])
+            ),
+        )?;
+        refs.write("refs/heads/feature/x", &RefTarget::Hash(Hash::new([4; 20])))?;
+        refs.write("refs/heads/master", &RefTarget::Hash(Hash::new([5; 20])))?;
+
+        assert_eq!(
+            refs.list("refs/heads/")?,
+            vec![
+                ("refs/heads/feature/x".to_owned(), Hash::new([4; 20])),
+                ("refs/heads/master".to_owned(), Hash::new([5; 20])),
+            ]
+        );
+        assert_eq!(refs.packed_refs()?[1].peeled, Some(Hash::new([3; 20])));
+
+        refs.delete("refs/heads/master")?;
+        refs.delete("refs/heads/feature/x")?;
+
+        assert_eq!(
+            refs.list("refs/")?,
+            vec![("refs/tags/v1".to_owned(), Hash::new([2; 20]))]
+        );
+        assert!(!refs.git_directory.join("refs/heads/feature").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn should_check_ref_names() {
+        for valid in [
+            "HEAD",
+            "ORIG_HEAD",
+            "refs/heads/master",
+            "refs/heads/feature/x-1",
+        ] {
+            assert!(check_ref_name(valid).is_ok(), "{valid}");
+        }
+
+        for invalid in [
+            "head",
+            "refs/heads/a..b",
+            "refs/heads/a b",
+            "refs/heads/.hidden",
+            "refs/heads/x.lock",
+            "refs/heads//x",
+            "refs/heads/x/",
+            "refs/heads/a@{1}",
+        ] {
+            assert!(check_ref_name(invalid).is_err(), "{invalid}");
+        }
+    }
+}
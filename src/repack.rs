@@ -0,0 +1,355 @@
+use crate::error::{Context, Result};
+use flate2::Compression;
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    hash::Hash,
+    object::Object,
+    object_store::{LooseObjectStore, ObjectStore},
+    pack::PackWriter,
+    pack_index::PackIndex,
+    process_packfile::create_delta,
+    refs::{Refs, HEAD},
+    repository::Repository,
+    tree::TreeObjectType,
+    utils::compression_level,
+};
+
+// how many of the previous objects are tried as a delta base, like `git repack --window`
+const DELTA_WINDOW: usize = 10;
+// longest chain of deltas a reader has to follow, like `git repack --depth`
+const MAX_DELTA_DEPTH: usize = 50;
+
+#[derive(Debug)]
+pub struct RepackSummary {
+    pub pack_path: PathBuf,
+    pub object_count: usize,
+    pub delta_count: usize,
+    pub removed_loose_objects: usize,
+    pub removed_packs: usize,
+}
+
+/// An object to pack. Its data is read again when it's needed, so repacking doesn't hold the
+/// whole repository in memory.
+struct PackCandidate {
+    hash: Hash,
+    object_type: &'static str,
+    size: usize,
+    name_hash: u32,
+    delta: Option<(usize, Vec<u8>)>,
+    depth: usize,
+}
+
+/// Writes every object reachable from HEAD and the refs into a single packfile, then
+/// removes the loose objects and packs it replaces. Unreachable loose objects are left alone.
+pub fn repack(repository: &Repository) -> Result<Option<RepackSummary>> {
+    let git_directory = repository.git_directory();
+    let tips = find_tips(repository.refs()).context("finding refs to pack from")?;
+    let objects = repository.objects();
+    let mut candidates = collect_reachable(objects, tips).context("walking objects")?;
+
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+
+    // similar objects end up next to each other, so the window finds good delta bases
+    candidates.sort_by(|a, b| {
+        a.object_type
+            .cmp(b.object_type)
+            .then(a.name_hash.cmp(&b.name_hash))
+            .then(b.size.cmp(&a.size))
+    });
+
+    find_deltas(objects, &mut candidates).context("finding deltas")?;
+
+    let pack_directory = git_directory.join("objects").join("pack");
+
+    std::fs::create_dir_all(&pack_directory).context("creating pack directory")?;
+
+    let temporary_path = pack_directory.join("tmp_pack_repack");
+    let compression = compression_level(repository.config(), "pack.compression")?;
+    let index = write_pack(objects, &candidates, &temporary_path, compression)
+        .context("writing packfile")?;
+    let name = format!("pack-{}", index.pack_checksum());
+    let pack_path = pack_directory.join(format!("{name}.pack"));
+    let temporary_index_path = pack_directory.join("tmp_idx_repack");
+
+    // like git, the index goes last, since readers only look for packs that have one
+    std::fs::rename(&temporary_path, &pack_path).context("renaming packfile")?;
+    index
+        .write(&temporary_index_path)
+        .context("writing pack index")?;
+    std::fs::rename(&temporary_index_path, pack_path.with_extension("idx"))
+        .context("renaming pack index")?;
+
+    let packed = candidates
+        .iter()
+        .map(|candidate| candidate.hash.clone())
+        .collect::<HashSet<_>>();
+    let removed_packs =
+        remove_redundant_packs(&pack_directory, &pack_path, &packed).context("removing packs")?;
+    let loose = LooseObjectStore::new(git_directory.join("objects"));
+    let removed_loose_objects =
+        remove_loose_objects(&loose, &packed).context("removing loose objects")?;
+
+    Ok(Some(RepackSummary {
+        pack_path,
+        object_count: candidates.len(),
+        delta_count: candidates
+            .iter()
+            .filter(|candidate| candidate.delta.is_some())
+            .count(),
+        removed_loose_objects,
+        removed_packs,
+    }))
+}
+
+/// HEAD, every loose ref under `refs` and everything in `packed-refs`.
+fn find_tips(refs: &Refs) -> Result<Vec<Hash>> {
+    // HEAD is usually a branch we list anyway, but a detached HEAD is a tip of its own
+    let mut tips = refs.resolve(HEAD)?.into_iter().collect::<Vec<_>>();
+
+    for (_name, hash) in refs.list("refs/")? {
+        tips.push(hash);
+    }
+
+    Ok(tips)
+}
+
+fn collect_reachable(objects: &impl ObjectStore, tips: Vec<Hash>) -> Result<Vec<PackCandidate>> {
+    let mut candidates = vec![];
+    let mut seen = HashSet::new();
+    let mut pending = tips
+        .into_iter()
+        .map(|hash| (hash, String::new()))
+        .collect::<Vec<_>>();
+
+    while let Some((hash, path)) = pending.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+
+        let (object_type, data) = objects.read_existing(&hash)?;
+
+        match Object::parse(object_type, &data).with_context(|| format!("parsing {hash}"))? {
+            Object::Commit(commit) => {
+                pending.push((commit.tree, String::new()));
+                pending.extend(
+                    commit
+                        .parents
+                        .into_iter()
+                        .map(|parent| (parent, String::new())),
+                );
+            }
+            Object::Tag(tag) => pending.push((tag.object, String::new())),
+            Object::Tree(tree) => {
+                for tree_object in tree.tree_objects {
+                    if let TreeObjectType::Commit = tree_object.object_type {
+                        continue;
+                    }
+
+                    let path = match path.as_str() {
+                        "" => tree_object.name().into_owned(),
+                        _ => format!("{path}/{}", tree_object.name()),
+                    };
+
+                    pending.push((tree_object.checksum, path));
+                }
+            }
+            Object::Blob(_) => {}
+        }
+
+        candidates.push(PackCandidate {
+            hash,
+            object_type,
+            size: data.len(),
+            name_hash: name_hash(&path),
+            delta: None,
+            depth: 0,
+        });
+    }
+
+    Ok(candidates)
+}
+
+/// git's path hash, which mostly depends on the last characters, so files with the same
+/// name or extension sort near each other.
+fn name_hash(path: &str) -> u32 {
+    path.bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .fold(0, |hash: u32, byte| {
+            (hash >> 2).wrapping_add((byte as u32) << 24)
+        })
+}
+
+/// Tries each candidate against the ones just before it, keeping only their data in memory.
+fn find_deltas(objects: &impl ObjectStore, candidates: &mut [PackCandidate]) -> Result<()> {
+    let mut window = VecDeque::<(usize, Vec<u8>)>::with_capacity(DELTA_WINDOW);
+
+    for index in 0..candidates.len() {
+        let target = &candidates[index];
+        let (_object_type, data) = objects.read_existing(&target.hash)?;
+        // a delta has to save a decent amount to be worth the extra work reading it
+        let mut best_size = (data.len() / 2).saturating_sub(20);
+        let mut best = None;
+
+        for (base_index, base_data) in &window {
+            let base = &candidates[*base_index];
+
+            if base.object_type != target.object_type || base.depth >= MAX_DELTA_DEPTH {
+                continue;
+            }
+
+            let delta = create_delta(base_data, &data);
+
+            if delta.len() < best_size {
+                best_size = delta.len();
+                best = Some((*base_index, delta));
+            }
+        }
+
+        if let Some((base_index, delta)) = best {
+            candidates[index].depth = candidates[base_index].depth + 1;
+            candidates[index].delta = Some((base_index, delta));
+        }
+
+        if window.len() == DELTA_WINDOW {
+            window.pop_front();
+        }
+
+        window.push_back((index, data));
+    }
+
+    Ok(())
+}
+
+fn write_pack(
+    objects: &impl ObjectStore,
+    candidates: &[PackCandidate],
+    path: &Path,
+    compression: Compression,
+) -> Result<PackIndex> {
+    let file = BufWriter::new(File::create(path).context("creating packfile")?);
+    let object_count = u32::try_from(candidates.len()).context("too many objects to pack")?;
+    let mut writer = PackWriter::new(file, object_count)?;
+
+    writer.set_compression(compression);
+    let mut offsets = Vec::with_capacity(candidates.len());
+
+    // bases always come earlier in the window, so they're written before their deltas
+    for candidate in candidates {
+        let offset = match &candidate.delta {
+            Some((base_index, delta)) => {
+                writer.write_ofs_delta(candidate.hash.clone(), offsets[*base_index], delta)?
+            }
+            None => {
+                let (object_type, data) = objects.read_existing(&candidate.hash)?;
+
+                writer.write_object(candidate.hash.clone(), object_type, &data)?
+            }
+        };
+
+        offsets.push(offset);
+    }
+
+    writer.finish()
+}
+
+/// Removes packs whose objects are all in the new pack.
+fn remove_redundant_packs(
+    pack_directory: &Path,
+    new_pack_path: &Path,
+    packed: &HashSet<Hash>,
+) -> Result<usize> {
+    let mut removed = 0;
+
+    for entry in std::fs::read_dir(pack_directory)? {
+        let index_path = entry?.path();
+
+        if index_path
+            .extension()
+            .is_none_or(|extension| extension != "idx")
+            || index_path == new_pack_path.with_extension("idx")
+        {
+            continue;
+        }
+
+        let index = PackIndex::open(&index_path)?;
+
+        if index
+            .entries()
+            .iter()
+            .all(|entry| packed.contains(&entry.hash))
+        {
+            std::fs::remove_file(index_path.with_extension("pack"))?;
+            std::fs::remove_file(&index_path)?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+fn remove_loose_objects(loose: &LooseObjectStore, packed: &HashSet<Hash>) -> Result<usize> {
+    let removable = loose
+        .iter()?
+        .filter(|hash| packed.contains(hash))
+        .collect::<Vec<_>>();
+
+    for hash in &removable {
+        loose.remove(hash)?;
+    }
+
+    Ok(removable.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::MemoryObjectStore;
+
+    fn candidate(objects: &mut MemoryObjectStore, data: &str) -> Result<PackCandidate> {
+        Ok(PackCandidate {
+            hash: objects.write("blob", data.as_bytes())?,
+            object_type: "blob",
+            size: data.len(),
+            name_hash: 0,
+            delta: None,
+            depth: 0,
+        })
+    }
+
+    #[test]
+    fn should_hash_similar_names_closely() {
+        let rust_file = name_hash("src/main.rs");
+        let other_rust_file = name_hash("tests/main.rs");
+        let text_file = name_hash("README.txt");
+
+        assert_eq!(rust_file >> 24, other_rust_file >> 24);
+        assert_ne!(rust_file >> 24, text_file >> 24);
+    }
+
+    #[test]
+    fn should_only_delta_when_it_saves_space() -> Result<()> {
+        let objects = &mut MemoryObjectStore::new();
+        let shared = "some shared content that repeats ".repeat(20);
+        let mut candidates = vec![
+            candidate(objects, &shared)?,
+            candidate(objects, &format!("{shared}with a change"))?,
+            candidate(objects, "nothing in common")?,
+        ];
+
+        find_deltas(objects, &mut candidates)?;
+
+        assert!(candidates[0].delta.is_none());
+        assert_eq!(candidates[1].delta.as_ref().map(|delta| delta.0), Some(0));
+        assert_eq!(candidates[1].depth, 1);
+        assert!(candidates[2].delta.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,371 @@
+use std::path::{Component, Path, PathBuf};
+
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Walk, WalkBuilder,
+};
+
+use crate::{
+    config::Config,
+    error::{Context, Error, Result},
+    object_store::Objects,
+    refs::Refs,
+    utils::compression_level,
+};
+
+/// Name of the repository directory inside a work tree
+pub const GIT_DIRECTORY_NAME: &str = ".vc";
+/// Uses this repository directory instead of searching for one, like `GIT_DIR`
+pub const GIT_DIRECTORY_ENVIRONMENT: &str = "VC_DIR";
+/// Uses this work tree instead of the one the repository directory sits in, like
+/// `GIT_WORK_TREE`
+pub const WORK_TREE_ENVIRONMENT: &str = "VC_WORK_TREE";
+/// Ignore rules only we read, next to `.gitignore` files
+pub const IGNORE_FILE_NAME: &str = ".vcignore";
+
+/// An opened repository: where its `.vc` directory and work tree are, plus its objects.
+pub struct Repository {
+    git_directory: PathBuf,
+    work_tree: PathBuf,
+    objects: Objects,
+    refs: Refs,
+    config: Config,
+}
+
+impl Repository {
+    pub fn open(git_directory: PathBuf, work_tree: PathBuf) -> Result<Self> {
+        if !git_directory.join("HEAD").is_file() || !git_directory.join("objects").is_dir() {
+            return Err(Error::NotARepository(git_directory));
+        }
+
+        let config = Config::load(Some(&git_directory))?;
+        let mut objects = Objects::open(&git_directory).context("opening objects")?;
+        let refs = Refs::new(git_directory.clone());
+
+        objects
+            .loose_mut()
+            .set_compression(compression_level(&config, "core.looseCompression")?);
+
+        Ok(Self {
+            git_directory,
+            work_tree,
+            objects,
+            refs,
+            config,
+        })
+    }
+
+    /// Finds the repository `path` is in by walking up its parents, unless `VC_DIR` or
+    /// `VC_WORK_TREE` say where it is.
+    pub fn discover(path: &Path) -> Result<Self> {
+        Self::discover_with_overrides(
+            path,
+            std::env::var_os(GIT_DIRECTORY_ENVIRONMENT).map(PathBuf::from),
+            std::env::var_os(WORK_TREE_ENVIRONMENT).map(PathBuf::from),
+        )
+    }
+
+    /// Finds the repository containing the current directory
+    pub fn discover_from_current_directory() -> Result<Self> {
+        let current_directory = std::env::current_dir().context("getting the current directory")?;
+
+        Self::discover(&current_directory)
+    }
+
+    fn discover_with_overrides(
+        path: &Path,
+        git_directory: Option<PathBuf>,
+        work_tree: Option<PathBuf>,
+    ) -> Result<Self> {
+        let path = std::path::absolute(path).context("making path absolute")?;
+
+        // like git, an explicit repository directory without a work tree means the work
+        // tree is wherever we are
+        if let Some(git_directory) = git_directory {
+            let git_directory = path.join(git_directory);
+            let work_tree =
+                work_tree.map_or_else(|| path.clone(), |work_tree| path.join(work_tree));
+
+            return Self::open(git_directory, work_tree);
+        }
+
+        let found = path
+            .ancestors()
+            .find(|directory| directory.join(GIT_DIRECTORY_NAME).is_dir())
+            .ok_or_else(|| Error::NotARepository(path.clone()))?;
+        let git_directory = found.join(GIT_DIRECTORY_NAME);
+        let work_tree = match work_tree {
+            Some(work_tree) => path.join(work_tree),
+            None => found.to_path_buf(),
+        };
+
+        Self::open(git_directory, work_tree)
+    }
+
+    /// The `.vc` directory
+    pub fn git_directory(&self) -> &Path {
+        &self.git_directory
+    }
+
+    pub fn work_tree(&self) -> &Path {
+        &self.work_tree
+    }
+
+    pub fn objects(&self) -> &Objects {
+        &self.objects
+    }
+
+    pub fn objects_mut(&mut self) -> &mut Objects {
+        &mut self.objects
+    }
+
+    pub fn refs(&self) -> &Refs {
+        &self.refs
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Where `path`, relative to the current directory, is in the work tree, with `/` between
+    /// directories like index paths use. The work tree itself is the empty path.
+    pub fn index_path(&self, path: &Path) -> Result<String> {
+        let path = normalize(&std::path::absolute(path).context("making path absolute")?);
+        let work_tree =
+            normalize(&std::path::absolute(&self.work_tree).context("making path absolute")?);
+        let relative = path
+            .strip_prefix(&work_tree)
+            .map_err(|_| Error::OutsideRepository(path.clone()))?;
+        let components = relative
+            .iter()
+            .map(|component| component.to_str())
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| Error::InvalidPath(path.clone()))?;
+
+        Ok(components.join("/"))
+    }
+
+    /// Walks the files under `path`, skipping the `.vc` and `.git` directories and whatever
+    /// `.gitignore` and `.vcignore` files say to.
+    pub fn walk(&self, path: &Path) -> Walk {
+        Self::walk_builder(path).build()
+    }
+
+    /// Whether the ignore files say `path` should be left out of `add`. Like git, everything
+    /// inside an ignored directory is ignored too, whatever the ignore files below it say.
+    pub fn is_ignored(&self, path: &Path) -> Result<bool> {
+        let path = normalize(&std::path::absolute(path).context("making path absolute")?);
+        let mut directory =
+            normalize(&std::path::absolute(&self.work_tree).context("making path absolute")?);
+        let Ok(relative) = path.strip_prefix(&directory).map(Path::to_path_buf) else {
+            return Ok(false);
+        };
+        let mut matchers = vec![];
+
+        for component in relative.components() {
+            matchers.push(ignore_matcher(&directory)?);
+            directory.push(component);
+
+            let is_directory = directory
+                .symlink_metadata()
+                .is_ok_and(|metadata| metadata.is_dir());
+            // the deepest ignore file with a rule for the path decides
+            let matched = matchers
+                .iter()
+                .rev()
+                .map(|matcher| matcher.matched(&directory, is_directory))
+                .find(|matched| !matched.is_none());
+
+            if matched.is_some_and(|matched| matched.is_ignore()) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Whether `path` is the repository directory or a `.git` directory, or inside one, which
+    /// is never content.
+    pub fn is_git_path(&self, path: &Path) -> Result<bool> {
+        let path = normalize(&std::path::absolute(path).context("making path absolute")?);
+        let git_directory =
+            normalize(&std::path::absolute(&self.git_directory).context("making path absolute")?);
+        let work_tree =
+            normalize(&std::path::absolute(&self.work_tree).context("making path absolute")?);
+        let relative = path.strip_prefix(&work_tree).unwrap_or(Path::new(""));
+
+        Ok(path.starts_with(git_directory)
+            || relative.components().any(|component| {
+                component.as_os_str() == GIT_DIRECTORY_NAME || component.as_os_str() == ".git"
+            }))
+    }
+
+    /// A walker over `path` that leaves out what `walk` does, for callers that need to change
+    /// how deep it goes or can't borrow the repository while walking.
+    pub(crate) fn walk_builder(path: &Path) -> WalkBuilder {
+        let mut builder = WalkBuilder::new(path);
+
+        builder
+            .hidden(false)
+            .require_git(false)
+            .add_custom_ignore_filename(IGNORE_FILE_NAME)
+            // a git repository sharing the work tree isn't content either
+            .filter_entry(|entry| {
+                entry.file_name() != GIT_DIRECTORY_NAME && entry.file_name() != ".git"
+            });
+
+        builder
+    }
+}
+
+/// The rules the ignore files in `directory` have for paths below it, with the later files
+/// winning the way they do for the walker.
+fn ignore_matcher(directory: &Path) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(directory);
+
+    for name in [".gitignore", ".ignore", IGNORE_FILE_NAME] {
+        let file = directory.join(name);
+
+        if !file.is_file() {
+            continue;
+        }
+
+        if let Some(error) = builder.add(file) {
+            return Err(error.into());
+        }
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Drops `.` and resolves `..` without touching the file system, so paths to deleted files
+/// still work.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_directory;
+
+    fn create_repository(name: &str) -> Result<PathBuf> {
+        let work_tree = temp_directory(&format!("repository-{name}"));
+
+        std::fs::create_dir_all(work_tree.join(GIT_DIRECTORY_NAME).join("objects"))?;
+        std::fs::create_dir_all(work_tree.join("src").join("nested"))?;
+        std::fs::write(
+            work_tree.join(GIT_DIRECTORY_NAME).join("HEAD"),
+            "ref: refs/heads/master\n",
+        )?;
+
+        Ok(work_tree)
+    }
+
+    #[test]
+    fn should_find_repository_from_subdirectory() -> Result<()> {
+        let work_tree = create_repository("subdirectory")?;
+        let repository =
+            Repository::discover_with_overrides(&work_tree.join("src/nested"), None, None)?;
+
+        assert_eq!(repository.work_tree(), work_tree);
+        assert_eq!(
+            repository.git_directory(),
+            work_tree.join(GIT_DIRECTORY_NAME)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_prefer_overrides() -> Result<()> {
+        let work_tree = create_repository("overrides")?;
+        let elsewhere = work_tree.join("src");
+        let repository = Repository::discover_with_overrides(
+            &elsewhere,
+            Some(work_tree.join(GIT_DIRECTORY_NAME)),
+            None,
+        )?;
+
+        assert_eq!(repository.work_tree(), elsewhere);
+
+        let repository =
+            Repository::discover_with_overrides(&work_tree, None, Some(PathBuf::from("src")))?;
+
+        assert_eq!(repository.work_tree(), elsewhere);
+        Ok(())
+    }
+
+    #[test]
+    fn should_turn_paths_into_index_paths() -> Result<()> {
+        let work_tree = create_repository("index-path")?;
+        let repository = Repository::discover_with_overrides(&work_tree, None, None)?;
+
+        assert_eq!(
+            repository.index_path(&work_tree.join("src/./nested/../main.rs"))?,
+            "src/main.rs"
+        );
+        assert_eq!(repository.index_path(&work_tree)?, "");
+        assert!(matches!(
+            repository.index_path(&work_tree.join("..")),
+            Err(Error::OutsideRepository(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn should_walk_without_ignored_files() -> Result<()> {
+        let work_tree = create_repository("walk")?;
+        let repository = Repository::discover_with_overrides(&work_tree, None, None)?;
+
+        std::fs::write(work_tree.join(".gitignore"), "*.log\n")?;
+        std::fs::write(work_tree.join(IGNORE_FILE_NAME), "build/\n")?;
+        std::fs::create_dir_all(work_tree.join("build"))?;
+        std::fs::write(work_tree.join("build/out"), "")?;
+        std::fs::write(work_tree.join("src/nested/debug.log"), "")?;
+        std::fs::write(work_tree.join("src/nested/main.rs"), "")?;
+
+        let mut files = repository
+            .walk(&work_tree)
+            .map(|entry| repository.index_path(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
+
+        files.sort();
+
+        assert_eq!(
+            files,
+            vec![
+                "",
+                ".gitignore",
+                ".vcignore",
+                "src",
+                "src/nested",
+                "src/nested/main.rs"
+            ]
+        );
+        assert!(repository.is_ignored(&work_tree.join("src/nested/debug.log"))?);
+        assert!(!repository.is_ignored(&work_tree.join("src/nested/main.rs"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn should_fail_outside_a_repository() -> Result<()> {
+        let work_tree = create_repository("missing")?;
+
+        std::fs::remove_dir_all(work_tree.join(GIT_DIRECTORY_NAME))?;
+
+        assert!(Repository::discover_with_overrides(&work_tree, None, None).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::Write;
+
+use crate::{
+    error::Result,
+    repository::Repository,
+    rev_walk::{RevWalk, RevWalkOptions},
+    revision::Tip,
+};
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RevListOptions {
+    /// List the trees and blobs of the commits too, with their paths
+    pub objects: bool,
+    /// Print how many there are instead of listing them
+    pub count: bool,
+    /// Stop after this many commits
+    pub max_count: Option<usize>,
+    /// Only follow the first parent of merges
+    pub first_parent: bool,
+    /// Never a parent before all of its children
+    pub topo_order: bool,
+    /// Oldest first, which means walking everything before printing anything
+    pub reverse: bool,
+}
+
+/// Lists the commits reachable from the included `tips` but not from the excluded ones, one
+/// hash per line like `git rev-list`, and then their objects as `<hash> <path>` lines.
+pub fn rev_list(
+    repository: &Repository,
+    tips: &[Tip],
+    options: RevListOptions,
+    output: &mut impl Write,
+) -> Result<()> {
+    let walk_options = RevWalkOptions {
+        first_parent: options.first_parent,
+        topo_order: options.topo_order,
+        objects: options.objects,
+    };
+    let mut walk = RevWalk::new(repository.objects(), tips, walk_options)?;
+    let commits = walk.by_ref().take(options.max_count.unwrap_or(usize::MAX));
+    let mut count = 0;
+
+    if options.reverse {
+        let commits = commits.collect::<Result<Vec<_>>>()?;
+
+        for (hash, _) in commits.iter().rev() {
+            count += 1;
+
+            if !options.count {
+                writeln!(output, "{hash}")?;
+            }
+        }
+    } else {
+        for commit in commits {
+            let (hash, _) = commit?;
+
+            count += 1;
+
+            if !options.count {
+                writeln!(output, "{hash}")?;
+            }
+        }
+    }
+
+    if options.objects {
+        for object in walk.into_objects()? {
+            let (hash, path) = object?;
+
+            count += 1;
+
+            if !options.count {
+                writeln!(output, "{hash} {path}")?;
+            }
+        }
+    }
+
+    if options.count {
+        writeln!(output, "{count}")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use std::io::Write;
+
+use crate::{
+    error::{Error, Result},
+    hash::Hash,
+    repository::Repository,
+    revision::{abbreviate, resolve_range, resolve_ref_name, resolve_revision, shorten_ref},
+};
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RevParseOptions {
+    /// Take exactly one revision, not ranges
+    pub verify: bool,
+    /// Abbreviate hashes to at least this many digits
+    pub short: Option<usize>,
+    /// Show ref names in full, like `refs/heads/master`, rather than the objects they name
+    pub symbolic_full_name: bool,
+    /// Show ref names as short as they can be without being ambiguous
+    pub abbrev_ref: bool,
+}
+
+/// Prints the objects `revisions` name, one per line like `git rev-parse`. Ranges print their
+/// tips, the excluded ones with a leading `^`.
+pub fn rev_parse(
+    repository: &Repository,
+    revisions: &[String],
+    options: RevParseOptions,
+    output: &mut impl Write,
+) -> Result<()> {
+    let show = |hash: &Hash| match options.short {
+        Some(length) => abbreviate(repository.objects(), hash, length),
+        None => Ok(hash.to_string()),
+    };
+
+    if options.verify {
+        let [revision] = revisions else {
+            return Err(Error::UnknownRevision(format!(
+                "{}, needed a single revision",
+                revisions.join(" ")
+            )));
+        };
+
+        writeln!(
+            output,
+            "{}",
+            show(&resolve_revision(repository, revision)?)?
+        )?;
+
+        return Ok(());
+    }
+
+    for revision in revisions {
+        if options.symbolic_full_name || options.abbrev_ref {
+            // like git, what isn't a ref name shows as nothing
+            let Some(name) = resolve_ref_name(repository, revision)? else {
+                continue;
+            };
+            let name = if options.abbrev_ref {
+                shorten_ref(repository.refs(), &name)?
+            } else {
+                name
+            };
+
+            writeln!(output, "{name}")?;
+            continue;
+        }
+
+        for tip in resolve_range(repository, revision)? {
+            let prefix = if tip.excluded { "^" } else { "" };
+
+            writeln!(output, "{prefix}{}", show(&tip.hash)?)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{init::init, ref_transaction::Expected, refs::HEAD, test_utils::temp_directory};
+
+    #[test]
+    fn should_shorten_ref_names_and_verify() -> Result<()> {
+        let directory = temp_directory("rev-parse");
+
+        std::fs::create_dir_all(&directory)?;
+
+        let git_directory = init(directory.clone())?;
+        let repository = Repository::open(git_directory, directory)?;
+        let mut transaction = repository.refs().transaction();
+
+        transaction.update(HEAD, Hash::new([1; 20]), Expected::Anything);
+        transaction.update("refs/tags/master", Hash::new([2; 20]), Expected::Anything);
+        transaction.commit()?;
+
+        let mut output = vec![];
+        let options = RevParseOptions {
+            abbrev_ref: true,
+            ..RevParseOptions::default()
+        };
+
+        rev_parse(
+            &repository,
+            &["HEAD".to_owned(), "tags/master".to_owned()],
+            options,
+            &mut output,
+        )?;
+
+        // the tag is found before the branch, so the branch keeps its prefix
+        assert_eq!(
+            String::from_utf8(output)?,
+            "heads/master\nmaster\n".to_owned()
+        );
+        assert!(matches!(
+            rev_parse(
+                &repository,
+                &["HEAD".to_owned(), "HEAD".to_owned()],
+                RevParseOptions {
+                    verify: true,
+                    ..RevParseOptions::default()
+                },
+                &mut vec![]
+            ),
+            Err(Error::UnknownRevision(_))
+        ));
+        Ok(())
+    }
+}
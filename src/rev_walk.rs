@@ -0,0 +1,548 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+};
+
+use crate::{
+    error::Result,
+    hash::Hash,
+    object::{Commit, Object},
+    object_store::ObjectStore,
+    revision::Tip,
+    tree::TreeObjectType,
+};
+
+/// How many more commits to look at once only excluded ones are left, in case clock skew
+/// dated a commit before its parent. git uses the same slop.
+const SLOP: usize = 5;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RevWalkOptions {
+    /// Only follow the first parent of merges
+    pub first_parent: bool,
+    /// Never a parent before all of its children, keeping each line of history together. The
+    /// whole walk is done before the first commit comes out.
+    pub topo_order: bool,
+    /// Keep the trees of the commits that come out, for `RevWalk::into_objects`
+    pub objects: bool,
+}
+
+/// A commit waiting to be walked, newest first, then in the order they were found
+struct Queued {
+    hash: Hash,
+    commit: Commit,
+    sequence: u64,
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.commit
+            .committer
+            .time
+            .cmp(&other.commit.committer.time)
+            .then(other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+/// The commits reachable from the included tips but not from the excluded ones, newest commit
+/// date first like `git rev-list`. Only the edge of the walk is kept in memory, except that
+/// excluded tips and `topo_order` mean walking ahead before anything comes out.
+pub struct RevWalk<'a, S: ObjectStore> {
+    objects: &'a S,
+    options: RevWalkOptions,
+    queue: BinaryHeap<Queued>,
+    /// The hashes in `queue`
+    queued: HashSet<Hash>,
+    /// How many commits in `queue` aren't excluded, so walking ahead knows when to stop
+    included: usize,
+    sequence: u64,
+    seen: HashSet<Hash>,
+    excluded: HashSet<Hash>,
+    /// What walking ahead found, in the order it comes out
+    sorted: Option<VecDeque<(Hash, Commit)>>,
+    /// Objects to list with `into_objects`: trees and blobs named as tips, then the trees of
+    /// the commits that came out
+    roots: Vec<(Hash, &'static str)>,
+    /// Trees and blobs whose objects are left out, from excluded tips and commits
+    excluded_roots: Vec<Hash>,
+}
+
+impl<'a, S: ObjectStore> RevWalk<'a, S> {
+    /// Starts a walk from `tips`, peeling tags. Trees and blobs among them are only listed by
+    /// `into_objects`.
+    pub fn new(objects: &'a S, tips: &[Tip], options: RevWalkOptions) -> Result<Self> {
+        let mut walk = Self {
+            objects,
+            options,
+            queue: BinaryHeap::new(),
+            queued: HashSet::new(),
+            included: 0,
+            sequence: 0,
+            seen: HashSet::new(),
+            excluded: HashSet::new(),
+            sorted: None,
+            roots: vec![],
+            excluded_roots: vec![],
+        };
+
+        for tip in tips {
+            let mut hash = tip.hash.clone();
+
+            loop {
+                let object = objects.read_object(&hash)?;
+                let object_type = object.object_type();
+
+                match object {
+                    Object::Tag(tag) => {
+                        if !tip.excluded {
+                            walk.roots.push((hash, object_type));
+                        }
+
+                        hash = tag.object;
+                        continue;
+                    }
+                    Object::Commit(commit) => {
+                        if tip.excluded {
+                            walk.exclude(hash.clone())?;
+                        }
+
+                        walk.enqueue(hash, commit);
+                    }
+                    _ if tip.excluded => walk.excluded_roots.push(hash),
+                    _ => walk.roots.push((hash, object_type)),
+                }
+
+                break;
+            }
+        }
+
+        Ok(walk)
+    }
+
+    /// Lists the trees and blobs of what the walk named and let out, without anything excluded
+    /// commits have.
+    pub fn into_objects(self) -> Result<ObjectWalk<'a, S>> {
+        let mut seen = HashSet::new();
+        let mut pending = self.excluded_roots;
+
+        while let Some(hash) = pending.pop() {
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+
+            let Object::Tree(tree) = self.objects.read_object(&hash)? else {
+                continue;
+            };
+
+            for entry in tree.tree_objects {
+                match entry.object_type {
+                    TreeObjectType::Tree => pending.push(entry.checksum),
+                    TreeObjectType::Blob => {
+                        seen.insert(entry.checksum);
+                    }
+                    TreeObjectType::Commit => {}
+                }
+            }
+        }
+
+        // reversed, so the first root is looked at first
+        let pending = self
+            .roots
+            .into_iter()
+            .rev()
+            .map(|(hash, object_type)| (hash, object_type, String::new()))
+            .collect();
+
+        Ok(ObjectWalk {
+            objects: self.objects,
+            seen,
+            pending,
+        })
+    }
+
+    fn enqueue(&mut self, hash: Hash, commit: Commit) {
+        if !self.seen.insert(hash.clone()) {
+            return;
+        }
+
+        if !self.excluded.contains(&hash) {
+            self.included += 1;
+        }
+
+        self.sequence += 1;
+        self.queued.insert(hash.clone());
+        self.queue.push(Queued {
+            hash,
+            commit,
+            sequence: self.sequence,
+        });
+    }
+
+    /// Leaves out `hash` and everything it reaches. Commits that were already walked passed
+    /// their parents on without this, so they're told now.
+    fn exclude(&mut self, hash: Hash) -> Result<()> {
+        let mut pending = vec![hash];
+
+        while let Some(hash) = pending.pop() {
+            if !self.excluded.insert(hash.clone()) || !self.seen.contains(&hash) {
+                continue;
+            }
+
+            if self.queued.contains(&hash) {
+                self.included -= 1;
+            }
+
+            pending.extend(self.objects.read_commit(&hash)?.parents);
+        }
+
+        Ok(())
+    }
+
+    fn parents<'c>(&self, commit: &'c Commit) -> &'c [Hash] {
+        if self.options.first_parent {
+            &commit.parents[..commit.parents.len().min(1)]
+        } else {
+            &commit.parents
+        }
+    }
+
+    /// Takes the newest commit off the queue and queues its parents, passing on exclusion.
+    /// Exclusion goes to every parent, even with `first_parent`, so nothing they reach slips
+    /// through a side line.
+    fn step(&mut self) -> Result<Option<(Hash, Commit)>> {
+        let Some(Queued { hash, commit, .. }) = self.queue.pop() else {
+            return Ok(None);
+        };
+        let excluded = self.excluded.contains(&hash);
+
+        self.queued.remove(&hash);
+
+        if !excluded {
+            self.included -= 1;
+        }
+
+        let parents = if excluded {
+            commit.parents.clone()
+        } else {
+            self.parents(&commit).to_vec()
+        };
+
+        for parent in parents {
+            if excluded {
+                self.exclude(parent.clone())?;
+            }
+
+            if !self.seen.contains(&parent) {
+                let parent_commit = self.objects.read_commit(&parent)?;
+
+                self.enqueue(parent, parent_commit);
+            }
+        }
+
+        if excluded && self.options.objects {
+            self.excluded_roots.push(commit.tree.clone());
+        }
+
+        Ok(Some((hash, commit)))
+    }
+
+    /// Walks until only excluded commits are left to look at, dropping the ones that turned
+    /// out to be excluded and sorting the rest when asked to.
+    fn walk_ahead(&mut self) -> Result<VecDeque<(Hash, Commit)>> {
+        let mut walked = vec![];
+        let mut slop = SLOP;
+
+        while let Some((hash, commit)) = self.step()? {
+            if !self.excluded.contains(&hash) {
+                walked.push((hash, commit));
+            }
+
+            if self.included > 0 {
+                slop = SLOP;
+            } else if slop == 0 {
+                break;
+            } else {
+                slop -= 1;
+            }
+        }
+
+        walked.retain(|(hash, _)| !self.excluded.contains(hash));
+
+        if self.options.topo_order {
+            walked = self.topo_sort(walked);
+        }
+
+        Ok(walked.into())
+    }
+
+    /// Orders `commits`, newest first, so that no parent comes before its children. Like git,
+    /// the parents are a stack, so a merge's last parent and its history come next.
+    fn topo_sort(&self, commits: Vec<(Hash, Commit)>) -> Vec<(Hash, Commit)> {
+        let mut children = HashMap::<Hash, usize>::new();
+
+        for (_, commit) in &commits {
+            for parent in self.parents(commit) {
+                *children.entry(parent.clone()).or_default() += 1;
+            }
+        }
+
+        let mut stack = commits
+            .iter()
+            .filter(|(hash, _)| !children.contains_key(hash))
+            .map(|(hash, _)| hash.clone())
+            .rev()
+            .collect::<Vec<_>>();
+        let mut commits = commits.into_iter().collect::<HashMap<_, _>>();
+        let mut sorted = vec![];
+
+        while let Some(hash) = stack.pop() {
+            let Some(commit) = commits.remove(&hash) else {
+                continue;
+            };
+
+            for parent in self.parents(&commit) {
+                let Some(count) = children.get_mut(parent) else {
+                    continue;
+                };
+
+                *count -= 1;
+
+                if *count == 0 && commits.contains_key(parent) {
+                    stack.push(parent.clone());
+                }
+            }
+
+            sorted.push((hash, commit));
+        }
+
+        sorted
+    }
+
+    fn next_commit(&mut self) -> Result<Option<(Hash, Commit)>> {
+        let walk_ahead = self.options.topo_order || !self.excluded.is_empty();
+
+        if self.sorted.is_none() && walk_ahead {
+            self.sorted = Some(self.walk_ahead()?);
+        }
+
+        // without excluded tips, everything the walk reaches comes out as it's reached
+        let next = match &mut self.sorted {
+            Some(sorted) => sorted.pop_front(),
+            None => self.step()?,
+        };
+
+        if let Some((_, commit)) = &next {
+            if self.options.objects {
+                self.roots.push((commit.tree.clone(), "tree"));
+            }
+        }
+
+        Ok(next)
+    }
+}
+
+impl<S: ObjectStore> Iterator for RevWalk<'_, S> {
+    type Item = Result<(Hash, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_commit().transpose()
+    }
+}
+
+/// The objects a `RevWalk` let out, each with its path like `src/main.rs`, depth first. Trees
+/// come before what's in them, and nothing comes out twice.
+pub struct ObjectWalk<'a, S: ObjectStore> {
+    objects: &'a S,
+    seen: HashSet<Hash>,
+    /// Objects to look at with their types and paths, the next one last
+    pending: Vec<(Hash, &'static str, String)>,
+}
+
+impl<S: ObjectStore> ObjectWalk<'_, S> {
+    fn next_object(&mut self) -> Result<Option<(Hash, String)>> {
+        while let Some((hash, object_type, path)) = self.pending.pop() {
+            if !self.seen.insert(hash.clone()) {
+                continue;
+            }
+
+            if object_type == "tree" {
+                let tree = self.objects.read_tree(&hash)?;
+
+                for entry in tree.tree_objects.into_iter().rev() {
+                    let object_type = match entry.object_type {
+                        TreeObjectType::Tree => "tree",
+                        TreeObjectType::Blob => "blob",
+                        // submodule commits are in another repository
+                        TreeObjectType::Commit => continue,
+                    };
+                    let path = if path.is_empty() {
+                        entry.name().into_owned()
+                    } else {
+                        format!("{path}/{}", entry.name())
+                    };
+
+                    self.pending.push((entry.checksum, object_type, path));
+                }
+            }
+
+            return Ok(Some((hash, path)));
+        }
+
+        Ok(None)
+    }
+}
+
+impl<S: ObjectStore> Iterator for ObjectWalk<'_, S> {
+    type Item = Result<(Hash, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_object().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commit_tree::commit_tree,
+        index::{Index, IndexEntry, StatData},
+        object::Signature,
+        object_store::MemoryObjectStore,
+    };
+
+    /// Commits with one file each, `src/<message>`, and every file of their parents
+    struct History {
+        objects: MemoryObjectStore,
+        files: Index,
+    }
+
+    impl History {
+        fn commit(&mut self, message: &str, parents: &[Hash], time: i64) -> Result<Hash> {
+            let blob = self.objects.write("blob", message.as_bytes())?;
+
+            self.files.add(IndexEntry::new(
+                &format!("src/{message}"),
+                0o100644,
+                blob,
+                StatData::default(),
+            ));
+
+            let tree = self.files.write_tree(&mut self.objects)?;
+            let signature = Signature::new("A U Thor", "author@example.com", time, 0);
+
+            commit_tree(
+                &tree,
+                parents,
+                message,
+                &signature,
+                &signature,
+                &mut self.objects,
+            )
+        }
+    }
+
+    fn tip(hash: &Hash, excluded: bool) -> Tip {
+        Tip {
+            hash: hash.clone(),
+            excluded,
+        }
+    }
+
+    fn walk(
+        objects: &MemoryObjectStore,
+        tips: &[Tip],
+        options: RevWalkOptions,
+    ) -> Result<Vec<Hash>> {
+        RevWalk::new(objects, tips, options)?
+            .map(|commit| commit.map(|(hash, _)| hash))
+            .collect()
+    }
+
+    #[test]
+    fn should_walk_included_but_not_excluded_commits() -> Result<()> {
+        let mut history = History {
+            objects: MemoryObjectStore::new(),
+            files: Index::default(),
+        };
+        let base = history.commit("base", &[], 1_700_000_000)?;
+        let side = history.commit("side", std::slice::from_ref(&base), 1_700_000_001)?;
+        let main = history.commit("main", std::slice::from_ref(&base), 1_700_000_002)?;
+        let merge = history.commit("merge", &[main.clone(), side.clone()], 1_700_000_003)?;
+        let objects = &history.objects;
+        let options = RevWalkOptions::default();
+
+        assert_eq!(
+            walk(objects, &[tip(&merge, false)], options)?,
+            vec![merge.clone(), main.clone(), side.clone(), base.clone()]
+        );
+        assert_eq!(
+            walk(objects, &[tip(&merge, false), tip(&main, true)], options)?,
+            vec![merge.clone(), side.clone()]
+        );
+
+        let first_parent = RevWalkOptions {
+            first_parent: true,
+            ..options
+        };
+
+        assert_eq!(
+            walk(objects, &[tip(&merge, false)], first_parent)?,
+            vec![merge.clone(), main.clone(), base.clone()]
+        );
+
+        let topo_order = RevWalkOptions {
+            topo_order: true,
+            ..options
+        };
+
+        // side is older, but as the last parent its line is followed first
+        assert_eq!(
+            walk(objects, &[tip(&merge, false)], topo_order)?,
+            vec![merge, side, main, base]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_list_objects_excluded_commits_do_not_have() -> Result<()> {
+        let mut history = History {
+            objects: MemoryObjectStore::new(),
+            files: Index::default(),
+        };
+        let base = history.commit("base", &[], 1_700_000_000)?;
+        let next = history.commit("next", std::slice::from_ref(&base), 1_700_000_001)?;
+        let objects = &history.objects;
+        let options = RevWalkOptions {
+            objects: true,
+            ..RevWalkOptions::default()
+        };
+        let mut walk = RevWalk::new(objects, &[tip(&next, false), tip(&base, true)], options)?;
+        let commits = walk
+            .by_ref()
+            .map(|commit| commit.map(|(hash, _)| hash))
+            .collect::<Result<Vec<_>>>()?;
+        let paths = walk
+            .into_objects()?
+            .map(|object| object.map(|(_, path)| path))
+            .collect::<Result<Vec<_>>>()?;
+
+        // the root tree has no path, and src/base is left out
+        assert_eq!(commits, vec![next]);
+        assert_eq!(paths, vec!["", "src", "src/next"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,629 @@
+// Revision syntax is described at https://git-scm.com/docs/gitrevisions
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use crate::{
+    config::Config,
+    error::{Error, Result},
+    hash::Hash,
+    index::Index,
+    object::Object,
+    object_store::{object_type_name, ObjectStore},
+    refs::{Refs, HEAD},
+    repository::Repository,
+};
+
+/// Where git looks for a short ref name like `master`, in order
+const REF_RULES: [&str; 6] = [
+    "{}",
+    "refs/{}",
+    "refs/tags/{}",
+    "refs/heads/{}",
+    "refs/remotes/{}",
+    "refs/remotes/{}/HEAD",
+];
+
+/// Prefixes dropped to shorten a ref name, most specific first
+const SHORT_REF_PREFIXES: [&str; 4] = ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"];
+
+/// Hex digits shorter than this are only ever taken as names, like in git
+const MIN_ABBREVIATED_LENGTH: usize = 4;
+
+/// A commit a walk of history starts from or, when `excluded`, one whose history is left out,
+/// as in `^A`, `A..B` and `A...B`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tip {
+    pub hash: Hash,
+    pub excluded: bool,
+}
+
+/// Works out the tips an argument like `master`, `^v1.0`, `A..B` or `A...B` stands for. The
+/// ends of ranges are peeled to commits, and an empty end means `HEAD`.
+pub fn resolve_range(repository: &Repository, argument: &str) -> Result<Vec<Tip>> {
+    let tip = |hash, excluded| Tip { hash, excluded };
+    let commit = |revision: &str| {
+        let revision = if revision.is_empty() { HEAD } else { revision };
+
+        peel(
+            repository.objects(),
+            resolve_revision(repository, revision)?,
+            "commit",
+        )
+    };
+
+    // the path in `rev:path` can have dots in it
+    if !argument.contains(':') {
+        if let Some((from, to)) = argument.split_once("...") {
+            let (from, to) = (commit(from)?, commit(to)?);
+            let bases = merge_bases(repository.objects(), &from, &to)?;
+
+            return Ok([tip(to, false), tip(from, false)]
+                .into_iter()
+                .chain(bases.into_iter().map(|base| tip(base, true)))
+                .collect());
+        }
+
+        if let Some((from, to)) = argument.split_once("..") {
+            return Ok(vec![tip(commit(to)?, false), tip(commit(from)?, true)]);
+        }
+    }
+
+    match argument.strip_prefix('^') {
+        Some("") => Err(Error::UnknownRevision(argument.to_owned())),
+        Some(revision) => Ok(vec![tip(commit(revision)?, true)]),
+        None => Ok(vec![tip(resolve_revision(repository, argument)?, false)]),
+    }
+}
+
+/// Works out the object a revision names. That's a ref name, a full or abbreviated hash, `@`,
+/// `name@{n}` or `name@{upstream}`, followed by any of `~n`, `^n`, `^{type}` and `^{}`, or by
+/// `:path` to look inside its tree. A bare `:path` is the blob staged at `path`.
+pub fn resolve_revision(repository: &Repository, revision: &str) -> Result<Hash> {
+    let unknown = || Error::UnknownRevision(revision.to_owned());
+    let objects = repository.objects();
+
+    if let Some(separator) = find_outside_braces(revision, |c| c == ':') {
+        let (base, path) = (&revision[..separator], &revision[separator + 1..]);
+
+        return resolve_path(repository, base, path, revision);
+    }
+
+    let split = find_outside_braces(revision, |c| c == '^' || c == '~').unwrap_or(revision.len());
+    let (base, mut suffixes) = revision.split_at(split);
+    let mut hash = resolve_base(repository, base, revision)?.ok_or_else(unknown)?;
+
+    while let Some(operator) = suffixes.chars().next() {
+        let rest = &suffixes[operator.len_utf8()..];
+
+        if operator == '^' && rest.starts_with('{') {
+            let end = rest.find('}').ok_or_else(unknown)?;
+
+            hash = match &rest[1..end] {
+                "" => peel_tags(objects, hash)?,
+                "object" => {
+                    objects.read_existing(&hash)?;
+                    hash
+                }
+                object_type => peel(objects, hash, object_type_name(object_type)?)?,
+            };
+            suffixes = &rest[end + 1..];
+            continue;
+        }
+
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let count = match digits {
+            0 => 1,
+            _ => rest[..digits]
+                .parse::<usize>()
+                .map_err(|_error| unknown())?,
+        };
+
+        suffixes = &rest[digits..];
+        hash = peel(objects, hash, "commit")?;
+
+        match operator {
+            '~' => {
+                for _ in 0..count {
+                    hash = objects
+                        .read_commit(&hash)?
+                        .parents
+                        .into_iter()
+                        .next()
+                        .ok_or_else(unknown)?;
+                }
+            }
+            '^' if count > 0 => {
+                hash = objects
+                    .read_commit(&hash)?
+                    .parents
+                    .into_iter()
+                    .nth(count - 1)
+                    .ok_or_else(unknown)?;
+            }
+            '^' => {}
+            _ => return Err(unknown()),
+        }
+    }
+
+    Ok(hash)
+}
+
+/// The full ref name a revision like `master`, `HEAD` or `@{upstream}` stands for, following
+/// symbolic refs. Revisions that aren't just a ref name have none.
+pub fn resolve_ref_name(repository: &Repository, revision: &str) -> Result<Option<String>> {
+    let refs = repository.refs();
+    let name = match revision.split_once("@{") {
+        Some((branch, selector)) if is_upstream(selector) => {
+            return upstream(repository, branch).map(Some)
+        }
+        Some(_) => return Ok(None),
+        None if revision == "@" => HEAD.to_owned(),
+        None => match expand_ref(refs, revision)? {
+            Some(name) => name,
+            None => return Ok(None),
+        },
+    };
+
+    refs.resolve_name(&name).map(Some)
+}
+
+/// The ref `branch` is set to follow with `branch.<name>.remote` and `branch.<name>.merge`,
+/// like `refs/remotes/origin/master`. An empty branch means the current one.
+pub fn upstream(repository: &Repository, branch: &str) -> Result<String> {
+    let refs = repository.refs();
+    let name = match branch {
+        "" => HEAD.to_owned(),
+        branch => expand_ref(refs, branch)?
+            .ok_or_else(|| Error::UnknownRevision(format!("{branch}@{{upstream}}")))?,
+    };
+    let name = refs.resolve_name(&name)?;
+    let Some(branch) = name.strip_prefix("refs/heads/") else {
+        return Err(Error::UnknownRevision(format!(
+            "{name}@{{upstream}}, {name} is not a branch"
+        )));
+    };
+
+    configured_upstream(repository.config(), branch).ok_or_else(|| {
+        Error::UnknownRevision(format!(
+            "{branch}@{{upstream}}, no upstream is configured for {branch}"
+        ))
+    })
+}
+
+/// The ref the branch called `branch`, like `master`, follows according to `config`, if any.
+pub fn configured_upstream(config: &Config, branch: &str) -> Option<String> {
+    let remote = config.get(&format!("branch.{branch}.remote"))?;
+    let merge = config.get(&format!("branch.{branch}.merge"))?;
+
+    // `.` is the repository itself, anything else is tracked under refs/remotes
+    Some(match (remote, merge.strip_prefix("refs/heads/")) {
+        (".", _) | (_, None) => merge.to_owned(),
+        (remote, Some(merged)) => format!("refs/remotes/{remote}/{merged}"),
+    })
+}
+
+/// Follows tags, and commits to their trees, until reaching an object of `object_type`.
+pub fn peel(objects: &impl ObjectStore, mut hash: Hash, object_type: &'static str) -> Result<Hash> {
+    loop {
+        let object = objects.read_object(&hash)?;
+
+        if object.object_type() == object_type {
+            return Ok(hash);
+        }
+
+        hash = match object {
+            Object::Tag(tag) => tag.object,
+            Object::Commit(commit) if object_type == "tree" => commit.tree,
+            object => {
+                return Err(Error::WrongObjectType {
+                    hash,
+                    expected: object_type,
+                    found: object.object_type(),
+                })
+            }
+        };
+    }
+}
+
+/// Every commit reachable from `tip`, including itself, with its parents.
+pub fn ancestors(objects: &impl ObjectStore, tip: &Hash) -> Result<HashMap<Hash, Vec<Hash>>> {
+    let mut ancestors = HashMap::new();
+    let mut pending = vec![tip.clone()];
+
+    while let Some(hash) = pending.pop() {
+        if ancestors.contains_key(&hash) {
+            continue;
+        }
+
+        let parents = objects.read_commit(&hash)?.parents;
+
+        pending.extend(parents.iter().cloned());
+        ancestors.insert(hash, parents);
+    }
+
+    Ok(ancestors)
+}
+
+/// The best common ancestors of two commits, the ones both reach that aren't an ancestor of
+/// another one both reach.
+pub fn merge_bases(objects: &impl ObjectStore, one: &Hash, other: &Hash) -> Result<Vec<Hash>> {
+    let theirs = ancestors(objects, other)?;
+    let common = ancestors(objects, one)?
+        .into_iter()
+        .filter(|(hash, _)| theirs.contains_key(hash))
+        .collect::<HashMap<_, _>>();
+    // whatever a common commit reaches is common too, so a common commit is below another
+    // exactly when it's the parent of one
+    let below = common.values().flatten().collect::<HashSet<_>>();
+    let mut bases = common
+        .keys()
+        .filter(|hash| !below.contains(hash))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    bases.sort();
+
+    Ok(bases)
+}
+
+/// The shortest prefix of `hash`, with at least `length` digits, that no other object in
+/// `objects` starts with.
+pub fn abbreviate(objects: &impl ObjectStore, hash: &Hash, length: usize) -> Result<String> {
+    let hex = hash.to_string();
+    let length = length.clamp(MIN_ABBREVIATED_LENGTH, hex.len());
+    let others = objects
+        .find_by_prefix(&hex[..length])?
+        .into_iter()
+        .map(|other| other.to_string())
+        .filter(|other| *other != hex)
+        .collect::<Vec<_>>();
+    let shared = others
+        .iter()
+        .map(|other| {
+            other
+                .bytes()
+                .zip(hex.bytes())
+                .take_while(|(a, b)| a == b)
+                .count()
+        })
+        .max()
+        .unwrap_or_default();
+
+    Ok(hex[..length.max(shared + 1).min(hex.len())].to_owned())
+}
+
+/// The full name of the ref a short name like `master` or `origin` refers to, if any.
+pub fn expand_ref(refs: &Refs, name: &str) -> Result<Option<String>> {
+    for rule in REF_RULES {
+        let candidate = rule.replace("{}", name);
+
+        // short names that can't be refs are just not found, they might be hashes
+        if crate::refs::check_ref_name(&candidate).is_err() {
+            continue;
+        }
+
+        if refs.read(&candidate)?.is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+
+    Ok(None)
+}
+
+/// `refs/heads/master` as `master`, unless that would name some other ref first.
+pub fn shorten_ref(refs: &Refs, name: &str) -> Result<String> {
+    for prefix in SHORT_REF_PREFIXES {
+        let Some(short) = name.strip_prefix(prefix) else {
+            continue;
+        };
+
+        if expand_ref(refs, short)?.as_deref() == Some(name) {
+            return Ok(short.to_owned());
+        }
+    }
+
+    Ok(name.to_owned())
+}
+
+/// `name@{index}`, where `index` counts back from the newest entry in the reflog. An empty
+/// name means the current branch.
+fn reflog_entry(refs: &Refs, name: &str, index: usize, revision: &str) -> Result<Hash> {
+    let name = if name.is_empty() {
+        refs.resolve_name(HEAD)?
+    } else {
+        expand_ref(refs, name)?.ok_or_else(|| Error::UnknownRevision(revision.to_owned()))?
+    };
+    let entries = refs.reflog(&name)?;
+
+    entries
+        .iter()
+        .rev()
+        .nth(index)
+        .map(|entry| entry.new.clone())
+        .ok_or_else(|| {
+            Error::UnknownRevision(format!(
+                "{revision}, the log for {name} only has {} entries",
+                entries.len()
+            ))
+        })
+}
+
+/// The name, `@` or hash a revision starts with, before any suffixes.
+fn resolve_base(repository: &Repository, base: &str, revision: &str) -> Result<Option<Hash>> {
+    let refs = repository.refs();
+
+    if base == "@" {
+        return refs.resolve(HEAD);
+    }
+
+    if let Some((name, selector)) = base.split_once("@{") {
+        if is_upstream(selector) {
+            return refs.resolve(&upstream(repository, name)?);
+        }
+
+        let Some(index) = selector
+            .strip_suffix('}')
+            .and_then(|index| index.parse::<usize>().ok())
+        else {
+            return Ok(None);
+        };
+
+        return reflog_entry(refs, name, index, revision).map(Some);
+    }
+
+    // like git, a ref wins over an object its name happens to be a prefix of
+    if let Some(name) = expand_ref(refs, base)? {
+        if let Some(hash) = refs.resolve(&name)? {
+            return Ok(Some(hash));
+        }
+    }
+
+    if base.len() == 40 {
+        return Ok(base.parse().ok());
+    }
+
+    if base.len() < MIN_ABBREVIATED_LENGTH || !base.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+
+    let mut candidates = repository.objects().find_by_prefix(base)?;
+
+    match candidates.len() {
+        0 | 1 => Ok(candidates.pop()),
+        _ => Err(Error::AmbiguousHash {
+            prefix: base.to_owned(),
+            candidates,
+        }),
+    }
+}
+
+/// `rev:path`, or the staged `:path` and `:stage:path`. Paths starting with `./` or `../`
+/// are relative to the current directory, others to the top of the work tree.
+fn resolve_path(repository: &Repository, base: &str, path: &str, revision: &str) -> Result<Hash> {
+    let path = if path.starts_with("./") || path.starts_with("../") {
+        repository.index_path(Path::new(path))?
+    } else {
+        path.trim_end_matches('/').to_owned()
+    };
+
+    if base.is_empty() {
+        let (stage, path) = match path.split_once(':') {
+            Some((stage, path)) if stage.len() == 1 => (stage.parse::<u8>()?, path),
+            _ => (0, path.as_str()),
+        };
+        let index = Index::read(repository.git_directory())?.unwrap_or_default();
+
+        return index
+            .entries()
+            .iter()
+            .find(|entry| entry.path == path && entry.stage == stage)
+            .map(|entry| entry.hash.clone())
+            .ok_or_else(|| {
+                Error::UnknownRevision(format!("{revision}, {path} is not in the index"))
+            });
+    }
+
+    let objects = repository.objects();
+    let tree = peel(objects, resolve_revision(repository, base)?, "tree")?;
+
+    if path.is_empty() {
+        return Ok(tree);
+    }
+
+    objects
+        .read_tree_entry(&tree, &path)?
+        .map(|entry| entry.checksum)
+        .ok_or_else(|| {
+            Error::UnknownRevision(format!("{revision}, {path} does not exist in {base}"))
+        })
+}
+
+/// Follows tags until reaching something that isn't one, for `^{}`.
+fn peel_tags(objects: &impl ObjectStore, mut hash: Hash) -> Result<Hash> {
+    while let Object::Tag(tag) = objects.read_object(&hash)? {
+        hash = tag.object;
+    }
+
+    Ok(hash)
+}
+
+fn is_upstream(selector: &str) -> bool {
+    selector.eq_ignore_ascii_case("u}") || selector.eq_ignore_ascii_case("upstream}")
+}
+
+/// Where the first character matching `pattern` is, skipping anything inside `@{...}`
+fn find_outside_braces(revision: &str, pattern: impl Fn(char) -> bool) -> Option<usize> {
+    let mut depth = 0_usize;
+
+    for (position, c) in revision.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            c if depth == 0 && pattern(c) => return Some(position),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commit_tree::commit_tree,
+        config::ConfigFile,
+        index::{IndexEntry, StatData},
+        init::init,
+        object::Signature,
+        ref_transaction::Expected,
+        test_utils::temp_directory,
+    };
+
+    fn create_repository(name: &str) -> Result<Repository> {
+        let directory = temp_directory(&format!("revision-{name}"));
+
+        std::fs::create_dir_all(&directory)?;
+
+        let git_directory = init(directory.clone())?;
+        let config = ConfigFile::new(git_directory.join("config"));
+
+        config.set("branch.master.remote", ".")?;
+        config.set("branch.master.merge", "refs/heads/side")?;
+
+        Repository::open(git_directory, directory)
+    }
+
+    #[test]
+    fn should_resolve_refs_and_reflog_entries() -> Result<()> {
+        let directory = temp_directory("revision");
+
+        std::fs::create_dir_all(&directory)?;
+
+        let git_directory = init(directory.clone())?;
+        let repository = Repository::open(git_directory, directory)?;
+        let refs = repository.refs();
+
+        for byte in 1..=3 {
+            let mut transaction = refs.transaction();
+
+            transaction
+                .update(HEAD, Hash::new([byte; 20]), Expected::Anything)
+                .message(&format!("commit: {byte}"));
+            transaction.commit()?;
+        }
+
+        assert_eq!(resolve_revision(&repository, "master")?, Hash::new([3; 20]));
+        assert_eq!(resolve_revision(&repository, "@")?, Hash::new([3; 20]));
+        assert_eq!(
+            resolve_revision(&repository, "HEAD@{1}")?,
+            Hash::new([2; 20])
+        );
+        assert_eq!(
+            resolve_revision(&repository, "master@{2}")?,
+            Hash::new([1; 20])
+        );
+        assert_eq!(resolve_revision(&repository, "@{0}")?, Hash::new([3; 20]));
+        assert_eq!(
+            resolve_revision(&repository, &Hash::new([9; 20]).to_string())?,
+            Hash::new([9; 20])
+        );
+        assert!(matches!(
+            resolve_revision(&repository, "master@{3}"),
+            Err(Error::UnknownRevision(_))
+        ));
+        assert!(matches!(
+            resolve_revision(&repository, "nope"),
+            Err(Error::UnknownRevision(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn should_resolve_expressions_and_ranges() -> Result<()> {
+        let mut repository = create_repository("expressions")?;
+        let objects = repository.objects_mut();
+        let blob = objects.write("blob", b"fn main() {}\n")?;
+        let mut index = Index::default();
+
+        index.add(IndexEntry::new(
+            "src/main.rs",
+            0o100644,
+            blob.clone(),
+            StatData::default(),
+        ));
+
+        let tree = index.write_tree(objects)?;
+        let signature = Signature::new("A U Thor", "author@example.com", 1_700_000_000, 0);
+        let mut commit = |message: &str, parents: &[Hash]| {
+            commit_tree(&tree, parents, message, &signature, &signature, objects)
+        };
+        let first = commit("first", &[])?;
+        let second = commit("second", std::slice::from_ref(&first))?;
+        let side = commit("side", std::slice::from_ref(&first))?;
+        let merge = commit("merge", &[second.clone(), side.clone()])?;
+        let mut transaction = repository.refs().transaction();
+
+        transaction.update(HEAD, merge.clone(), Expected::Anything);
+        transaction.update("refs/heads/side", side.clone(), Expected::Anything);
+        transaction.commit()?;
+
+        let resolve = |revision: &str| resolve_revision(&repository, revision);
+        let tip = |hash: &Hash, excluded| Tip {
+            hash: hash.clone(),
+            excluded,
+        };
+
+        assert_eq!(resolve("HEAD~2")?, first);
+        assert_eq!(resolve("master^2")?, side);
+        assert_eq!(resolve("@^1~1^0")?, first);
+        assert_eq!(resolve("HEAD^{tree}")?, tree);
+        assert_eq!(resolve("HEAD~:src/main.rs")?, blob);
+        assert_eq!(resolve("@{upstream}")?, side);
+        assert_eq!(resolve(&merge.to_string()[..7])?, merge);
+        assert!(matches!(resolve("HEAD^3"), Err(Error::UnknownRevision(_))));
+        assert!(matches!(
+            resolve("HEAD:missing"),
+            Err(Error::UnknownRevision(_))
+        ));
+        assert!(matches!(
+            resolve("HEAD^{tree}^{commit}"),
+            Err(Error::WrongObjectType { .. })
+        ));
+        assert_eq!(
+            resolve_range(&repository, "side..")?,
+            vec![tip(&merge, false), tip(&side, true)]
+        );
+        assert_eq!(
+            resolve_range(&repository, &format!("{second}...side"))?,
+            vec![tip(&side, false), tip(&second, false), tip(&first, true)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_ambiguous_prefixes() -> Result<()> {
+        let mut repository = create_repository("ambiguous")?;
+        // both hashes start with 8324
+        let one = repository.objects_mut().write("blob", b"142")?;
+        let other = repository.objects_mut().write("blob", b"784")?;
+
+        assert!(matches!(
+            resolve_revision(&repository, "8324"),
+            Err(Error::AmbiguousHash { candidates, .. }) if candidates.len() == 2
+        ));
+
+        let abbreviated = abbreviate(repository.objects(), &one, 4)?;
+
+        assert!(abbreviated.len() > 4);
+        assert_eq!(resolve_revision(&repository, &abbreviated)?, one);
+        assert_eq!(
+            abbreviate(repository.objects(), &other, 7)?,
+            other.to_string()[..7]
+        );
+        Ok(())
+    }
+}
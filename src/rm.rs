@@ -0,0 +1,260 @@
+use std::path::Path;
+
+use crate::{
+    add::{hash_file, tracked_under},
+    error::{Context, Error, Result},
+    hash::Hash,
+    index::{canonical_mode, index_mode, mode_from_metadata, Index, StatData},
+    object_store::{MemoryObjectStore, ObjectStore},
+    refs::HEAD,
+    repository::Repository,
+    utils::remove_empty_directories,
+};
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RmOptions {
+    /// Only unstage, leaving the files in the work tree
+    pub cached: bool,
+    /// Allow directories, removing everything staged inside them
+    pub recursive: bool,
+    /// Remove files even when they have changes that were never committed
+    pub force: bool,
+}
+
+/// Unstages what `pathspecs` name and deletes it from the work tree, like `git rm`. Nothing is
+/// removed unless every path can be, returning the removed paths.
+pub fn rm(
+    repository: &Repository,
+    pathspecs: &[impl AsRef<Path>],
+    options: RmOptions,
+) -> Result<Vec<String>> {
+    let mut index = Index::read(repository.git_directory())?.unwrap_or_default();
+    let index_stat = Index::stat(repository.git_directory())?;
+    let mut removed = vec![];
+
+    for pathspec in pathspecs {
+        let path = repository.index_path(pathspec.as_ref())?;
+        let tracked = tracked_under(&index, &path);
+
+        if tracked.is_empty() {
+            return Err(Error::PathspecMismatch(path));
+        }
+
+        if !options.recursive && tracked.iter().any(|tracked| *tracked != path) {
+            return Err(Error::NotRecursive(path));
+        }
+
+        removed.extend(tracked);
+    }
+
+    removed.sort();
+    removed.dedup();
+
+    // like git, the file or HEAD has to have what's staged, so removing it loses nothing
+    if !options.force {
+        let head_tree = match repository.refs().resolve(HEAD)? {
+            Some(head) => Some(repository.objects().read_commit(&head)?.tree),
+            None => None,
+        };
+
+        for path in &removed {
+            let staged = is_staged(repository, &index, head_tree.as_ref(), path)?;
+            let modified = is_modified(repository, &index, index_stat.as_ref(), path)?;
+
+            match (staged, modified) {
+                (true, true) => return Err(Error::StagedAndLocalChanges(path.clone())),
+                _ if options.cached => {}
+                (true, false) => return Err(Error::StagedChanges(path.clone())),
+                (false, true) => return Err(Error::LocalChanges(path.clone())),
+                (false, false) => {}
+            }
+        }
+    }
+
+    for path in &removed {
+        index.remove(path);
+
+        if options.cached {
+            continue;
+        }
+
+        let file = repository.work_tree().join(path);
+
+        match std::fs::remove_file(&file) {
+            Ok(()) => remove_empty_directories(&file, repository.work_tree()),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error).with_context(|| format!("removing {path}")),
+        }
+    }
+
+    index.write(repository.git_directory())?;
+
+    Ok(removed)
+}
+
+/// Whether what's staged at `path` differs from HEAD's tree, or isn't in it.
+fn is_staged(
+    repository: &Repository,
+    index: &Index,
+    head_tree: Option<&Hash>,
+    path: &str,
+) -> Result<bool> {
+    let Some(entry) = index.entry(path) else {
+        return Ok(false);
+    };
+    let head = match head_tree {
+        Some(tree) => repository.objects().read_tree_entry(tree, path)?,
+        None => None,
+    };
+
+    Ok(head.is_none_or(|head| {
+        head.checksum != entry.hash || canonical_mode(index_mode(head.mode())) != entry.mode
+    }))
+}
+
+/// Whether the file at `path` differs from what's staged for it, trusting its stat data only
+/// when `index_stat`, the index file's own, says it isn't racy. Deleted files have nothing left
+/// to lose.
+pub(crate) fn is_modified(
+    repository: &Repository,
+    index: &Index,
+    index_stat: Option<&StatData>,
+    path: &str,
+) -> Result<bool> {
+    let Some(entry) = index.entry(path) else {
+        return Ok(false);
+    };
+    let file = repository.work_tree().join(path);
+    let Ok(metadata) = std::fs::symlink_metadata(&file) else {
+        return Ok(false);
+    };
+
+    if entry.stat.matches(&metadata) && !entry.stat.is_racy(index_stat) {
+        return Ok(false);
+    }
+
+    let hash = hash_file(&file, &metadata, &mut MemoryObjectStore::new())?;
+
+    Ok(hash != entry.hash || mode_from_metadata(&metadata) != entry.mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        add::add, commit::commit, config::ConfigFile, init::init, test_utils::temp_directory,
+    };
+
+    fn create_repository(name: &str) -> Result<Repository> {
+        let directory = temp_directory(&format!("rm-{name}"));
+
+        std::fs::create_dir_all(directory.join("src/nested"))?;
+        std::fs::write(directory.join("README"), "hello\n")?;
+        std::fs::write(directory.join("src/nested/main.rs"), "fn main() {}\n")?;
+
+        let git_directory = init(directory.clone())?;
+        let config = ConfigFile::new(git_directory.join("config"));
+
+        config.set("user.name", "A U Thor")?;
+        config.set("user.email", "author@example.com")?;
+
+        let mut repository = Repository::open(git_directory, directory.clone())?;
+
+        add(&mut repository, &[&directory], false)?;
+        commit(&mut repository, "first")?;
+
+        Ok(repository)
+    }
+
+    fn staged(repository: &Repository) -> Result<Vec<String>> {
+        let index = Index::read(repository.git_directory())?.unwrap_or_default();
+
+        Ok(index
+            .entries()
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect())
+    }
+
+    #[test]
+    fn should_remove_from_index_and_work_tree() -> Result<()> {
+        let repository = create_repository("remove")?;
+        let work_tree = repository.work_tree().to_path_buf();
+
+        assert!(matches!(
+            rm(&repository, &[work_tree.join("src")], RmOptions::default()),
+            Err(Error::NotRecursive(_))
+        ));
+
+        let options = RmOptions {
+            recursive: true,
+            ..RmOptions::default()
+        };
+        let removed = rm(&repository, &[work_tree.join("src")], options)?;
+
+        assert_eq!(removed, vec!["src/nested/main.rs"]);
+        assert_eq!(staged(&repository)?, vec!["README"]);
+        assert!(!work_tree.join("src").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn should_keep_local_changes() -> Result<()> {
+        let repository = create_repository("changes")?;
+        let readme = repository.work_tree().join("README");
+
+        std::fs::write(&readme, "changed\n")?;
+
+        assert!(matches!(
+            rm(&repository, &[&readme], RmOptions::default()),
+            Err(Error::LocalChanges(_))
+        ));
+
+        let options = RmOptions {
+            cached: true,
+            ..RmOptions::default()
+        };
+
+        rm(&repository, &[&readme], options)?;
+
+        assert_eq!(staged(&repository)?, vec!["src/nested/main.rs"]);
+        assert_eq!(std::fs::read_to_string(&readme)?, "changed\n");
+        Ok(())
+    }
+
+    #[test]
+    fn should_keep_changes_that_were_only_staged() -> Result<()> {
+        let mut repository = create_repository("staged")?;
+        let new = repository.work_tree().join("NEW");
+
+        std::fs::write(&new, "new\n")?;
+        add(&mut repository, &[&new], false)?;
+
+        assert!(matches!(
+            rm(&repository, &[&new], RmOptions::default()),
+            Err(Error::StagedChanges(_))
+        ));
+
+        std::fs::write(&new, "changed\n")?;
+
+        let options = RmOptions {
+            cached: true,
+            ..RmOptions::default()
+        };
+
+        assert!(matches!(
+            rm(&repository, &[&new], options),
+            Err(Error::StagedAndLocalChanges(_))
+        ));
+
+        let options = RmOptions {
+            force: true,
+            ..RmOptions::default()
+        };
+
+        rm(&repository, &[&new], options)?;
+
+        assert!(!new.exists());
+        Ok(())
+    }
+}
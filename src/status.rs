@@ -0,0 +1,713 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    io::Write,
+};
+
+use crate::{
+    add::hash_file,
+    error::{Error, Result},
+    hash::Hash,
+    index::{mode_from_metadata, Index, IndexEntry, StatData, GITLINK_MODE},
+    object_store::{MemoryObjectStore, ObjectStore},
+    refs::{RefTarget, HEAD},
+    repository::Repository,
+};
+
+/// Everything but the permission bits, telling files, symlinks and submodules apart
+const FILE_TYPE_MASK: u32 = 0o170000;
+
+/// How a path differs between two of HEAD, the index and the work tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+    /// A file became a symlink or the other way around
+    TypeChanged,
+}
+
+/// Which untracked files to report, like `--untracked-files`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UntrackedFiles {
+    No,
+    /// Directories with nothing tracked in them are shown as the directory
+    #[default]
+    Normal,
+    All,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StatusFormat {
+    /// For people, grouped under headings
+    #[default]
+    Long,
+    /// `XY path` lines
+    Short,
+    /// Like short, but stable for scripts
+    Porcelain,
+    /// Every mode and hash involved, for scripts, see `git status --porcelain=v2`
+    PorcelainV2,
+}
+
+/// A tracked path that differs somewhere
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusEntry {
+    pub path: String,
+    /// Between HEAD and the index
+    pub staged: Option<Change>,
+    /// Between the index and the work tree
+    pub unstaged: Option<Change>,
+    /// Mode and hash in HEAD's tree
+    pub head: Option<(u32, Hash)>,
+    /// Mode and hash in the index
+    pub index: Option<(u32, Hash)>,
+    /// Mode of the file in the work tree, if there is one
+    pub work_tree_mode: Option<u32>,
+}
+
+/// A conflicted path, with the mode and hash of each stage that has it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnmergedEntry {
+    pub path: String,
+    /// Base, ours and theirs
+    pub stages: [Option<(u32, Hash)>; 3],
+    pub work_tree_mode: Option<u32>,
+}
+
+/// What `status` found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    /// The branch HEAD is on, `None` when it's detached
+    pub branch: Option<String>,
+    pub head: Option<Hash>,
+    pub entries: Vec<StatusEntry>,
+    pub unmerged: Vec<UnmergedEntry>,
+    /// Directories end with `/`
+    pub untracked: Vec<String>,
+}
+
+/// Compares HEAD's tree with the index, and the index with the work tree, like `git status`.
+/// Files whose stat data still matches the index aren't read again, unless they changed too
+/// soon after the index was written to tell. Without an index, HEAD's tree stands in for it.
+pub fn status(repository: &Repository, untracked: UntrackedFiles) -> Result<Status> {
+    let objects = repository.objects();
+    let git_directory = repository.git_directory();
+    let branch = match repository.refs().read(HEAD)? {
+        Some(RefTarget::Symbolic(name)) => Some(name),
+        _ => None,
+    };
+    let head = repository.refs().resolve(HEAD)?;
+    let head_tree = match &head {
+        Some(head) => Index::from_tree(objects, &objects.read_commit(head)?.tree)?,
+        None => Index::default(),
+    };
+    let (mut index, index_stat) = match Index::read(git_directory)? {
+        Some(index) => {
+            let metadata = std::fs::metadata(Index::path(git_directory))?;
+
+            (index, Some(StatData::from_metadata(&metadata)))
+        }
+        None => (head_tree.clone(), None),
+    };
+    let head_entries = head_tree
+        .entries()
+        .iter()
+        .map(|entry| (entry.path.as_str(), (entry.mode, entry.hash.clone())))
+        .collect::<BTreeMap<_, _>>();
+    let mut entries = BTreeMap::<String, StatusEntry>::new();
+    let mut unmerged = BTreeMap::<String, UnmergedEntry>::new();
+    let mut refreshed = vec![];
+
+    for entry in index.entries() {
+        if entry.stage != 0 {
+            let unmerged = unmerged
+                .entry(entry.path.clone())
+                .or_insert_with(|| UnmergedEntry {
+                    path: entry.path.clone(),
+                    stages: [None, None, None],
+                    work_tree_mode: work_tree_mode(repository, &entry.path),
+                });
+
+            unmerged.stages[entry.stage as usize - 1] = Some((entry.mode, entry.hash.clone()));
+            continue;
+        }
+
+        let head = head_entries.get(entry.path.as_str()).cloned();
+        let staged = match &head {
+            _ if entry.intent_to_add => None,
+            None => Some(Change::Added),
+            Some(head) => compare(head.0, &head.1, entry.mode, &entry.hash),
+        };
+        let (unstaged, work_tree_mode) = if entry.intent_to_add {
+            (Some(Change::Added), work_tree_mode(repository, &entry.path))
+        } else {
+            let (unstaged, work_tree_mode, stat) =
+                compare_work_tree(repository, entry, index_stat.as_ref())?;
+
+            refreshed.extend(stat.map(|stat| (entry.path.clone(), stat)));
+
+            (unstaged, work_tree_mode)
+        };
+
+        if staged.is_some() || unstaged.is_some() {
+            entries.insert(
+                entry.path.clone(),
+                StatusEntry {
+                    path: entry.path.clone(),
+                    staged,
+                    unstaged,
+                    head,
+                    index: Some((entry.mode, entry.hash.clone())),
+                    work_tree_mode,
+                },
+            );
+        }
+    }
+
+    let staged_paths = index
+        .entries()
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .collect::<BTreeSet<_>>();
+
+    for (path, head) in &head_entries {
+        if !staged_paths.contains(path) {
+            entries.insert(
+                path.to_string(),
+                StatusEntry {
+                    path: path.to_string(),
+                    staged: Some(Change::Deleted),
+                    unstaged: None,
+                    head: Some(head.clone()),
+                    index: None,
+                    work_tree_mode: None,
+                },
+            );
+        }
+    }
+
+    let untracked = match untracked {
+        UntrackedFiles::No => vec![],
+        untracked => find_untracked(repository, &index, untracked)?,
+    };
+
+    // like git, files that turned out unchanged are refreshed in the index so they don't
+    // need hashing next time, if nobody else is writing it
+    if index_stat.is_some() && !refreshed.is_empty() {
+        for (path, stat) in refreshed {
+            if let Some(entry) = index.entry(&path) {
+                let mut entry = entry.clone();
+
+                entry.stat = stat;
+                index.add(entry);
+            }
+        }
+
+        match index.write(git_directory) {
+            Ok(()) | Err(Error::Locked(_)) => {}
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(Status {
+        branch,
+        head,
+        entries: entries.into_values().collect(),
+        unmerged: unmerged.into_values().collect(),
+        untracked,
+    })
+}
+
+fn compare(old_mode: u32, old_hash: &Hash, new_mode: u32, new_hash: &Hash) -> Option<Change> {
+    if old_mode & FILE_TYPE_MASK != new_mode & FILE_TYPE_MASK {
+        Some(Change::TypeChanged)
+    } else if old_mode != new_mode || old_hash != new_hash {
+        Some(Change::Modified)
+    } else {
+        None
+    }
+}
+
+fn work_tree_mode(repository: &Repository, path: &str) -> Option<u32> {
+    std::fs::symlink_metadata(repository.work_tree().join(path))
+        .ok()
+        .map(|metadata| mode_from_metadata(&metadata))
+}
+
+/// How the work tree file differs from `entry`, its mode, and new stat data for the index
+/// when it turned out to have the same content.
+fn compare_work_tree(
+    repository: &Repository,
+    entry: &IndexEntry,
+    index_stat: Option<&StatData>,
+) -> Result<(Option<Change>, Option<u32>, Option<StatData>)> {
+    if entry.assume_valid || entry.skip_worktree || entry.mode == GITLINK_MODE {
+        return Ok((None, Some(entry.mode), None));
+    }
+
+    let file = repository.work_tree().join(&entry.path);
+    let Ok(metadata) = std::fs::symlink_metadata(&file) else {
+        return Ok((Some(Change::Deleted), None, None));
+    };
+
+    if metadata.is_dir() {
+        return Ok((Some(Change::Deleted), None, None));
+    }
+
+    let mode = mode_from_metadata(&metadata);
+
+    if mode & FILE_TYPE_MASK != entry.mode & FILE_TYPE_MASK {
+        return Ok((Some(Change::TypeChanged), Some(mode), None));
+    }
+
+    if mode != entry.mode {
+        return Ok((Some(Change::Modified), Some(mode), None));
+    }
+
+    if entry.stat.matches(&metadata) && !entry.stat.is_racy(index_stat) {
+        return Ok((None, Some(mode), None));
+    }
+
+    let hash = hash_file(&file, &metadata, &mut MemoryObjectStore::new())?;
+
+    if hash == entry.hash {
+        Ok((None, Some(mode), Some(StatData::from_metadata(&metadata))))
+    } else {
+        Ok((Some(Change::Modified), Some(mode), None))
+    }
+}
+
+fn find_untracked(
+    repository: &Repository,
+    index: &Index,
+    untracked: UntrackedFiles,
+) -> Result<Vec<String>> {
+    let tracked = index
+        .entries()
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .collect::<BTreeSet<_>>();
+    let mut found = BTreeSet::new();
+
+    for entry in repository.walk(repository.work_tree()) {
+        let entry = entry?;
+
+        if entry
+            .file_type()
+            .is_some_and(|file_type| file_type.is_dir())
+        {
+            continue;
+        }
+
+        let path = repository.index_path(entry.path())?;
+
+        if tracked.contains(path.as_str()) {
+            continue;
+        }
+
+        // the outermost directory nothing is tracked in stands for everything inside it
+        let directory = path
+            .match_indices('/')
+            .map(|(position, _)| &path[..=position])
+            .find(|directory| {
+                untracked == UntrackedFiles::Normal
+                    && !tracked
+                        .range(*directory..)
+                        .next()
+                        .is_some_and(|tracked| tracked.starts_with(directory))
+            });
+
+        found.insert(directory.map_or_else(|| path.clone(), str::to_owned));
+    }
+
+    Ok(found.into_iter().collect())
+}
+
+impl Change {
+    fn letter(change: Option<Self>) -> char {
+        match change {
+            None => '.',
+            Some(Self::Added) => 'A',
+            Some(Self::Modified) => 'M',
+            Some(Self::Deleted) => 'D',
+            Some(Self::TypeChanged) => 'T',
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Self::Added => "new file",
+            Self::Modified => "modified",
+            Self::Deleted => "deleted",
+            Self::TypeChanged => "typechange",
+        }
+    }
+}
+
+impl UnmergedEntry {
+    /// `UU`, `AA` and friends, from which sides have the path
+    fn code(&self) -> &'static str {
+        match self.stages.each_ref().map(Option::is_some) {
+            [true, false, false] => "DD",
+            [false, true, false] => "AU",
+            [true, true, false] => "UD",
+            [false, false, true] => "UA",
+            [true, false, true] => "DU",
+            [false, true, true] => "AA",
+            _ => "UU",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self.code() {
+            "DD" => "both deleted",
+            "AU" => "added by us",
+            "UD" => "deleted by them",
+            "UA" => "added by them",
+            "DU" => "deleted by us",
+            "AA" => "both added",
+            _ => "both modified",
+        }
+    }
+}
+
+impl StatusEntry {
+    fn code(&self) -> String {
+        let letter = |change| match Change::letter(change) {
+            '.' => ' ',
+            letter => letter,
+        };
+
+        format!("{}{}", letter(self.staged), letter(self.unstaged))
+    }
+}
+
+impl Status {
+    pub fn is_clean(&self) -> bool {
+        self.entries.is_empty() && self.unmerged.is_empty() && self.untracked.is_empty()
+    }
+
+    /// Writes the status in `format`, with a branch header for the script formats when
+    /// `show_branch` is set. The long format always has one.
+    pub fn write(
+        &self,
+        format: StatusFormat,
+        show_branch: bool,
+        output: &mut impl Write,
+    ) -> Result<()> {
+        match format {
+            StatusFormat::Long => self.write_long(output),
+            StatusFormat::Short | StatusFormat::Porcelain => self.write_short(show_branch, output),
+            StatusFormat::PorcelainV2 => self.write_porcelain_v2(show_branch, output),
+        }
+    }
+
+    fn branch_name(&self) -> Option<&str> {
+        self.branch
+            .as_deref()
+            .map(|branch| branch.strip_prefix("refs/heads/").unwrap_or(branch))
+    }
+
+    fn write_long(&self, output: &mut impl Write) -> Result<()> {
+        match self.branch_name() {
+            Some(branch) => writeln!(output, "On branch {branch}")?,
+            None => writeln!(output, "HEAD detached")?,
+        }
+
+        if self.head.is_none() {
+            writeln!(output, "\nNo commits yet")?;
+        }
+
+        let staged = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                entry
+                    .staged
+                    .map(|change| (change.description(), &entry.path))
+            })
+            .collect::<Vec<_>>();
+        let unstaged = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                entry
+                    .unstaged
+                    .map(|change| (change.description(), &entry.path))
+            })
+            .collect::<Vec<_>>();
+        let unmerged = self
+            .unmerged
+            .iter()
+            .map(|entry| (entry.description(), &entry.path))
+            .collect::<Vec<_>>();
+
+        write_section(output, "Unmerged paths", &unmerged)?;
+        write_section(output, "Changes to be committed", &staged)?;
+        write_section(output, "Changes not staged for commit", &unstaged)?;
+
+        if !self.untracked.is_empty() {
+            writeln!(output, "\nUntracked files:")?;
+
+            for path in &self.untracked {
+                writeln!(output, "\t{path}")?;
+            }
+        }
+
+        if self.is_clean() {
+            writeln!(output, "\nnothing to commit, working tree clean")?;
+        } else if staged.is_empty() && unmerged.is_empty() {
+            writeln!(output, "\nno changes added to commit")?;
+        }
+
+        Ok(())
+    }
+
+    fn write_short(&self, show_branch: bool, output: &mut impl Write) -> Result<()> {
+        if show_branch {
+            match (self.branch_name(), &self.head) {
+                (Some(branch), None) => writeln!(output, "## No commits yet on {branch}")?,
+                (Some(branch), Some(_)) => writeln!(output, "## {branch}")?,
+                (None, _) => writeln!(output, "## HEAD (no branch)")?,
+            }
+        }
+
+        let mut lines = self
+            .entries
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.code()))
+            .chain(
+                self.unmerged
+                    .iter()
+                    .map(|entry| (entry.path.as_str(), entry.code().to_owned())),
+            )
+            .collect::<Vec<_>>();
+
+        lines.sort();
+
+        for (path, code) in lines {
+            writeln!(output, "{code} {path}")?;
+        }
+
+        for path in &self.untracked {
+            writeln!(output, "?? {path}")?;
+        }
+
+        Ok(())
+    }
+
+    fn write_porcelain_v2(&self, show_branch: bool, output: &mut impl Write) -> Result<()> {
+        if show_branch {
+            match &self.head {
+                Some(head) => writeln!(output, "# branch.oid {head}")?,
+                None => writeln!(output, "# branch.oid (initial)")?,
+            }
+
+            writeln!(
+                output,
+                "# branch.head {}",
+                self.branch_name().unwrap_or("(detached)")
+            )?;
+        }
+
+        let mut lines = vec![];
+
+        for entry in &self.entries {
+            let (head_mode, head_hash) = mode_and_hash(&entry.head);
+            let (index_mode, index_hash) = mode_and_hash(&entry.index);
+
+            lines.push((
+                &entry.path,
+                format!(
+                    "1 {}{} N... {head_mode} {index_mode} {} {head_hash} {index_hash} {}",
+                    Change::letter(entry.staged),
+                    Change::letter(entry.unstaged),
+                    Mode(entry.work_tree_mode),
+                    entry.path
+                ),
+            ));
+        }
+
+        for entry in &self.unmerged {
+            let [(base_mode, base_hash), (ours_mode, ours_hash), (theirs_mode, theirs_hash)] =
+                entry.stages.each_ref().map(mode_and_hash);
+
+            lines.push((
+                &entry.path,
+                format!(
+                    "u {} N... {base_mode} {ours_mode} {theirs_mode} {} {base_hash} {ours_hash} \
+                     {theirs_hash} {}",
+                    entry.code(),
+                    Mode(entry.work_tree_mode),
+                    entry.path
+                ),
+            ));
+        }
+
+        lines.sort();
+
+        for (_, line) in lines {
+            writeln!(output, "{line}")?;
+        }
+
+        for path in &self.untracked {
+            writeln!(output, "? {path}")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_section(
+    output: &mut impl Write,
+    heading: &str,
+    changes: &[(&str, &String)],
+) -> Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(output, "\n{heading}:")?;
+
+    for (description, path) in changes {
+        writeln!(output, "\t{:<12}{path}", format!("{description}:"))?;
+    }
+
+    Ok(())
+}
+
+/// Six octal digits, all zero for a side that doesn't have the path
+struct Mode(Option<u32>);
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:06o}", self.0.unwrap_or_default())
+    }
+}
+
+fn mode_and_hash(side: &Option<(u32, Hash)>) -> (Mode, Hash) {
+    match side {
+        Some((mode, hash)) => (Mode(Some(*mode)), hash.clone()),
+        None => (Mode(None), Hash::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        add::add, commit::commit, config::ConfigFile, error::Context, init::init,
+        test_utils::temp_directory,
+    };
+
+    fn create_repository(name: &str) -> Result<Repository> {
+        let directory = temp_directory(&format!("status-{name}"));
+
+        std::fs::create_dir_all(directory.join("src"))?;
+        std::fs::write(directory.join("README"), "hello\n")?;
+        std::fs::write(directory.join("src/main.rs"), "fn main() {}\n")?;
+
+        let git_directory = init(directory.clone())?;
+        let config = ConfigFile::new(git_directory.join("config"));
+
+        config.set("user.name", "A U Thor")?;
+        config.set("user.email", "author@example.com")?;
+
+        let mut repository = Repository::open(git_directory, directory.clone())?;
+
+        add(&mut repository, &[&directory], false)?;
+        commit(&mut repository, "first")?;
+
+        Ok(repository)
+    }
+
+    fn short(repository: &Repository, format: StatusFormat) -> Result<String> {
+        let mut output = vec![];
+
+        status(repository, UntrackedFiles::Normal)?.write(format, false, &mut output)?;
+
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn should_report_staged_unstaged_and_untracked_files() -> Result<()> {
+        let mut repository = create_repository("changes")?;
+        let work_tree = repository.work_tree().to_path_buf();
+
+        assert!(status(&repository, UntrackedFiles::Normal)?.is_clean());
+
+        std::fs::write(work_tree.join("README"), "changed\n")?;
+        std::fs::write(work_tree.join("new.txt"), "")?;
+        std::fs::create_dir_all(work_tree.join("docs/guide"))?;
+        std::fs::write(work_tree.join("docs/guide/intro.md"), "")?;
+        std::fs::write(work_tree.join("src/lib.rs"), "")?;
+        add(&mut repository, &[work_tree.join("new.txt")], false)?;
+        std::fs::write(work_tree.join("new.txt"), "more\n")?;
+        std::fs::remove_file(work_tree.join("src/main.rs"))?;
+
+        assert_eq!(
+            short(&repository, StatusFormat::Short)?,
+            " M README\nAM new.txt\n D src/main.rs\n?? docs/\n?? src/lib.rs\n"
+        );
+
+        let status = status(&repository, UntrackedFiles::All)?;
+
+        assert_eq!(status.untracked, vec!["docs/guide/intro.md", "src/lib.rs"]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_write_porcelain_v2() -> Result<()> {
+        let mut repository = create_repository("porcelain")?;
+        let work_tree = repository.work_tree().to_path_buf();
+        let index = Index::read(repository.git_directory())?.unwrap_or_default();
+        let readme = index.entry("README").map(|entry| entry.hash.clone());
+
+        std::fs::remove_file(work_tree.join("README"))?;
+        add(&mut repository, &[work_tree.join("README")], false)?;
+
+        let mut output = vec![];
+
+        status(&repository, UntrackedFiles::No)?.write(
+            StatusFormat::PorcelainV2,
+            true,
+            &mut output,
+        )?;
+
+        let head = repository.refs().resolve(HEAD)?.unwrap_or_default();
+
+        assert_eq!(
+            String::from_utf8(output)?,
+            format!(
+                "# branch.oid {head}\n# branch.head master\n\
+                 1 D. N... 100644 000000 000000 {} {} README\n",
+                readme.unwrap_or_default(),
+                Hash::default()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_rehash_files_changed_too_soon_to_tell() -> Result<()> {
+        let repository = create_repository("racy")?;
+        let file = repository.work_tree().join("README");
+        let mut index = Index::read(repository.git_directory())?.unwrap_or_default();
+        let mut entry = index.entry("README").cloned().context("README is staged")?;
+
+        // the index records the changed file's stat data, but the file is no older than the
+        // index so that can't be trusted
+        std::fs::write(&file, "HELLO\n")?;
+        std::fs::File::options()
+            .write(true)
+            .open(&file)?
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))?;
+        entry.stat = StatData::from_metadata(&std::fs::metadata(&file)?);
+        index.add(entry);
+        index.write(repository.git_directory())?;
+
+        assert_eq!(short(&repository, StatusFormat::Porcelain)?, " M README\n");
+        Ok(())
+    }
+}
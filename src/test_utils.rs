@@ -0,0 +1,36 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Once,
+    },
+};
+
+use crate::config::{GLOBAL_CONFIG_ENVIRONMENT, SYSTEM_CONFIG_ENVIRONMENT};
+
+static NEXT_DIRECTORY: AtomicUsize = AtomicUsize::new(0);
+static ISOLATE_CONFIG: Once = Once::new();
+
+/// A path for one test to build its repository in, which doesn't exist yet. The process id and
+/// a counter keep it apart from other tests and other runs, even ones with the same `name`.
+///
+/// The first call also points `HOME` and the global and system config files at an empty
+/// directory, so `Config::load` never reads the settings of whoever runs the tests.
+pub fn temp_directory(name: &str) -> PathBuf {
+    let process = std::process::id();
+
+    ISOLATE_CONFIG.call_once(|| {
+        let home = std::env::temp_dir().join(format!("vc-home-{process}"));
+        let _ = std::fs::remove_dir_all(&home);
+
+        std::env::set_var("HOME", &home);
+        std::env::set_var(GLOBAL_CONFIG_ENVIRONMENT, home.join(".vcconfig"));
+        std::env::set_var(SYSTEM_CONFIG_ENVIRONMENT, home.join("vcconfig"));
+    });
+
+    let number = NEXT_DIRECTORY.fetch_add(1, Ordering::Relaxed);
+    let directory = std::env::temp_dir().join(format!("vc-{name}-{process}-{number}"));
+    let _ = std::fs::remove_dir_all(&directory);
+
+    directory
+}
@@ -1,68 +1,109 @@
+use crate::error::{Context, Error, Result};
 use crate::hash::Hash;
-use anyhow::{bail, Context, Result};
-use std::fmt::Display;
+use std::{borrow::Cow, fmt::Display};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Tree {
     pub tree_objects: Vec<TreeObject>,
 }
 
 impl Tree {
-    pub fn filenames(&self) -> Vec<&str> {
-        self.tree_objects
-            .iter()
-            .map(|tree_object| tree_object.filename.as_str())
-            .collect()
+    pub fn new(mut tree_objects: Vec<TreeObject>) -> Self {
+        // git compares directories as if their names ended with a slash
+        tree_objects.sort_by_key(|tree_object| tree_object.sort_name());
+
+        Self { tree_objects }
     }
-}
 
-impl From<&[u8]> for Tree {
-    fn from(value: &[u8]) -> Self {
+    pub fn parse(value: &[u8]) -> Result<Self> {
         let mut tree_objects = vec![];
-        let value_iter = value.iter();
-        let mut values = value_iter.copied().peekable();
-
-        loop {
-            if let None = values.peek() {
-                break;
-            };
+        let mut values = value.iter().copied().peekable();
 
+        while values.peek().is_some() {
             let mut tree_object = TreeObject::default();
 
             tree_object
                 .extract_mode(&mut values)
-                .expect("Error extracting mode while creating tree");
-
-            tree_object.set_object_type();
-
+                .context("extracting mode while creating tree")?;
+            tree_object.set_object_type()?;
             tree_object
                 .extract_filename(&mut values)
-                .expect("Error extracting filename while creating tree");
-
-            // tree_object
-            //     .parse_mode_and_filename(lines.next())
-            //     .expect("error parseing mode and filename");
-
+                .context("extracting filename while creating tree")?;
             tree_object
                 .parse_hash(&mut values)
-                .expect("error parseing hash");
+                .context("parsing hash while creating tree")?;
 
             tree_objects.push(tree_object);
         }
 
-        Self { tree_objects }
+        Ok(Self { tree_objects })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+
+        for tree_object in &self.tree_objects {
+            match &tree_object.raw_mode {
+                Some(raw_mode) => bytes.extend(raw_mode),
+                None => bytes.extend(tree_object.mode.to_string().as_bytes()),
+            }
+
+            bytes.push(b' ');
+            bytes.extend(&tree_object.filename);
+            bytes.push(b'\0');
+            bytes.extend(&tree_object.checksum);
+        }
+
+        bytes
+    }
+
+    pub fn filenames(&self) -> Vec<Cow<'_, str>> {
+        self.tree_objects.iter().map(TreeObject::name).collect()
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct TreeObject {
     mode: u32,
+    /// The digits the mode was parsed from, when they had leading zeros like `040000`
+    raw_mode: Option<Vec<u8>>,
     pub object_type: TreeObjectType,
-    pub filename: String,
+    /// Git allows any bytes but `/` and NUL, so names aren't always UTF-8
+    pub filename: Vec<u8>,
     pub checksum: Hash,
 }
 
 impl TreeObject {
+    /// `mode` is written the way trees store it, e.g. 100644 or 40000
+    pub fn new(mode: u32, filename: impl Into<Vec<u8>>, checksum: Hash) -> Result<Self> {
+        Ok(Self {
+            mode,
+            raw_mode: None,
+            object_type: TreeObjectType::try_from(mode)?,
+            filename: filename.into(),
+            checksum,
+        })
+    }
+
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    /// The filename, with anything that isn't UTF-8 replaced
+    pub fn name(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.filename)
+    }
+
+    fn sort_name(&self) -> Vec<u8> {
+        let mut name = self.filename.clone();
+
+        if let TreeObjectType::Tree = self.object_type {
+            name.push(b'/');
+        }
+
+        name
+    }
+
     pub fn extract_mode(&mut self, bytes: &mut impl Iterator<Item = u8>) -> Result<()> {
         let mut mode_bytes = Vec::new();
 
@@ -74,43 +115,23 @@ impl TreeObject {
             mode_bytes.push(byte);
         }
 
-        self.mode = String::from_utf8(mode_bytes)?.parse()?;
+        self.mode = String::from_utf8(mode_bytes.clone())?.parse()?;
 
-        Ok(())
-    }
+        if self.mode.to_string().as_bytes() != mode_bytes {
+            self.raw_mode = Some(mode_bytes);
+        }
 
-    pub fn set_object_type(&mut self) {
-        self.object_type = TreeObjectType::from(self.mode);
+        Ok(())
     }
 
-    pub fn extract_filename(&mut self, bytes: &mut impl Iterator<Item = u8>) -> Result<()> {
-        let mut filename_bytes = vec![];
-
-        for byte in bytes.take_while(|&byte| byte != b'\0') {
-            filename_bytes.push(byte);
-        }
-
-        self.filename = String::from_utf8(filename_bytes).context("extracting filename")?;
+    pub fn set_object_type(&mut self) -> Result<()> {
+        self.object_type = TreeObjectType::try_from(self.mode)?;
 
         Ok(())
     }
 
-    pub fn parse_mode_and_filename(&mut self, bytes: Option<&[u8]>) -> Result<()> {
-        let Some(bytes) = bytes else {
-            bail!("missing bytes")
-        };
-        let mut split_bytes = bytes.split(|&byte| byte == b' ');
-        let mode_as_bytes = split_bytes
-            .next()
-            .expect("missing mode when parseing mode and filename");
-        let mode = std::str::from_utf8(mode_as_bytes)?.parse()?;
-        let filename_as_bytes = split_bytes
-            .next()
-            .expect("missing filename when parseing mode and filename");
-        let filename = std::str::from_utf8(filename_as_bytes)?.to_owned();
-
-        self.mode = mode;
-        self.filename = filename;
+    pub fn extract_filename(&mut self, bytes: &mut impl Iterator<Item = u8>) -> Result<()> {
+        self.filename = bytes.take_while(|&byte| byte != b'\0').collect();
 
         Ok(())
     }
@@ -118,12 +139,10 @@ impl TreeObject {
     pub fn parse_hash(&mut self, bytes: &mut impl Iterator<Item = u8>) -> Result<()> {
         let mut hash_bytes = [0; 20];
 
-        for index in 0..20 {
-            let byte = bytes
+        for hash_byte in &mut hash_bytes {
+            *hash_byte = bytes
                 .next()
                 .context("missing byte when extracting the hash")?;
-
-            hash_bytes[index] = byte;
         }
 
         let hash = Hash::new(hash_bytes);
@@ -138,25 +157,99 @@ impl Display for TreeObject {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{} {:?} {}\t{}",
-            self.mode, self.object_type, self.checksum, &self.filename
+            "{:06} {} {}\t{}",
+            self.mode,
+            self.object_type,
+            self.checksum,
+            self.name()
         )
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub enum TreeObjectType {
     #[default]
     Blob,
     Tree,
+    /// A submodule, pointing at a commit in another repository
+    Commit,
 }
 
-impl From<u32> for TreeObjectType {
-    fn from(value: u32) -> Self {
-        match value {
-            100644 | 644 | 755 | 100755 => Self::Blob,
-            40000 => Self::Tree,
-            _ => unreachable!("attempting to extract tree object type, but not one of the types"),
+impl TryFrom<u32> for TreeObjectType {
+    type Error = Error;
+
+    /// `value` is written the way trees store it. Like git, any file mode is a blob, including
+    /// legacy ones like 100664 or 644.
+    fn try_from(value: u32) -> Result<Self> {
+        let unknown = || Error::corrupt(format!("unknown tree entry mode {value}"));
+        let mode = u32::from_str_radix(&value.to_string(), 8).map_err(|_error| unknown())?;
+
+        match mode & 0o170000 {
+            0o040000 => Ok(Self::Tree),
+            0o160000 => Ok(Self::Commit),
+            // symlinks are blobs holding the link target
+            0o100000 | 0o120000 => Ok(Self::Blob),
+            // written by very old versions of git
+            0 if mode == 0o644 || mode == 0o755 => Ok(Self::Blob),
+            _ => Err(unknown()),
         }
     }
 }
+
+impl Display for TreeObjectType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Blob => "blob",
+            Self::Tree => "tree",
+            Self::Commit => "commit",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_sort_directories_as_if_they_end_in_a_slash() -> Result<()> {
+        let tree = Tree::new(vec![
+            TreeObject::new(40000, "a".to_owned(), Hash::new([1; 20]))?,
+            TreeObject::new(100644, "a.txt".to_owned(), Hash::new([2; 20]))?,
+            TreeObject::new(100644, "B".to_owned(), Hash::new([3; 20]))?,
+        ]);
+
+        assert_eq!(tree.filenames(), vec!["B", "a.txt", "a"]);
+        assert_eq!(Tree::parse(&tree.serialize())?, tree);
+        assert_eq!(
+            tree.tree_objects[2].to_string(),
+            format!("040000 tree {}\ta", Hash::new([1; 20]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_keep_legacy_modes_and_names_that_arent_utf8() -> Result<()> {
+        let mut data = b"100664 \xfffile\0".to_vec();
+
+        data.extend([1; 20]);
+        data.extend(b"040000 dir\0");
+        data.extend([2; 20]);
+
+        let tree = Tree::parse(&data)?;
+
+        assert_eq!(tree.tree_objects[0].object_type, TreeObjectType::Blob);
+        assert_eq!(tree.tree_objects[1].object_type, TreeObjectType::Tree);
+        assert_eq!(tree.filenames(), vec!["\u{fffd}file", "dir"]);
+        assert_eq!(tree.serialize(), data);
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_malformed_trees() {
+        assert!(Tree::parse(b"100644 file\0too short").is_err());
+        assert!(Tree::parse(b"123 file\0aaaaaaaaaaaaaaaaaaaa").is_err());
+        assert!(Tree::parse(b"100644 no hash").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::BufRead;
+
+use crate::{
+    error::{Context, Error, Result},
+    hash::Hash,
+    ref_transaction::{Expected, RefTransaction},
+    refs::Refs,
+};
+
+/// An old value from the command line. Like git, an empty value or the all zero hash means the
+/// ref must not exist, and no value at all means anything goes.
+pub fn parse_expected(value: Option<&str>) -> Result<Expected> {
+    match value {
+        None => Ok(Expected::Anything),
+        Some(value) => Ok(parse_value(value)?.map_or(Expected::Missing, Expected::Hash)),
+    }
+}
+
+/// A new value, where empty or the all zero hash means deleting the ref
+fn parse_value(value: &str) -> Result<Option<Hash>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    let hash = value.parse::<Hash>()?;
+
+    Ok((hash != Hash::default()).then_some(hash))
+}
+
+/// Sets `name` to `new` unless it doesn't hold `expected`, logging `message` in the reflog.
+pub fn update_ref(
+    refs: &Refs,
+    name: &str,
+    new: &str,
+    expected: Expected,
+    message: &str,
+) -> Result<()> {
+    let mut transaction = refs.transaction();
+
+    transaction.message(message);
+
+    match parse_value(new)? {
+        Some(hash) => transaction.update(name, hash, expected),
+        None => transaction.delete(name, expected),
+    };
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Applies the instructions in `input` as one transaction, one per line:
+///
+/// ```text
+/// update <ref> <new> [<old>]
+/// create <ref> <new>
+/// delete <ref> [<old>]
+/// verify <ref> [<old>]
+/// ```
+pub fn update_refs_from(refs: &Refs, input: impl BufRead, message: &str) -> Result<()> {
+    let mut transaction = refs.transaction();
+
+    transaction.message(message);
+
+    for line in input.lines() {
+        let line = line.context("reading instructions")?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        add_instruction(&mut transaction, &line)
+            .with_context(|| format!("in instruction {line:?}"))?;
+    }
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+fn add_instruction(transaction: &mut RefTransaction, line: &str) -> Result<()> {
+    let words = line.split(' ').collect::<Vec<_>>();
+    let invalid = || Error::InvalidRef(format!("can't parse update-ref instruction {line:?}"));
+
+    match words.as_slice() {
+        ["update", name, new, old @ ..] if old.len() <= 1 => {
+            let expected = parse_expected(old.first().copied())?;
+
+            match parse_value(new)? {
+                Some(hash) => transaction.update(name, hash, expected),
+                None => transaction.delete(name, expected),
+            };
+        }
+        ["create", name, new] => {
+            let hash = parse_value(new)?.ok_or_else(invalid)?;
+
+            transaction.create(name, hash);
+        }
+        ["delete", name, old @ ..] if old.len() <= 1 => {
+            transaction.delete(name, parse_expected(old.first().copied())?);
+        }
+        ["verify", name, old @ ..] if old.len() <= 1 => {
+            // verifying with no old value checks that the ref doesn't exist
+            transaction.verify(name, parse_expected(Some(old.first().unwrap_or(&"")))?);
+        }
+        _ => return Err(invalid()),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::refs::RefTarget;
+    use crate::test_utils::temp_directory;
+
+    #[test]
+    fn should_apply_instructions_as_one_transaction() -> Result<()> {
+        let git_directory = temp_directory("update-ref");
+        let refs = Refs::new(git_directory);
+        let one = Hash::new([1; 20]);
+        let two = Hash::new([2; 20]);
+
+        refs.write("HEAD", &RefTarget::Symbolic("refs/heads/master".to_owned()))?;
+        update_refs_from(
+            &refs,
+            format!(
+                "create refs/heads/master {one}\n\nupdate refs/tags/v1 {two} {}\n",
+                Hash::default()
+            )
+            .as_bytes(),
+            "test",
+        )?;
+
+        assert_eq!(refs.resolve("HEAD")?, Some(one.clone()));
+
+        let result = update_refs_from(
+            &refs,
+            format!("delete refs/tags/v1 {two}\nverify refs/heads/master {two}\n").as_bytes(),
+            "test",
+        );
+
+        assert!(matches!(
+            result.map_err(|error| error.root().to_string()),
+            Err(message) if message.contains("refs/heads/master")
+        ));
+        assert_eq!(refs.resolve("refs/tags/v1")?, Some(two));
+
+        assert!(update_refs_from(&refs, &b"move refs/heads/master\n"[..], "test").is_err());
+        Ok(())
+    }
+}
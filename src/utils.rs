@@ -1,14 +1,14 @@
-use anyhow::{bail, Context, Result};
+use crate::error::{Context, Error, Result};
 use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
 use sha1::{Digest, Sha1};
 use std::{
     io::{Read, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
-use crate::hash::Hash;
+use crate::{config::Config, hash::Hash};
 
 pub fn get_object_directory_name(hash: &str) -> String {
     hash[0..2].to_owned()
@@ -18,15 +18,15 @@ pub fn get_object_file_name(hash: &str) -> String {
     hash[2..].to_owned()
 }
 
-pub fn decompress(bytes: &[u8]) -> Vec<u8> {
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>> {
     let mut decoder = ZlibDecoder::new(bytes);
     let mut result = vec![];
 
     decoder
         .read_to_end(&mut result)
-        .expect("error decompressing");
+        .map_err(|error| Error::corrupt(format!("decompressing: {error}")))?;
 
-    result
+    Ok(result)
 }
 
 pub fn next_chunk(bytes: &[u8], offset_nulls: usize) -> Option<&[u8]> {
@@ -35,9 +35,9 @@ pub fn next_chunk(bytes: &[u8], offset_nulls: usize) -> Option<&[u8]> {
     result.nth(offset_nulls)
 }
 
-pub fn compress(content: &[u8]) -> Result<Vec<u8>> {
+pub fn compress(content: &[u8], compression: Compression) -> Result<Vec<u8>> {
     let mut encoded = Vec::new();
-    let mut encoder = ZlibEncoder::new(&mut encoded, Compression::default());
+    let mut encoder = ZlibEncoder::new(&mut encoded, compression);
 
     encoder
         .write_all(content)
@@ -46,40 +46,38 @@ pub fn compress(content: &[u8]) -> Result<Vec<u8>> {
     Ok(encoder.finish()?.to_owned())
 }
 
-pub fn get_hash(content: &[u8]) -> Result<Hash> {
-    let mut hasher = Sha1::new();
-
-    hasher.update(content);
+/// The zlib level configured by `key`, like `core.looseCompression`, falling back to
+/// `core.compression`. Like git, -1 means zlib's default.
+pub fn compression_level(config: &Config, key: &str) -> Result<Compression> {
+    let level = match config.get_int(key)? {
+        Some(level) => Some(level),
+        None => config.get_int("core.compression")?,
+    };
 
-    Ok(Hash::new(hasher.finalize().into()))
+    match level {
+        None | Some(-1) => Ok(Compression::default()),
+        Some(level @ 0..=9) => Ok(Compression::new(level as u32)),
+        Some(level) => Err(Error::Config(format!(
+            "compression level {level} isn't between -1 and 9"
+        ))),
+    }
 }
 
-pub fn save_to_disk(content: &[u8], mut path: PathBuf) -> Result<Hash> {
-    let hash = get_hash(content)?;
-    let compressed = compress(content)?;
-    let hash_utf8 = hex::encode(&hash);
-    let directory_name = get_object_directory_name(&hash_utf8);
-    let file_name = get_object_file_name(&hash_utf8);
-
-    path = path.join(".vc").join("objects").join(directory_name);
-
-    let Ok(directory_exists) = path.try_exists() else {
-        bail!("Error checking if directory exists");
-    };
+/// Prefixes object content with the `<type> <size>\0` header it is hashed and stored with.
+pub fn add_header(object_type: &str, content: &[u8]) -> Vec<u8> {
+    let mut object = format!("{object_type} {}\0", content.len()).into_bytes();
 
-    if !directory_exists {
-        std::fs::DirBuilder::new().create(&path)?;
-    }
+    object.extend_from_slice(content);
 
-    path = path.join(&file_name);
+    object
+}
 
-    if path.exists() {
-        return Ok(hash);
-    }
+pub fn get_hash(content: &[u8]) -> Result<Hash> {
+    let mut hasher = Sha1::new();
 
-    std::fs::write(path, &compressed)?;
+    hasher.update(content);
 
-    Ok(hash)
+    Ok(Hash::new(hasher.finalize().into()))
 }
 
 pub fn create_directory(path: &PathBuf) -> Result<()> {
@@ -98,6 +96,16 @@ pub fn remove_header(bytes: &[u8]) -> Result<&[u8]> {
     Ok(&bytes[index + 1..])
 }
 
+/// Cleans up the directories `path` was in, like `refs/heads/feature` once its last ref is
+/// gone, stopping at `stop`.
+pub(crate) fn remove_empty_directories(path: &Path, stop: &Path) {
+    for directory in path.ancestors().skip(1) {
+        if directory == stop || std::fs::remove_dir(directory).is_err() {
+            break;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Write;
@@ -132,16 +140,21 @@ mod tests {
         encoder.write_all(de_compressed_string.as_bytes()).unwrap();
 
         let compressed = encoder.finish().unwrap();
-        let de_compressed = decompress(&compressed);
+        let de_compressed = decompress(&compressed).unwrap();
 
         assert_eq!(de_compressed, de_compressed_string.as_bytes());
     }
 
+    #[test]
+    fn should_fail_to_decompress_garbage() {
+        assert!(decompress(b"not zlib").is_err());
+    }
+
     #[test]
     fn should_return_chunk_before_first_null() {
         let string = "eanfphensrtduyfj\0rsiueaptyrafupgdreif\0";
         let expected_value = "eanfphensrtduyfj".as_bytes();
-        let result = next_chunk(&string.as_bytes(), 0).unwrap();
+        let result = next_chunk(string.as_bytes(), 0).unwrap();
 
         assert_eq!(result, expected_value);
     }
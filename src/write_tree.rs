@@ -1,24 +1,40 @@
+use crate::error::{Context, Result};
 use crate::hash::Hash;
-use crate::{hash_object::hash_object, utils::save_to_disk};
-use anyhow::{Context, Result};
-use ignore::WalkBuilder;
-use std::{fmt::Display, path::PathBuf};
+use crate::index::Index;
+use crate::tree::{Tree, TreeObject};
+use crate::{hash_object::hash_object, object_store::ObjectStore, repository::Repository};
+use std::path::Path;
 
 // Unix-specific permissions handling
 #[cfg(unix)]
 use std::os::unix::prelude::PermissionsExt;
 
-pub fn write_tree() -> Result<String> {
-    // files and folders in the current directory
-    let path = PathBuf::new().join(".");
-    let checksum = write_tree_object(&path)?.expect("getting checksum after writing all trees");
+/// Writes what's staged in the index as trees, like git. Repositories that have never staged
+/// anything have no index, so the whole work tree is written instead.
+pub fn write_tree(repository: &mut Repository) -> Result<Hash> {
+    if let Some(index) = Index::read(repository.git_directory())? {
+        return index.write_tree(repository.objects_mut());
+    }
+
+    let path = repository.work_tree().to_path_buf();
+    let git_directory = repository.git_directory().to_path_buf();
+    let objects = repository.objects_mut();
+    let checksum = match write_tree_object(&path, &git_directory, objects)? {
+        Some(checksum) => checksum,
+        // an empty work tree is still a tree
+        None => objects.write("tree", &[])?,
+    };
 
-    Ok(hex::encode(checksum))
+    Ok(checksum)
 }
-fn write_tree_object(path: &PathBuf) -> Result<Option<Hash>> {
+fn write_tree_object(
+    path: &Path,
+    git_directory: &Path,
+    store: &mut impl ObjectStore,
+) -> Result<Option<Hash>> {
     let mut objects = vec![];
-    for object in WalkBuilder::new(&path)
-        .hidden(false)
+    // the same ignore rules as `add` and `status`, so what they leave out isn't committed
+    for object in Repository::walk_builder(path)
         .max_depth(Some(1))
         .build()
         .skip(1)
@@ -46,133 +62,69 @@ fn write_tree_object(path: &PathBuf) -> Result<Option<Hash>> {
         };
 
         let file_object = if metadata.is_file() {
-            let checksum = hash_object(true, file_path.to_path_buf())?;
-            Some(TreeObject::new(true, checksum, name, mode))
+            let checksum = hash_object(true, file_path, store)?;
+            // only the executable bit survives in a tree
+            let mode = if mode & 0o100 == 0o100 {
+                100755
+            } else {
+                100644
+            };
+
+            Some(TreeObject::new(mode, name, checksum)?)
         } else {
-            if name == ".vc" {
+            if file_path == git_directory {
                 continue;
             }
 
-            if let Some(checksum) = write_tree_object(&file_path.to_path_buf())? {
-                Some(TreeObject::new(false, checksum, name, mode))
-            } else {
-                None
-            }
+            write_tree_object(file_path, git_directory, store)?
+                .map(|checksum| TreeObject::new(40000, name, checksum))
+                .transpose()?
         };
 
         objects.extend(file_object);
     }
 
-    objects.sort_unstable_by_key(|object| object.name.clone());
     if objects.is_empty() {
         Ok(None)
     } else {
-        let tree_file = create_tree_file(&objects);
-        let path = PathBuf::new();
-        let hash = save_to_disk(&tree_file, path)?;
+        let hash = store.write("tree", &Tree::new(objects).serialize())?;
 
         Ok(Some(hash))
     }
 }
 
-
-#[derive(Debug)]
-struct TreeObject {
-    mode: String,
-    checksum: Hash,
-    name: String,
-}
-
-impl TreeObject {
-    pub fn new(is_file: bool, checksum: Hash, name: String, mode: u32) -> Self {
-        let object_type = TreeObjectType::new(is_file, mode);
-        let mode = object_type.mode();
-
-        Self {
-            mode,
-            checksum,
-            name,
-        }
-    }
-
-    pub fn as_bytes(&self) -> Vec<u8> {
-        let mut bytes = vec![];
-
-        let mode = format!("{} ", &self.mode);
-        bytes.extend(mode.as_bytes());
-
-        let name = format!("{}\0", &self.name);
-        bytes.extend(name.as_bytes());
-
-        bytes.extend(&self.checksum);
-
-        bytes
-    }
-}
-
-impl Display for TreeObject {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} {}\0{:?}", &self.mode, &self.name, &self.checksum)
-    }
-}
-
-#[derive(Debug)]
-enum TreeObjectType {
-    Blob(&'static str),
-    Tree(&'static str),
-}
-
-impl TreeObjectType {
-    pub fn new(is_file: bool, mode: u32) -> Self {
-        if is_file {
-            let mode = if mode & 0o100 == 0o100 {
-                "100755"
-            } else {
-                "100644"
-            };
-            Self::Blob(mode)
-        } else {
-            Self::Tree("40000")
-        }
-    }
-
-    pub fn mode(&self) -> String {
-        match self {
-            Self::Blob(mode) => mode,
-            Self::Tree(mode) => mode,
-        }
-        .to_string()
-    }
-}
-
-impl Display for TreeObjectType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let name = match self {
-            Self::Blob(_) => "blob",
-            Self::Tree(_) => "tree",
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{init::init, object::Object, test_utils::temp_directory};
+
+    #[test]
+    fn should_leave_out_ignored_files_without_an_index() -> Result<()> {
+        let directory = temp_directory("write-tree");
+
+        std::fs::create_dir_all(directory.join("src"))?;
+        std::fs::write(directory.join(".vcignore"), "secret\n")?;
+        std::fs::write(directory.join("secret"), "hunter2\n")?;
+        std::fs::write(directory.join("src/secret"), "hunter2\n")?;
+        std::fs::write(directory.join("src/main.rs"), "fn main() {}\n")?;
+
+        let git_directory = init(directory.clone())?;
+        let mut repository = Repository::open(git_directory, directory)?;
+        let tree = write_tree(&mut repository)?;
+        let Object::Tree(root) = repository.objects().read_object(&tree)? else {
+            panic!("{tree} isn't a tree");
         };
-
-        write!(f, "{name}")
+        let src = root
+            .tree_objects
+            .iter()
+            .find(|tree_object| tree_object.name() == "src")
+            .context("src is in the tree")?;
+
+        assert_eq!(root.filenames(), [".vcignore", "src"]);
+        assert_eq!(
+            repository.objects().read_tree(&src.checksum)?.filenames(),
+            ["main.rs"]
+        );
+        Ok(())
     }
 }
-
-fn create_tree_file(objects: &[TreeObject]) -> Vec<u8> {
-    let mut tree_file = vec![];
-
-    let objects = objects
-        .iter()
-        .map(|object| object.as_bytes())
-        .collect::<Vec<Vec<u8>>>();
-    let size = objects.iter().fold(0, |acc, object| acc + object.len());
-
-    tree_file.extend(format!("tree {size}\0").as_bytes());
-    for object in objects {
-        tree_file.extend(object)
-    }
-
-    tree_file
-}
-
-// fn write_tree_to_file(content: &str, hash: &str) -> Result<()> {
-
-// }